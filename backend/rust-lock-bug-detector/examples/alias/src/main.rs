@@ -0,0 +1,82 @@
+use std::sync;
+
+struct Foo {
+    mu: sync::Mutex<i32>,
+}
+
+fn lock_second(_first: &Foo, second: &Foo) {
+    *second.mu.lock().unwrap() += 1;
+}
+
+impl Foo {
+    fn new() -> Self {
+        Self {
+            mu: sync::Mutex::new(1),
+        }
+    }
+
+    fn same_instance_through_two_params(&self) {
+        let _guard = self.mu.lock().unwrap();
+        lock_second(self, self);
+    }
+
+    fn maybe_same_instance(&self, other: &Foo) {
+        let _guard = self.mu.lock().unwrap();
+        *other.mu.lock().unwrap() += 1;
+    }
+}
+
+struct Inner {
+    mu: sync::Mutex<i32>,
+}
+
+struct Outer {
+    a: Inner,
+    b: Inner,
+}
+
+fn lock_inner(inner: &Inner) {
+    *inner.mu.lock().unwrap() += 1;
+}
+
+impl Outer {
+    fn new() -> Self {
+        Self {
+            a: Inner {
+                mu: sync::Mutex::new(1),
+            },
+            b: Inner {
+                mu: sync::Mutex::new(1),
+            },
+        }
+    }
+
+    fn same_field_through_arg(&self) {
+        let _guard = self.a.mu.lock().unwrap();
+        lock_inner(&self.a);
+    }
+
+    fn other_field_through_arg(&self) {
+        let _guard = self.a.mu.lock().unwrap();
+        lock_inner(&self.b);
+    }
+}
+
+fn two_instances() {
+    let a = Foo::new();
+    let b = Foo::new();
+    let _guard = a.mu.lock().unwrap();
+    *b.mu.lock().unwrap() += 1;
+    lock_second(&a, &b);
+}
+
+fn main() {
+    let a = Foo::new();
+    let b = Foo::new();
+    a.maybe_same_instance(&b);
+    two_instances();
+    a.same_instance_through_two_params();
+    let outer = Outer::new();
+    outer.other_field_through_arg();
+    outer.same_field_through_arg();
+}
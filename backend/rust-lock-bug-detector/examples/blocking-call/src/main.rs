@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::Read;
+use std::sync::{self, mpsc};
+use std::thread;
+use std::time::Duration;
+use parking_lot;
+
+struct Worker {
+    state: sync::Mutex<i32>,
+    config: parking_lot::RwLock<String>,
+}
+
+impl Worker {
+    fn new() -> Self {
+        Self {
+            state: sync::Mutex::new(0),
+            config: parking_lot::RwLock::new(String::new()),
+        }
+    }
+
+    fn join_holding_lock(&self) {
+        let mut state = self.state.lock().unwrap();
+        let handle = thread::spawn(|| 1);
+        *state += handle.join().unwrap();
+    }
+
+    fn recv_holding_lock(&self, rx: &mpsc::Receiver<i32>) {
+        let mut state = self.state.lock().unwrap();
+        *state += rx.recv().unwrap();
+    }
+
+    fn sleep_holding_lock(&self) {
+        let _state = self.state.lock().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    fn read_file_holding_lock(&self) {
+        let mut config = self.config.write();
+        *config = fs::read_to_string("config.toml").unwrap_or_default();
+    }
+
+    fn read_open_file_holding_lock(&self, file: &mut fs::File) {
+        let mut config = self.config.write();
+        let _ = file.read_to_string(&mut config);
+    }
+
+    fn sleep_in_callee_holding_lock(&self) {
+        let _config = self.config.read();
+        self.wait_a_bit();
+    }
+
+    fn wait_a_bit(&self) {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    fn read_file_after_unlock(&self) {
+        let config = fs::read_to_string("config.toml").unwrap_or_default();
+        *self.config.write() = config;
+    }
+
+    fn dir_entries_holding_lock(&self, entries: &[fs::DirEntry]) {
+        let mut state = self.state.lock().unwrap();
+        for entry in entries {
+            if entry.file_name().len() > 0 {
+                *state += 1;
+            }
+        }
+    }
+}
+
+fn main() {
+    let worker = Worker::new();
+    let (tx, rx) = mpsc::channel();
+    tx.send(1).unwrap();
+    worker.join_holding_lock();
+    worker.recv_holding_lock(&rx);
+    worker.sleep_holding_lock();
+    worker.read_file_holding_lock();
+    if let Ok(mut file) = fs::File::open("config.toml") {
+        worker.read_open_file_holding_lock(&mut file);
+    }
+    worker.sleep_in_callee_holding_lock();
+    worker.read_file_after_unlock();
+    worker.dir_entries_holding_lock(&[]);
+}
@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+trait Update {
+    fn update(&self);
+}
+
+struct Db {
+    mu: Mutex<i32>,
+}
+
+impl Update for Db {
+    fn update(&self) {
+        *self.mu.lock().unwrap() += 1;
+    }
+}
+
+struct Log {
+    mu: Mutex<Vec<i32>>,
+}
+
+impl Update for Log {
+    fn update(&self) {
+        self.mu.lock().unwrap().push(1);
+    }
+}
+
+fn update_generic<U: Update>(u: &U) {
+    u.update();
+}
+
+fn call_fn_pointer(db: &Db, f: fn(&Db)) {
+    f(db);
+}
+
+fn call_dyn_fn(db: &Db, f: &dyn Fn(&Db)) {
+    f(db);
+}
+
+fn call_passed_on<F: Fn()>(f: F) {
+    call_closure(f);
+}
+
+fn call_closure<F: Fn()>(f: F) {
+    f();
+}
+
+impl Db {
+    fn trait_call(&self) {
+        let _guard = self.mu.lock().unwrap();
+        self.update();
+    }
+
+    fn generic_call(&self) {
+        let _guard = self.mu.lock().unwrap();
+        update_generic(self);
+    }
+
+    fn dyn_call(&self) {
+        let _guard = self.mu.lock().unwrap();
+        let u: &dyn Update = self;
+        u.update();
+    }
+
+    fn other_impl_call(&self, log: &Log) {
+        let _guard = self.mu.lock().unwrap();
+        update_generic(log);
+    }
+
+    fn with_lock<F: FnOnce(&mut i32)>(&self, f: F) {
+        let mut guard = self.mu.lock().unwrap();
+        f(&mut guard);
+    }
+
+    fn closure_callback(&self) {
+        self.with_lock(|value| *value += *self.mu.lock().unwrap());
+    }
+
+    fn fn_pointer_callback(&self) {
+        let _guard = self.mu.lock().unwrap();
+        call_fn_pointer(self, Db::update);
+    }
+
+    fn dyn_fn_callback(&self) {
+        let _guard = self.mu.lock().unwrap();
+        call_dyn_fn(self, &|db| db.update());
+    }
+
+    fn callback_passed_on(&self) {
+        let _guard = self.mu.lock().unwrap();
+        call_passed_on(|| self.update());
+    }
+}
+
+fn main() {
+    let db = Db { mu: Mutex::new(1) };
+    let log = Log { mu: Mutex::new(Vec::new()) };
+    db.trait_call();
+    db.generic_call();
+    db.dyn_call();
+    db.other_impl_call(&log);
+    db.closure_callback();
+    db.fn_pointer_callback();
+    db.dyn_fn_callback();
+    db.callback_passed_on();
+}
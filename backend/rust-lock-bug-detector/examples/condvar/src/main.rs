@@ -0,0 +1,88 @@
+use std::sync;
+use parking_lot;
+
+struct Queue {
+    items: sync::Mutex<Vec<i32>>,
+    stats: sync::Mutex<i32>,
+    ready: sync::Condvar,
+}
+
+impl Queue {
+    fn new() -> Self {
+        Self {
+            items: sync::Mutex::new(Vec::new()),
+            stats: sync::Mutex::new(0),
+            ready: sync::Condvar::new(),
+        }
+    }
+
+    fn wait_without_loop(&self) -> Option<i32> {
+        let mut items = self.items.lock().unwrap();
+        if items.is_empty() {
+            items = self.ready.wait(items).unwrap();
+        }
+        items.pop()
+    }
+
+    fn wait_in_loop(&self) -> i32 {
+        let mut items = self.items.lock().unwrap();
+        loop {
+            if let Some(item) = items.pop() {
+                return item;
+            }
+            items = self.ready.wait(items).unwrap();
+        }
+    }
+
+    fn wait_while(&self) -> Option<i32> {
+        let items = self.items.lock().unwrap();
+        let mut items = self.ready.wait_while(items, |items| items.is_empty()).unwrap();
+        items.pop()
+    }
+
+    fn wait_holding_another_lock(&self) -> i32 {
+        let mut stats = self.stats.lock().unwrap();
+        let mut items = self.items.lock().unwrap();
+        while items.is_empty() {
+            items = self.ready.wait(items).unwrap();
+        }
+        *stats += 1;
+        items.pop().unwrap()
+    }
+
+    fn wait_on_stats(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        while *stats == 0 {
+            stats = self.ready.wait(stats).unwrap();
+        }
+    }
+
+    fn push(&self, item: i32) {
+        self.items.lock().unwrap().push(item);
+        self.ready.notify_one();
+    }
+}
+
+fn parking_lot_wait_without_loop(mu: &parking_lot::Mutex<bool>, cv: &parking_lot::Condvar) {
+    let mut ready = mu.lock();
+    if !*ready {
+        cv.wait(&mut ready);
+    }
+}
+
+fn main() {
+    let queue = Queue::new();
+    queue.push(1);
+    queue.push(2);
+    queue.push(3);
+    queue.push(4);
+    queue.wait_without_loop();
+    queue.wait_in_loop();
+    queue.wait_while();
+    queue.wait_holding_another_lock();
+    *queue.stats.lock().unwrap() = 1;
+    queue.wait_on_stats();
+    let mu = parking_lot::Mutex::new(true);
+    let cv = parking_lot::Condvar::new();
+    parking_lot_wait_without_loop(&mu, &cv);
+}
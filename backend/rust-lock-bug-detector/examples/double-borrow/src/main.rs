@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+
+struct Counter {
+    value: RefCell<i32>,
+    history: RefCell<Vec<i32>>,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self {
+            value: RefCell::new(0),
+            history: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn borrow_then_borrow_mut(&self) {
+        let value = self.value.borrow();
+        *self.value.borrow_mut() += *value;
+    }
+
+    fn borrow_mut_then_borrow(&self) {
+        let mut value = self.value.borrow_mut();
+        *value += *self.value.borrow();
+    }
+
+    fn borrow_then_borrow(&self) {
+        let first = self.value.borrow();
+        let second = self.value.borrow();
+        println!("{}", *first + *second);
+    }
+
+    fn two_cells(&self) {
+        let value = self.value.borrow();
+        self.history.borrow_mut().push(*value);
+    }
+
+    fn dropped_before_borrow_mut(&self) {
+        let value = *self.value.borrow();
+        *self.value.borrow_mut() += value;
+    }
+
+    fn record_1(&self) {
+        let value = self.value.borrow_mut();
+        self.record_2(*value);
+    }
+
+    fn record_2(&self, value: i32) {
+        *self.value.borrow_mut() = value + 1;
+    }
+}
+
+fn main() {
+    let counter = Counter::new();
+    counter.borrow_then_borrow_mut();
+    counter.borrow_mut_then_borrow();
+    counter.borrow_then_borrow();
+    counter.two_cells();
+    counter.dropped_before_borrow_mut();
+    counter.record_1();
+}
@@ -0,0 +1,84 @@
+use std::mem;
+use std::sync;
+use parking_lot;
+
+struct Foo {
+    mu1: sync::Mutex<i32>,
+    mu2: parking_lot::Mutex<i32>,
+    cv: sync::Condvar,
+}
+
+fn release(guard: sync::MutexGuard<i32>) {
+    println!("{}", *guard);
+}
+
+fn lock_again(guard: parking_lot::MutexGuard<i32>, foo: &Foo) {
+    *foo.mu2.lock() += *guard;
+}
+
+impl Foo {
+    fn new() -> Self {
+        Self {
+            mu1: sync::Mutex::new(1),
+            mu2: parking_lot::Mutex::new(1),
+            cv: sync::Condvar::new(),
+        }
+    }
+
+    fn drop_then_lock(&self) {
+        let guard = self.mu1.lock().unwrap();
+        let value = *guard;
+        drop(guard);
+        *self.mu1.lock().unwrap() += value;
+    }
+
+    fn rebind_then_drop_then_lock(&self) {
+        let guard = self.mu1.lock().unwrap();
+        let rebound = guard;
+        drop(rebound);
+        *self.mu1.lock().unwrap() += 1;
+    }
+
+    fn wait_then_drop_then_lock(&self) {
+        let mut guard = self.mu1.lock().unwrap();
+        while *guard == 0 {
+            guard = self.cv.wait(guard).unwrap();
+        }
+        drop(guard);
+        *self.mu1.lock().unwrap() += 1;
+    }
+
+    fn forget_then_lock(&self) {
+        let guard = self.mu2.lock();
+        mem::forget(guard);
+        *self.mu2.lock() += 1;
+    }
+
+    fn move_into_releasing_fn_then_lock(&self) {
+        let guard = self.mu1.lock().unwrap();
+        release(guard);
+        *self.mu1.lock().unwrap() += 1;
+    }
+
+    fn move_into_tuple_then_lock(&self) {
+        let guard = self.mu1.lock().unwrap();
+        let pair = (guard, 1);
+        *self.mu1.lock().unwrap() += pair.1;
+    }
+
+    fn move_into_locking_fn(&self) {
+        let guard = self.mu2.lock();
+        lock_again(guard, self);
+    }
+}
+
+fn main() {
+    let foo = Foo::new();
+    foo.drop_then_lock();
+    foo.rebind_then_drop_then_lock();
+    foo.wait_then_drop_then_lock();
+    foo.move_into_releasing_fn_then_lock();
+    foo.move_into_locking_fn();
+    foo.move_into_tuple_then_lock();
+    foo.forget_then_lock();
+}
@@ -0,0 +1,261 @@
+//! The detectors run in-process on the crate being compiled.
+//!
+//! `analyze` returns the findings of every detector enabled in the config instead of printing them,
+//! for the driver in `main.rs` and for tools embedding the detector in their own rustc driver.
+//! The summary of the crate is still written to the summary dir of the config, whichever detectors are enabled.
+//!
+//! The detectors enabled all run in the one compilation,
+//! on the lockguards and callgraph of a `CrateContext` computed once for them.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use crate::await_lock_checker::{AwaitLockBug, AwaitLockChecker};
+use crate::baseline::Baseline;
+use crate::blocking_call_checker::{BlockingCallBug, BlockingCallChecker};
+use crate::condvar_checker::{CondvarBug, CondvarChecker};
+use crate::config::{LockDetectorConfig, LockDetectorType, OutputConfig, Severities, Severity};
+use crate::conflict_lock_checker::{ConflictLockBug, ConflictLockChecker};
+use crate::crate_context::CrateContext;
+use crate::double_borrow_checker::{DoubleBorrowBug, DoubleBorrowChecker};
+use crate::double_lock_checker::{DoubleLockBug, DoubleLockChecker};
+use crate::output::{Findings, SpanInfo};
+use crate::results::{
+    AnalysisStats, CachedReport, CrateFindingDigest, FindingCount, FindingFingerprint,
+};
+use rustc_hir::def_id::LOCAL_CRATE;
+use rustc_middle::ty::TyCtxt;
+
+/// The findings of the detectors on a crate, `None` for the detectors not run.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisResult {
+    pub crate_name: String,
+    pub double_locks: Option<Findings<DoubleLockBug>>,
+    pub conflict_locks: Option<Findings<ConflictLockBug>>,
+    pub await_locks: Option<Findings<AwaitLockBug>>,
+    pub condvars: Option<Findings<CondvarBug>>,
+    pub double_borrows: Option<Findings<DoubleBorrowBug>>,
+    pub blocking_calls: Option<Findings<BlockingCallBug>>,
+    /// of the double-lock detector, or of the conflict-lock detector when it is run without it
+    pub stats: Option<AnalysisStats>,
+}
+
+impl AnalysisResult {
+    /// The crate reports of the detectors run, in the output format, without the empty text reports.
+    pub fn render(&self, output: &OutputConfig) -> Vec<String> {
+        let crate_name = self.crate_name.as_str();
+        vec![
+            self.await_locks
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+            self.double_locks
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+            self.conflict_locks
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+            self.condvars
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+            self.double_borrows
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+            self.blocking_calls
+                .as_ref()
+                .and_then(|f| f.render(crate_name, output)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// The crate reports of the detectors run in every output format, for `cargo lock-bug-detect` to cache.
+    pub fn cached_reports(&self, severities: &Severities) -> Vec<CachedReport> {
+        let crate_name = self.crate_name.as_str();
+        vec![
+            self.await_locks
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+            self.double_locks
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+            self.conflict_locks
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+            self.condvars
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+            self.double_borrows
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+            self.blocking_calls
+                .as_ref()
+                .map(|f| f.cached_report(crate_name, severities)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Drop the findings in `baseline` from the findings of every detector run.
+    pub fn apply_baseline(&mut self, baseline: &Baseline) {
+        if let Some(f) = self.await_locks.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+        if let Some(f) = self.double_locks.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+        if let Some(f) = self.conflict_locks.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+        if let Some(f) = self.condvars.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+        if let Some(f) = self.double_borrows.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+        if let Some(f) = self.blocking_calls.as_mut() {
+            f.apply_baseline(baseline, |bug| &bug.fingerprint);
+        }
+    }
+
+    /// The number of findings and the fingerprints of each detector run.
+    pub fn finding_digest(&self, severities: &Severities) -> CrateFindingDigest {
+        let mut digest = CrateFindingDigest {
+            crate_name: self.crate_name.clone(),
+            findings: Vec::new(),
+            fingerprints: Vec::new(),
+            baselined: Vec::new(),
+        };
+        add_findings(
+            &mut digest,
+            "await-lock",
+            severities.await_lock,
+            &self.await_locks,
+            |bug| (&bug.fingerprint, &bug.await_span),
+        );
+        add_findings(
+            &mut digest,
+            "double-lock",
+            severities.double_lock,
+            &self.double_locks,
+            |bug| (&bug.fingerprint, &bug.second_lock.span),
+        );
+        add_findings(
+            &mut digest,
+            "conflict-lock",
+            severities.conflict_lock,
+            &self.conflict_locks,
+            |bug| (&bug.fingerprint, &bug.edges[0][0].second_lock),
+        );
+        add_findings(
+            &mut digest,
+            "condvar",
+            severities.condvar,
+            &self.condvars,
+            |bug| (&bug.fingerprint, &bug.wait_span),
+        );
+        add_findings(
+            &mut digest,
+            "double-borrow",
+            severities.double_borrow,
+            &self.double_borrows,
+            |bug| (&bug.fingerprint, &bug.second_borrow.span),
+        );
+        add_findings(
+            &mut digest,
+            "blocking-call",
+            severities.blocking_call,
+            &self.blocking_calls,
+            |bug| (&bug.fingerprint, &bug.call_span),
+        );
+        digest
+    }
+}
+
+/// Add the findings of a detector to `digest`, nothing if it was not run.
+/// `fingerprint` gives the fingerprint and the primary location of a finding.
+fn add_findings<B>(
+    digest: &mut CrateFindingDigest,
+    kind: &'static str,
+    severity: Severity,
+    findings: &Option<Findings<B>>,
+    fingerprint: impl Fn(&B) -> (&String, &SpanInfo),
+) {
+    let findings = match findings {
+        Some(findings) => findings,
+        None => return,
+    };
+    digest.findings.push(FindingCount {
+        kind: kind.to_string(),
+        severity: severity.as_str().to_string(),
+        count: findings.bugs.len(),
+    });
+    digest.fingerprints.extend(findings.bugs.iter().map(|bug| {
+        let (fingerprint, location) = fingerprint(bug);
+        FindingFingerprint {
+            kind: kind.to_string(),
+            fingerprint: fingerprint.clone(),
+            location: location.clone(),
+        }
+    }));
+    digest.baselined.extend(findings.baselined.iter().cloned());
+}
+
+/// Run the detectors of `config` on the crate of `tcx`, nothing for a crate the crate filter skips.
+pub fn analyze(tcx: TyCtxt, config: &LockDetectorConfig) -> AnalysisResult {
+    let mut result = AnalysisResult {
+        crate_name: tcx.crate_name(LOCAL_CRATE).to_string(),
+        ..AnalysisResult::default()
+    };
+    if !config.crate_filter.is_checked(&result.crate_name) {
+        return result;
+    }
+    // the await lock detector reads the MIR before optimization, which the other detectors steal
+    if config
+        .lock_detector_types
+        .contains(&LockDetectorType::AwaitLockDetector)
+    {
+        result.await_locks = AwaitLockChecker::new(config).check(tcx);
+    }
+    if config.summary_dir.is_some()
+        || config
+            .lock_detector_types
+            .iter()
+            .any(|lock_detector_type| *lock_detector_type != LockDetectorType::AwaitLockDetector)
+    {
+        check_optimized_mir(tcx, config, &mut result);
+    }
+    if let Some(baseline) = &config.baseline {
+        result.apply_baseline(baseline);
+    }
+    result
+}
+
+/// Run the detectors reading the optimized MIR, on a `CrateContext` shared by them.
+fn check_optimized_mir(tcx: TyCtxt, config: &LockDetectorConfig, result: &mut AnalysisResult) {
+    let crate_context = CrateContext::new(tcx, config);
+    crate_context.write_summary(tcx, config);
+    for lock_detector_type in config.lock_detector_types.iter() {
+        match lock_detector_type {
+            LockDetectorType::DoubleLockDetector => {
+                let mut double_lock_checker = DoubleLockChecker::new(config);
+                result.double_locks = double_lock_checker.check(tcx, &crate_context);
+                result.stats = Some(double_lock_checker.stats());
+            }
+            LockDetectorType::ConflictLockDetector => {
+                let mut conflict_lock_checker = ConflictLockChecker::new(config);
+                result.conflict_locks = conflict_lock_checker.check(tcx, &crate_context);
+                result.stats.get_or_insert(conflict_lock_checker.stats());
+            }
+            LockDetectorType::AwaitLockDetector => {}
+            LockDetectorType::CondvarDetector => {
+                result.condvars = CondvarChecker::new(config).check(tcx, &crate_context);
+            }
+            LockDetectorType::DoubleBorrowDetector => {
+                result.double_borrows = DoubleBorrowChecker::new(config).check(tcx, &crate_context);
+            }
+            LockDetectorType::BlockingCallDetector => {
+                result.blocking_calls = BlockingCallChecker::new(config).check(tcx, &crate_context);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Lockguards held across a suspension point of an async fn or generator.
+//! A blocking lock held across `.await` blocks the executor thread while the task is suspended,
+//! and deadlocks if another task on the same thread locks it.
+//! The generator transform replaces the `Yield` terminators in the optimized MIR,
+//! so the MIR before optimization is checked, with the collector and gen/kill of the double-lock detector.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use super::config::{AnalysisLimits, CrateFilter, LockDetectorConfig, UnknownGuardMove};
+use super::report::{AwaitLockBug, AwaitLockReports};
+use crate::crate_context::crate_findings;
+use crate::custom_guard::CustomGuardTypes;
+use crate::double_lock_checker::collector::collect_lockguard_info;
+use crate::double_lock_checker::genkill::GenKill;
+use crate::output::Findings;
+use rustc_hir::def_id::{LocalDefId, LOCAL_CRATE};
+use rustc_middle::mir::TerminatorKind;
+use rustc_middle::ty::TyCtxt;
+use std::collections::HashSet;
+
+pub struct AwaitLockChecker {
+    crate_filter: CrateFilter,
+    limits: AnalysisLimits,
+    crate_await_lock_reports: AwaitLockReports,
+    custom_guard_types: CustomGuardTypes,
+    unknown_guard_move: UnknownGuardMove,
+}
+
+impl AwaitLockChecker {
+    pub fn new(config: &LockDetectorConfig) -> Self {
+        Self {
+            crate_filter: config.crate_filter.clone(),
+            limits: config.limits,
+            crate_await_lock_reports: AwaitLockReports::new(),
+            custom_guard_types: CustomGuardTypes::new(&config.guard_types),
+            unknown_guard_move: config.unknown_guard_move,
+        }
+    }
+
+    /// Must run before the other checkers: optimizing a fn steals its MIR before optimization.
+    pub fn check(&mut self, tcx: TyCtxt) -> Option<Findings<AwaitLockBug>> {
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+        if !self.crate_filter.is_checked(&crate_name) {
+            return None;
+        }
+        // generators are closures
+        let fn_ids: Vec<LocalDefId> = tcx
+            .mir_keys(LOCAL_CRATE)
+            .iter()
+            .cloned()
+            .filter(|id| {
+                let hir = tcx.hir();
+                hir.body_owner_kind(hir.as_local_hir_id(*id))
+                    .is_fn_or_closure()
+            })
+            .collect();
+        for fn_id in fn_ids {
+            self.check_generator(tcx, fn_id);
+        }
+        Some(crate_findings(tcx, &mut self.crate_await_lock_reports))
+    }
+
+    fn check_generator(&mut self, tcx: TyCtxt, fn_id: LocalDefId) {
+        let body = tcx.mir_validated(fn_id).0.borrow();
+        if body.generator_kind.is_none() {
+            return;
+        }
+        let lockguards = collect_lockguard_info(
+            tcx,
+            fn_id,
+            &body,
+            &self.custom_guard_types,
+            self.unknown_guard_move,
+        );
+        if lockguards.is_empty() {
+            return;
+        }
+        let context = HashSet::new();
+        let mut genkill = GenKill::new(fn_id, &body, &lockguards, &context, self.limits.run_limit);
+        // the double-locks are left to the double-lock detector
+        genkill.analyze(&body);
+        for (bb, data) in body.basic_blocks().iter_enumerated() {
+            let terminator = data.terminator();
+            if let TerminatorKind::Yield { .. } = terminator.kind {
+                if let Some(live) = genkill.get_live_lockguards_after(&bb) {
+                    for id in live {
+                        self.crate_await_lock_reports
+                            .add(&lockguards[id], terminator.source_info.span);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,5 @@
+mod checker;
+mod report;
+pub use self::checker::AwaitLockChecker;
+pub use self::report::AwaitLockBug;
+use super::config;
@@ -0,0 +1,171 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::baseline::Fingerprinter;
+use crate::config::Severities;
+use crate::crate_context::CrateReports;
+use crate::double_lock_checker::lock::{LockGuardInfo, LockGuardType};
+use crate::output::{CrateReport, Findings, LockSite, SpanInfo};
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct AwaitLockSite {
+    lock_type_name: (LockGuardType, String),
+    lock_span: Span,
+    await_span: Span,
+}
+
+/// One lockguard held across a suspension point in the serialized report.
+#[derive(Debug, Clone, Serialize)]
+pub struct AwaitLockBug {
+    pub lock: LockSite,
+    pub await_span: SpanInfo,
+    /// identifies the await-lock in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct AwaitLockCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    await_locks: Vec<AwaitLockBug>,
+    suppressed: usize,
+}
+
+pub struct AwaitLockReports {
+    reports: HashSet<AwaitLockSite>,
+    // the number of reports suppressed in source
+    suppressed: usize,
+}
+
+impl AwaitLockReports {
+    pub fn new() -> Self {
+        Self {
+            reports: HashSet::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// `lockguard` is live at the suspension point `await_span`.
+    pub fn add(&mut self, lockguard: &LockGuardInfo, await_span: Span) {
+        self.reports.insert(AwaitLockSite {
+            lock_type_name: lockguard.type_name.clone(),
+            lock_span: lockguard.span,
+            await_span,
+        });
+    }
+
+    /// A block for each lockguard held across a suspension point.
+    pub fn text(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> TextReport {
+        let mut sites = self.reports.iter().collect::<Vec<_>>();
+        sites.sort_by_key(|site| (site.await_span, site.lock_span));
+        let mut text = TextReport::new("await-locks", self.suppressed);
+        for site in sites {
+            text.finding(
+                Self::bug(site, tcx, fingerprinter).fingerprint,
+                format!(
+                    "AwaitLockReport: {:?}\n\tLock: {:?}\n\tAwait: {:?}\n",
+                    site.lock_type_name, site.lock_span, site.await_span
+                ),
+            );
+        }
+        text
+    }
+
+    fn bug(site: &AwaitLockSite, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> AwaitLockBug {
+        let source_map = tcx.sess.source_map();
+        let mut bug = AwaitLockBug {
+            lock: LockSite {
+                lock_type: format!("{:?}", site.lock_type_name.0),
+                data_type: site.lock_type_name.1.clone(),
+                span: SpanInfo::new(site.lock_span, source_map),
+            },
+            await_span: SpanInfo::new(site.await_span, source_map),
+            fingerprint: String::new(),
+        };
+        bug.fingerprint = fingerprinter.fingerprint(
+            "await-lock",
+            &[fingerprinter.lock_site(&bug.lock), fingerprinter.position(&bug.await_span)],
+        );
+        bug
+    }
+
+    /// Resolve spans into serializable findings, sorted by location.
+    pub fn bugs(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<AwaitLockBug> {
+        let mut bugs = self
+            .reports
+            .iter()
+            .map(|site| Self::bug(site, tcx, fingerprinter))
+            .collect::<Vec<_>>();
+        bugs.sort_by(|a, b| (&a.await_span, &a.lock.span).cmp(&(&b.await_span, &b.lock.span)));
+        bugs
+    }
+}
+
+impl CrateReports for AwaitLockReports {
+    type Bug = AwaitLockBug;
+
+    /// Drop the reports whose lock or `.await` is suppressed in source, counting them.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        let before = self.reports.len();
+        self.reports.retain(|site| {
+            !suppressions.is_suppressed(BugKind::AwaitLock, site.lock_span)
+                && !suppressions.is_suppressed(BugKind::AwaitLock, site.await_span)
+        });
+        self.suppressed += before - self.reports.len();
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<AwaitLockBug> {
+        Findings {
+            bugs: self.bugs(tcx, fingerprinter),
+            suppressed: self.suppressed,
+            baselined: Vec::new(),
+            text: self.text(tcx, fingerprinter),
+        }
+    }
+}
+
+impl CrateReport for Findings<AwaitLockBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = AwaitLockCrateReport {
+            crate_name,
+            severity: severities.await_lock.as_str(),
+            await_locks: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log, the `.await` being the primary location.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let lock = format!("{}<{}>", bug.lock.lock_type, bug.lock.data_type);
+                SarifFinding {
+                    rule: SarifRule::AwaitLock,
+                    message: format!("{} is held across a suspension point", lock),
+                    primary: SarifLocation::new(
+                        bug.await_span.clone(),
+                        format!("suspended while holding {}", lock),
+                    ),
+                    related: vec![SarifLocation::new(bug.lock.span.clone(), format!("{} acquired", lock))],
+                    code_flows: vec![vec![
+                        SarifLocation::new(bug.lock.span, format!("{} acquired", lock)),
+                        SarifLocation::new(bug.await_span, "suspended while holding the lock"),
+                    ]],
+                    fingerprint: bug.fingerprint,
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
@@ -0,0 +1,182 @@
+//! Baselines of known findings, so that a run reports only the findings not in the baseline.
+//!
+//! A finding is identified by a fingerprint that survives edits elsewhere in the crate:
+//! a hash of the crate name, the bug kind, the lock srcs and lock types, and each location as
+//! the def path of the innermost fn containing it with the line offset from the start of that fn.
+//! The MIR place of a lock local to a fn is left out, as MIR renumbers its locals on any edit of the fn.
+//!
+//! `cargo lock-bug-detect --write-baseline` collects the fingerprints of a run into a baseline file,
+//! `--baseline` drops the findings it holds from the results of the run,
+//! and the driver run on its own drops them with `RUST_LOCK_DETECTOR_BASELINE`.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use crate::output::{LockSite, LockSrcInfo, SpanInfo};
+use crate::results::{BaselineFile, BASELINE_VERSION};
+use crate::summary::canonical_def_path;
+use rustc_hir::def_id::LOCAL_CRATE;
+use rustc_middle::ty::TyCtxt;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// The fingerprints of the findings of a baseline file.
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    fingerprints: HashSet<String>,
+}
+
+impl Baseline {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read baseline {}: {}", path, e))?;
+        let file: BaselineFile = serde_json::from_str(&content)
+            .map_err(|e| format!("cannot parse baseline {}: {}", path, e))?;
+        if file.version != BASELINE_VERSION {
+            return Err(format!(
+                "baseline {} is of version {}, expected {}",
+                path, file.version, BASELINE_VERSION
+            ));
+        }
+        Ok(Self {
+            fingerprints: file
+                .findings
+                .into_iter()
+                .map(|entry| entry.fingerprint)
+                .collect(),
+        })
+    }
+
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.fingerprints.contains(fingerprint)
+    }
+}
+
+/// Computes the fingerprints of the findings of the crate of `tcx`.
+pub struct Fingerprinter {
+    crate_name: String,
+    // the span of each fn and closure of the crate and its def path
+    fns: Vec<(SpanInfo, String)>,
+}
+
+impl Fingerprinter {
+    pub fn new(tcx: TyCtxt) -> Self {
+        let hir = tcx.hir();
+        let source_map = tcx.sess.source_map();
+        let fns = tcx
+            .mir_keys(LOCAL_CRATE)
+            .iter()
+            .filter_map(|id| {
+                let hir_id = hir.as_local_hir_id(*id);
+                if !hir.body_owner_kind(hir_id).is_fn_or_closure() {
+                    return None;
+                }
+                let span = hir.span(hir_id).source_callsite();
+                Some((
+                    SpanInfo::new(span, source_map),
+                    canonical_def_path(tcx, id.to_def_id()),
+                ))
+            })
+            .collect();
+        Self {
+            crate_name: tcx.crate_name(LOCAL_CRATE).to_string(),
+            fns,
+        }
+    }
+
+    /// The innermost fn containing `span` and the line offset from its start,
+    /// or only the file name for a location outside the fns, e.g. in the summary of an extern crate.
+    pub fn position(&self, span: &SpanInfo) -> String {
+        let innermost = self
+            .fns
+            .iter()
+            .filter(|(fn_span, _)| {
+                fn_span.file == span.file
+                    && fn_span.start_line <= span.start_line
+                    && span.start_line <= fn_span.end_line
+            })
+            .max_by_key(|(fn_span, _)| (fn_span.start_line, fn_span.start_col));
+        match innermost {
+            Some((fn_span, def_path)) => {
+                format!("{}+{}", def_path, span.start_line - fn_span.start_line)
+            }
+            None => Path::new(&span.file)
+                .file_name()
+                .map_or(span.file.clone(), |name| name.to_string_lossy().to_string()),
+        }
+    }
+
+    /// The lock type, the protected data type and the position of a lock acquisition.
+    pub fn lock_site(&self, lock: &LockSite) -> String {
+        format!(
+            "{}<{}>@{}",
+            lock.lock_type,
+            lock.data_type,
+            self.position(&lock.span)
+        )
+    }
+
+    /// The hex FNV-1a hash of the crate name, `kind` and `parts`,
+    /// a hash that does not change with the rustc or std version.
+    pub fn fingerprint(&self, kind: &str, parts: &[String]) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let fields = [self.crate_name.as_str(), kind]
+            .iter()
+            .copied()
+            .chain(parts.iter().map(|part| part.as_str()));
+        for field in fields {
+            // NUL separated so that moving characters between fields changes the hash
+            for byte in field.bytes().chain(Some(0)) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+}
+
+/// A lock src in a fingerprint, without the MIR place of a local lock.
+pub fn lock_src(src: &LockSrcInfo) -> String {
+    match src {
+        LockSrcInfo::ParamSrc {
+            struct_type,
+            fields,
+        } => format!("{}.{}", struct_type, fields),
+        LockSrcInfo::LocalSrc { .. } => "local".to_string(),
+        LockSrcInfo::GlobalSrc { global } => global.clone(),
+    }
+}
+
+#[test]
+fn test_baseline_load() {
+    use crate::results::BaselineEntry;
+    let path = std::env::temp_dir().join(format!(
+        "lock-bug-detect-load-baseline-{}.json",
+        std::process::id()
+    ));
+    let path = path.to_str().unwrap();
+    // as `cargo lock-bug-detect --write-baseline` writes it
+    let mut file = BaselineFile {
+        version: BASELINE_VERSION,
+        findings: vec![BaselineEntry {
+            fingerprint: "00000000000000aa".to_string(),
+            kind: "double-lock".to_string(),
+            crate_name: "my_crate".to_string(),
+            location: "src/lib.rs:12".to_string(),
+        }],
+    };
+    fs::write(path, serde_json::to_string_pretty(&file).unwrap()).unwrap();
+    let baseline = Baseline::load(path).unwrap();
+    assert!(baseline.contains("00000000000000aa"));
+    assert!(!baseline.contains("00000000000000bb"));
+    file.version = BASELINE_VERSION + 1;
+    fs::write(path, serde_json::to_string_pretty(&file).unwrap()).unwrap();
+    assert!(Baseline::load(path).unwrap_err().contains("expected 1"));
+    fs::write(path, "{}").unwrap();
+    assert!(Baseline::load(path)
+        .unwrap_err()
+        .starts_with("cannot parse baseline"));
+    fs::remove_file(path).unwrap();
+    assert!(Baseline::load(path)
+        .unwrap_err()
+        .starts_with("cannot read baseline"));
+}
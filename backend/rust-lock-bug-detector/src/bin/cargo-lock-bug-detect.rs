@@ -1,27 +1,370 @@
+// the driver builds the text reports, the subcommand only filters and renders them
+#[allow(dead_code)]
+#[path = "../results.rs"]
+mod results;
+
+use cargo_metadata::{Metadata, MetadataCommand, Package};
+use results::{
+    BaselineEntry, BaselineFile, CachedReport, CachedResults, CrateFindingDigest, FindingCount,
+    BASELINE_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::ffi::OsString;
-use std::process::Command;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-const CARGO_LOCK_BUG_DETECTOR_HELP: &str = r#"Detect double-lock&conflict-lock on MIR
+const CARGO_LOCK_BUG_DETECTOR_HELP: &str = r#"Detect double-lock&conflict-lock&await-lock&condvar misuse&RefCell double-borrow&blocking calls under locks on MIR
 Usage:
     cargo lock-bug-detect [subcommand] [<cargo options>...] [--] [<program/test suite options>...]
 Subcommands:
     double-lock              Detect double-lock bugs
     conflict-lock            Detect conflict-lock bugs
+    await-lock               Detect lockguards held across .await
+    condvar                  Detect Condvar waits holding another lock, outside a loop or with mixed mutexes
+    double-borrow            Detect RefCell borrow_mut while the cell is borrowed
+    blocking-call            Detect blocking calls (join, recv, sleep, IO, ...) while a lock is held
+    all                      Run all the detectors above in one cargo check
+    <a>,<b>,...              Run the detectors listed, e.g. double-lock,conflict-lock
+    (none)                   Run the detectors of the config file, double-lock by default
 Common options:
     -h, --help               Print this message
     -V, --version            Print version info and exit
+    --config <FILE>          Read the config from FILE instead of lock-bug-detect.toml
+    --output-format <FMT>    Report format: text (default), json or sarif
+    --output <FILE>          Write reports to FILE instead of stdout
+    -p, --package <SPEC>     Check only the package SPEC, a workspace member or a dependency
+    --workspace              Check all the workspace members, the default
+    --exclude <SPEC>         Do not check the workspace member SPEC, with --workspace
+    --deny <KIND>            Exit with 1 if there is any finding of the bug kind KIND, e.g. double-lock
+    --fail-on <SEVERITY>     Exit with 1 if there is any finding of SEVERITY (error, warning or note) or above
+    --baseline <FILE>        Report only the findings not in the baseline FILE, and the ones in it no longer found
+    --write-baseline <FILE>  Write the findings of the run to the baseline FILE
 Other [options] are the same as `cargo check`. Everything after the second "--" verbatim
 to the program.
+The results of each crate checked are cached in lock-bug-detect/cache of the target dir, so the crates
+cargo considers fresh are reported without `cargo clean`. The first run and a config change rebuild
+the crates checked, the output format and the baseline do not.
+The report of the whole run is printed once `cargo check` finishes, ending with the totals.
+Config:
+    lock-bug-detect.toml in the workspace root is read if it exists. The subcommand and
+    the options above override it.
+        detectors = ["double-lock", "conflict-lock", "await-lock", "condvar", "double-borrow", "blocking-call"]
+        callchain-depth = 4
+        iteration-limit = 10000
+        link-dyn-calls = false      # link dyn Trait calls to every local impl of the method
+        unknown-guard-move = "release" # a guard moved into an unknown fn is released or held ("hold")
+        [crates]
+        include = ["my_crate*"]     # glob patterns of crate names, the workspace members if empty
+        exclude = ["cc", "*_sys"]   # default ["cc"]
+        [severity]
+        double-lock = "error"       # error, warning or note
+        conflict-lock = "warning"
+        [output]
+        format = "sarif"
+        path = "target/lock-bugs.sarif" # relative to the workspace root
+        [[guard-types]]             # lockguard types of your own locks
+        path = "my_sync::MutexGuard"
+        kind = "exclusive"          # exclusive, shared or upgradable
+        data = 0                    # the type argument protected, default the last
+        lock = "my_sync::Mutex"     # guards of the same lock conflict, default the guard type
+        result = false              # whether the guard is unwrapped from a Result
+        [blocking-calls]
+        functions = ["my_rpc::Client::call"] # glob patterns of def paths, added to the built-in ones
+        builtin = true              # whether to check the built-in ones: join, recv, sleep, std::fs, std::net, ...
 Examples:
+    cargo lock-bug-detect
     cargo lock-bug-detect double-lock
     cargo lock-bug-detect conflict-lock
+    cargo lock-bug-detect condvar
+    cargo lock-bug-detect double-borrow
+    cargo lock-bug-detect blocking-call
+    cargo lock-bug-detect all
+    cargo lock-bug-detect double-lock,conflict-lock
+    cargo lock-bug-detect all -p my_crate
+    cargo lock-bug-detect all --workspace --exclude my_bench
+    cargo lock-bug-detect double-lock --output-format json --output reports.json
+    cargo lock-bug-detect conflict-lock --output-format sarif --output reports.sarif
+    cargo lock-bug-detect all --fail-on error --deny conflict-lock
+    cargo lock-bug-detect all --write-baseline lock-bugs-baseline.json
+    cargo lock-bug-detect all --baseline lock-bugs-baseline.json --fail-on error
 "#;
 
+const CONFIG_FILE: &str = "lock-bug-detect.toml";
+const DETECTORS: [&str; 6] = [
+    "double-lock",
+    "conflict-lock",
+    "await-lock",
+    "condvar",
+    "double-borrow",
+    "blocking-call",
+];
+const OUTPUT_FORMATS: [&str; 3] = ["text", "json", "sarif"];
+const SEVERITIES: [&str; 3] = ["error", "warning", "note"];
+const GUARD_KINDS: [&str; 3] = ["exclusive", "shared", "upgradable"];
+const GUARD_MOVES: [&str; 2] = ["release", "hold"];
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum LockBugDetectCommand {
     DoubleLock,
     ConflictLock,
+    AwaitLock,
+    Condvar,
+    DoubleBorrow,
+    BlockingCall,
+    All,
+}
+
+impl LockBugDetectCommand {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "double-lock" => Some(LockBugDetectCommand::DoubleLock),
+            "conflict-lock" => Some(LockBugDetectCommand::ConflictLock),
+            "await-lock" => Some(LockBugDetectCommand::AwaitLock),
+            "condvar" => Some(LockBugDetectCommand::Condvar),
+            "double-borrow" => Some(LockBugDetectCommand::DoubleBorrow),
+            "blocking-call" => Some(LockBugDetectCommand::BlockingCall),
+            "all" => Some(LockBugDetectCommand::All),
+            _ => None,
+        }
+    }
+
+    /// The detectors run by the subcommand.
+    fn detectors(self) -> &'static [&'static str] {
+        match self {
+            LockBugDetectCommand::DoubleLock => &["double-lock"],
+            LockBugDetectCommand::ConflictLock => &["conflict-lock"],
+            LockBugDetectCommand::AwaitLock => &["await-lock"],
+            LockBugDetectCommand::Condvar => &["condvar"],
+            LockBugDetectCommand::DoubleBorrow => &["double-borrow"],
+            LockBugDetectCommand::BlockingCall => &["blocking-call"],
+            LockBugDetectCommand::All => &DETECTORS,
+        }
+    }
+}
+
+// `lock-bug-detect.toml`, every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    detectors: Option<Vec<String>>,
+    callchain_depth: Option<usize>,
+    iteration_limit: Option<u32>,
+    link_dyn_calls: bool,
+    unknown_guard_move: Option<String>,
+    crates: CratesConfig,
+    // bug kind -> severity
+    severity: BTreeMap<String, String>,
+    output: OutputConfig,
+    guard_types: Vec<GuardTypeConfig>,
+    blocking_calls: BlockingCallsConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CratesConfig {
+    include: Vec<String>,
+    exclude: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BlockingCallsConfig {
+    functions: Vec<String>,
+    builtin: bool,
+}
+
+impl Default for BlockingCallsConfig {
+    fn default() -> Self {
+        Self {
+            functions: Vec::new(),
+            builtin: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputConfig {
+    format: Option<String>,
+    path: Option<PathBuf>,
+}
+
+// passed to the detector as JSON
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct GuardTypeConfig {
+    path: String,
+    kind: String,
+    #[serde(default)]
+    data: Option<usize>,
+    #[serde(default)]
+    lock: Option<String>,
+    #[serde(default)]
+    result: bool,
+}
+
+// The finding counts of a run, at the end of its report.
+// A crate compiled more than once, e.g. as a lib and as a test, counts as its most findings.
+#[derive(Debug, Serialize)]
+struct FindingTotals {
+    crates: Vec<CrateFindings>,
+    // of each bug kind and severity over all the crates
+    kinds: Vec<FindingCount>,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct CrateFindings {
+    crate_name: String,
+    count: usize,
+    findings: Vec<FindingCount>,
+}
+
+impl FindingTotals {
+    fn new(digests: &[&CrateFindingDigest]) -> Self {
+        let mut crate_counts: BTreeMap<&str, BTreeMap<(&str, &str), usize>> = BTreeMap::new();
+        for digest in digests {
+            let counts = crate_counts.entry(digest.crate_name.as_str()).or_default();
+            for finding in digest.findings.iter() {
+                let count = counts
+                    .entry((finding.kind.as_str(), finding.severity.as_str()))
+                    .or_insert(0);
+                *count = (*count).max(finding.count);
+            }
+        }
+        let finding_counts = |counts: BTreeMap<(&str, &str), usize>| {
+            counts
+                .into_iter()
+                .map(|((kind, severity), count)| FindingCount {
+                    kind: kind.to_string(),
+                    severity: severity.to_string(),
+                    count,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut kind_counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+        let mut crates = Vec::new();
+        for (crate_name, counts) in crate_counts {
+            for (kind, count) in counts.iter() {
+                *kind_counts.entry(*kind).or_insert(0) += count;
+            }
+            crates.push(CrateFindings {
+                crate_name: crate_name.to_string(),
+                count: counts.values().sum(),
+                findings: finding_counts(counts),
+            });
+        }
+        FindingTotals {
+            count: crates.iter().map(|krate| krate.count).sum(),
+            crates,
+            kinds: finding_counts(kind_counts),
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut out = "Findings by crate:\n".to_string();
+        for krate in self.crates.iter() {
+            let findings = krate
+                .findings
+                .iter()
+                .filter(|finding| finding.count > 0)
+                .map(|finding| format!("{}: {}", finding.kind, finding.count))
+                .collect::<Vec<_>>();
+            if findings.is_empty() {
+                out += &format!("    {}: 0\n", krate.crate_name);
+            } else {
+                out += &format!(
+                    "    {}: {} ({})\n",
+                    krate.crate_name,
+                    krate.count,
+                    findings.join(", ")
+                );
+            }
+        }
+        out += "Findings by kind:\n";
+        for finding in self.kinds.iter() {
+            out += &format!(
+                "    {} ({}): {}\n",
+                finding.kind, finding.severity, finding.count
+            );
+        }
+        out += &format!(
+            "Total: {} finding(s) in {} crate(s)\n",
+            self.count,
+            self.crates.len()
+        );
+        out
+    }
+}
+
+// A crate compiled by `cargo check`, fresh or not, from its `compiler-artifact` message.
+#[derive(Debug)]
+struct CompiledCrate {
+    package_id: String,
+    crate_name: String,
+    // the name of its artifact without the `lib` prefix, also the name of its cache file
+    cache_file_name: String,
+}
+
+// The crates the driver checks, matched as with the white and black lists.
+// An invalid pattern fails the run, as it fails the driver.
+struct CheckedCrates {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl CheckedCrates {
+    fn new(include: &[String], exclude: &[String]) -> Self {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    glob::Pattern::new(&pattern.replace('-', "_")).unwrap_or_else(|e| {
+                        show_error(format!("invalid crate pattern `{}`: {}", pattern, e))
+                    })
+                })
+                .collect()
+        };
+        CheckedCrates {
+            include: patterns(include),
+            exclude: patterns(exclude),
+        }
+    }
+
+    fn contains(&self, crate_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(crate_name)))
+            && !self.exclude.iter().any(|p| p.matches(crate_name))
+    }
+}
+
+// `--deny` and `--fail-on`: the findings making `cargo lock-bug-detect` exit with 1.
+#[derive(Debug, Default)]
+struct FailureThreshold {
+    deny: Vec<String>,
+    fail_on: Option<String>,
+}
+
+impl FailureThreshold {
+    fn fails(&self, finding: &FindingCount) -> bool {
+        // `SEVERITIES` is ordered from the highest severity
+        let rank = |severity: &str| SEVERITIES.iter().position(|s| *s == severity);
+        self.deny.contains(&finding.kind)
+            || matches!(&self.fail_on, Some(fail_on) if rank(&finding.severity) <= rank(fail_on))
+    }
+
+    // The findings of the run failing it, of each bug kind.
+    fn failing<'a>(&self, totals: &'a FindingTotals) -> Vec<&'a FindingCount> {
+        totals
+            .kinds
+            .iter()
+            .filter(|finding| finding.count > 0 && self.fails(finding))
+            .collect()
+    }
 }
 
 fn show_help() {
@@ -47,48 +390,875 @@ fn has_arg_flag(name: &str) -> bool {
     args.any(|val| val == name)
 }
 
+// Returns the value of `name` if `arg` is `name value` or `name=value`,
+// or `namevalue` for a short option such as `-p`, as cargo reads them.
+fn arg_value(name: &str, arg: &str, args: &mut impl Iterator<Item = String>) -> Option<String> {
+    if arg == name {
+        match args.next() {
+            Some(value) => Some(value),
+            None => show_error(format!("`{}` requires a value", name)),
+        }
+    } else if arg.starts_with(name) && arg[name.len()..].starts_with('=') {
+        Some(arg[name.len() + 1..].to_string())
+    } else if !name.starts_with("--") && arg.starts_with(name) {
+        Some(arg[name.len()..].to_string())
+    } else {
+        None
+    }
+}
+
+// `cargo metadata` of the current package or workspace.
+fn cargo_metadata(manifest_path: Option<&Path>) -> Metadata {
+    let mut cmd = MetadataCommand::new();
+    cmd.no_deps();
+    if let Some(path) = manifest_path {
+        cmd.manifest_path(path);
+    }
+    cmd.exec()
+        .unwrap_or_else(|e| show_error(format!("cargo metadata failed: {}", e)))
+}
+
+// The target dir `cargo check` builds in: `--target-dir`, else `CARGO_TARGET_DIR`,
+// else the one of the cargo config or the default one, as `cargo metadata` finds it.
+fn target_dir(target_dir_arg: Option<&Path>, metadata: &Metadata, current_dir: &Path) -> PathBuf {
+    target_dir_arg
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os("CARGO_TARGET_DIR").map(PathBuf::from))
+        .map(|dir| current_dir.join(dir))
+        .unwrap_or_else(|| metadata.target_directory.clone())
+}
+
+// The crates rustc compiles for the targets of `package`, except its build script.
+fn package_crate_names(package: &Package) -> Vec<String> {
+    let mut crate_names: Vec<String> = Vec::new();
+    for target in package.targets.iter() {
+        if target.kind.iter().any(|kind| kind == "custom-build") {
+            continue;
+        }
+        let crate_name = target.name.replace('-', "_");
+        if !crate_names.contains(&crate_name) {
+            crate_names.push(crate_name);
+        }
+    }
+    crate_names
+}
+
+// The packages selected with `-p/--package`, `--workspace` and `--exclude`, as with `cargo check`.
+#[derive(Debug, Default)]
+struct PackageSelection {
+    packages: Vec<String>,
+    workspace: bool,
+    exclude: Vec<String>,
+}
+
+impl PackageSelection {
+    // The crates of the packages selected, `None` if none is.
+    // A package spec naming no workspace member is a dependency, whose lib is checked.
+    fn crate_names(&self, metadata: &Metadata) -> Option<Vec<String>> {
+        if !self.exclude.is_empty() && !self.workspace {
+            show_error("`--exclude` can only be used together with `--workspace`".to_string());
+        }
+        let members = workspace_members(metadata);
+        if self.workspace {
+            let excluded = |package: &Package| {
+                self.exclude.iter().any(|spec| {
+                    matches!(
+                        glob::Pattern::new(spec_name(spec)),
+                        Ok(pattern) if pattern.matches(&package.name)
+                    )
+                })
+            };
+            return Some(
+                members
+                    .into_iter()
+                    .filter(|package| !excluded(package))
+                    .flat_map(package_crate_names)
+                    .collect(),
+            );
+        }
+        if self.packages.is_empty() {
+            return None;
+        }
+        let mut crate_names = Vec::new();
+        for spec in self.packages.iter() {
+            let name = spec_name(spec);
+            match members.iter().find(|package| package.name == name) {
+                Some(package) => crate_names.extend(package_crate_names(package)),
+                None => crate_names.push(name.replace('-', "_")),
+            }
+        }
+        Some(crate_names)
+    }
+}
+
+fn workspace_members(metadata: &Metadata) -> Vec<&Package> {
+    metadata
+        .packages
+        .iter()
+        .filter(|package| metadata.workspace_members.contains(&package.id))
+        .collect()
+}
+
+// The package name of a package spec, `name`, `name:version` or `name@version`,
+// or a URL such as `https://github.com/rust-lang/crates.io-index#regex@1.4.3`,
+// whose fragment is one of those or a version alone, the name being then the last segment of the path.
+fn spec_name(spec: &str) -> &str {
+    fn name(spec: &str) -> &str {
+        spec.split(&[':', '@'][..]).next().unwrap_or(spec)
+    }
+    if !spec.contains("://") {
+        return name(spec);
+    }
+    let mut parts = spec.splitn(2, '#');
+    let url = parts.next().unwrap_or(spec);
+    match parts.next() {
+        // package names do not start with a digit
+        Some(fragment) if !fragment.starts_with(|c: char| c.is_ascii_digit()) => name(fragment),
+        _ => url.trim_end_matches('/').rsplit('/').next().unwrap_or(url),
+    }
+}
+
+fn load_config_file(path: &Path) -> ConfigFile {
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| show_error(format!("could not read {}: {}", path.display(), e)));
+    let config: ConfigFile = toml::from_str(&content)
+        .unwrap_or_else(|e| show_error(format!("invalid {}: {}", path.display(), e)));
+    let invalid = |msg: String| -> ! { show_error(format!("invalid {}: {}", path.display(), msg)) };
+    for detector in config.detectors.iter().flatten() {
+        if !DETECTORS.contains(&detector.as_str()) {
+            invalid(format!("unknown detector `{}`", detector));
+        }
+    }
+    for pattern in config.crates.include.iter().chain(config.crates.exclude.iter().flatten()) {
+        if let Err(e) = glob::Pattern::new(pattern) {
+            invalid(format!("invalid crate pattern `{}`: {}", pattern, e));
+        }
+    }
+    for pattern in config.blocking_calls.functions.iter() {
+        if pattern.contains(',') {
+            invalid(format!("blocking function pattern `{}` must not contain commas", pattern));
+        }
+        if let Err(e) = glob::Pattern::new(pattern) {
+            invalid(format!("invalid blocking function pattern `{}`: {}", pattern, e));
+        }
+    }
+    if let Some(assumption) = &config.unknown_guard_move {
+        if !GUARD_MOVES.contains(&assumption.as_str()) {
+            invalid(format!("unknown guard move `{}`, expected release or hold", assumption));
+        }
+    }
+    for (kind, severity) in config.severity.iter() {
+        if !DETECTORS.contains(&kind.as_str()) {
+            invalid(format!("unknown bug kind `{}` in [severity]", kind));
+        }
+        if !SEVERITIES.contains(&severity.as_str()) {
+            invalid(format!("unknown severity `{}`, expected error, warning or note", severity));
+        }
+    }
+    if let Some(format) = &config.output.format {
+        if !OUTPUT_FORMATS.contains(&format.as_str()) {
+            invalid(format!("unknown output format `{}`", format));
+        }
+    }
+    for guard_type in config.guard_types.iter() {
+        if !GUARD_KINDS.contains(&guard_type.kind.as_str()) {
+            invalid(format!(
+                "unknown kind `{}` of guard type `{}`, expected exclusive, shared or upgradable",
+                guard_type.kind, guard_type.path
+            ));
+        }
+    }
+    config
+}
+
+fn detector_type(detector: &str) -> &'static str {
+    match detector {
+        "double-lock" => "DoubleLockDetector",
+        "conflict-lock" => "ConflictLockDetector",
+        "await-lock" => "AwaitLockDetector",
+        "condvar" => "CondvarDetector",
+        "double-borrow" => "DoubleBorrowDetector",
+        "blocking-call" => "BlockingCallDetector",
+        _ => unreachable!(),
+    }
+}
+
+// Each crate renders its own SARIF log. Merge them into a single log with one run
+// holding the results of all the crates, and the totals of the run in its properties.
+// The relative paths of the results are relative to the workspace root, where cargo runs rustc.
+fn merge_sarif_logs(
+    logs: &[String],
+    totals: &FindingTotals,
+    workspace_root: &Path,
+) -> Option<serde_json::Value> {
+    let mut merged: Option<serde_json::Value> = None;
+    let mut suppressed = 0;
+    for log in logs {
+        let log: serde_json::Value = serde_json::from_str(log)
+            .unwrap_or_else(|e| show_error(format!("invalid cached SARIF log: {}", e)));
+        let results = log["runs"][0]["results"].as_array().cloned().unwrap_or_default();
+        suppressed += log["runs"][0]["properties"]["suppressed"].as_u64().unwrap_or(0);
+        match merged {
+            None => merged = Some(log),
+            Some(ref mut merged) => {
+                if let Some(merged_results) = merged["runs"][0]["results"].as_array_mut() {
+                    merged_results.extend(results);
+                }
+            }
+        }
+    }
+    let mut merged = merged?;
+    // the results come from many crates now
+    if let Some(run) = merged["runs"][0].as_object_mut() {
+        run.insert(
+            "properties".to_string(),
+            serde_json::json!({ "suppressed": suppressed, "totals": totals }),
+        );
+        // a base URI ends with a slash
+        let workspace_root = workspace_root.to_string_lossy();
+        let src_root = if workspace_root.ends_with('/') {
+            format!("file://{}", workspace_root)
+        } else {
+            format!("file://{}/", workspace_root)
+        };
+        run.insert(
+            "originalUriBaseIds".to_string(),
+            serde_json::json!({ "%SRCROOT%": { "uri": src_root } }),
+        );
+    }
+    Some(merged)
+}
+
+// A cached crate report in the output format, without the findings in the baseline.
+fn render_report(
+    report: &CachedReport,
+    format: &str,
+    in_baseline: &dyn Fn(&str) -> bool,
+) -> String {
+    // the fingerprint of a JSON finding, or of a SARIF result
+    let not_baselined = |finding: &serde_json::Value| match finding["fingerprint"]
+        .as_str()
+        .or_else(|| finding["partialFingerprints"]["lockBugDetect/v1"].as_str())
+    {
+        Some(fingerprint) => !in_baseline(fingerprint),
+        None => true,
+    };
+    match format {
+        "text" => {
+            let mut text = report.text.clone();
+            text.apply_baseline(in_baseline);
+            text.render()
+        }
+        "json" => {
+            let mut report: serde_json::Value = serde_json::from_str(&report.json)
+                .unwrap_or_else(|e| show_error(format!("invalid cached JSON report: {}", e)));
+            if let Some(fields) = report.as_object_mut() {
+                for findings in fields.values_mut().filter_map(|field| field.as_array_mut()) {
+                    findings.retain(not_baselined);
+                }
+            }
+            serde_json::to_string(&report).unwrap()
+        }
+        _ => {
+            let mut log: serde_json::Value = serde_json::from_str(&report.sarif)
+                .unwrap_or_else(|e| show_error(format!("invalid cached SARIF log: {}", e)));
+            if let Some(results) = log["runs"][0]["results"].as_array_mut() {
+                results.retain(not_baselined);
+            }
+            serde_json::to_string(&log).unwrap()
+        }
+    }
+}
+
+// The report of a run in the output format: the reports of each crate in the order of the crate names,
+// not the order cargo happens to compile them in, then the totals.
+// The reports of a crate compiled more than once are only given once.
+fn merged_report(
+    results: &[CachedResults],
+    totals: &FindingTotals,
+    format: &str,
+    in_baseline: &dyn Fn(&str) -> bool,
+    workspace_root: &Path,
+) -> String {
+    let mut crates: BTreeMap<&str, Vec<&CachedResults>> = BTreeMap::new();
+    for crate_results in results {
+        crates
+            .entry(crate_results.crate_name.as_str())
+            .or_default()
+            .push(crate_results);
+    }
+    let mut out = String::new();
+    let mut sarif_logs = Vec::new();
+    for (crate_name, compilations) in crates {
+        let mut reports: Vec<String> = Vec::new();
+        for report in compilations
+            .iter()
+            .flat_map(|results| results.reports.iter())
+            .map(|report| render_report(report, format, in_baseline))
+        {
+            // an empty text report has no findings to print
+            if !report.is_empty() && !reports.contains(&report) {
+                reports.push(report);
+            }
+        }
+        if format == "sarif" {
+            sarif_logs.extend(reports);
+            continue;
+        }
+        if format == "text" {
+            out += &format!("{}\n", crate_name);
+            let stats = compilations
+                .iter()
+                .filter_map(|results| results.stats.as_ref())
+                .max_by_key(|stats| stats.fns);
+            if let Some(stats) = stats {
+                out += &format!(
+                    "fn with locks: {}, lockguards num: {}, local fn num: {}\n",
+                    stats.fns_with_locks, stats.lockguards, stats.fns
+                );
+            }
+        }
+        for report in reports {
+            out += &report;
+            if !report.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+    }
+    match format {
+        "text" => out += &totals.text(),
+        "json" => {
+            out += &serde_json::to_string(&serde_json::json!({ "totals": totals })).unwrap();
+            out.push('\n');
+        }
+        _ => {
+            if let Some(merged) = merge_sarif_logs(&sarif_logs, totals, workspace_root) {
+                out += &serde_json::to_string_pretty(&merged).unwrap();
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+// Run `cargo check` with JSON messages and return the crates it compiled, exiting if it fails.
+// The lines of stdout that are not cargo messages are printed.
+fn run_cargo_check(cmd: &mut Command) -> Vec<CompiledCrate> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("could not run cargo");
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut crates = Vec::new();
+    for line in stdout.lines() {
+        let line = line.expect("could not read the output of cargo");
+        match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(message) if message["reason"].is_string() => {
+                if message["reason"] == "compiler-artifact" {
+                    crates.extend(compiled_crate(&message));
+                }
+            }
+            _ => println!("{}", line),
+        }
+    }
+    let exit_status = child.wait().expect("failed to wait for cargo?");
+    if !exit_status.success() {
+        std::process::exit(exit_status.code().unwrap_or(-1))
+    };
+    crates
+}
+
+fn compiled_crate(message: &serde_json::Value) -> Option<CompiledCrate> {
+    // `lib<crate name><extra filename>.rmeta`, or `.so` and the like for a proc macro
+    let artifact = Path::new(message["filenames"].get(0)?.as_str()?);
+    let stem = artifact.file_stem()?.to_str()?;
+    let cache_file_name = if stem.starts_with("lib") {
+        stem.get("lib".len()..)?
+    } else {
+        stem
+    };
+    Some(CompiledCrate {
+        package_id: message["package_id"].as_str()?.to_string(),
+        crate_name: message["target"]["name"].as_str()?.replace('-', "_"),
+        cache_file_name: cache_file_name.to_string(),
+    })
+}
+
+// The results cached for `krate` with the config of `key`, `None` if they are missing or stale.
+fn cached_results(cache_dir: &Path, krate: &CompiledCrate, key: &str) -> Option<CachedResults> {
+    let path = cache_dir.join(format!("{}.json", krate.cache_file_name));
+    let content = std::fs::read_to_string(path).ok()?;
+    // results of an older driver do not parse
+    let results: CachedResults = serde_json::from_str(&content).ok()?;
+    Some(results).filter(|results| results.key == key)
+}
+
+// The package id spec of the package id of a cargo message, for `cargo clean -p`.
+fn package_spec(package_id: &str) -> String {
+    match package_id.split_whitespace().collect::<Vec<_>>().as_slice() {
+        // `<name> <version> (<source>)`, the format of older cargo versions
+        [name, version, _] => format!("{}:{}", name, version),
+        // a package id spec already
+        _ => package_id.to_string(),
+    }
+}
+
+// A hash of the config the driver runs with, the env vars passed to it,
+// to tell the results cached with another config.
+// The output format and the baseline are not part of it: the driver caches the reports in every format
+// with the findings of the baseline, which are filtered here.
+fn config_key(driver_env: &BTreeMap<&str, String>) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    driver_env.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// Exit with 1 if the findings of the run fail it, printing them.
+fn check_findings(totals: &FindingTotals, threshold: &FailureThreshold) {
+    let failing = threshold.failing(totals);
+    if failing.is_empty() {
+        return;
+    }
+    for finding in failing {
+        eprintln!(
+            "error: {} {} finding(s) of severity {}",
+            finding.count, finding.kind, finding.severity
+        );
+    }
+    std::process::exit(1)
+}
+
+fn load_baseline(path: &Path) -> BaselineFile {
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| show_error(format!("could not read {}: {}", path.display(), e)));
+    let baseline: BaselineFile = serde_json::from_str(&content)
+        .unwrap_or_else(|e| show_error(format!("invalid baseline {}: {}", path.display(), e)));
+    if baseline.version != BASELINE_VERSION {
+        show_error(format!(
+            "baseline {} is of version {}, expected {}",
+            path.display(),
+            baseline.version,
+            BASELINE_VERSION
+        ));
+    }
+    baseline
+}
+
+// Print the baseline entries of the crates checked that no longer occur, so that the baseline can be refreshed.
+fn report_stale_baseline(baseline: &BaselineFile, digests: &[&CrateFindingDigest]) {
+    let checked = digests
+        .iter()
+        .map(|digest| digest.crate_name.as_str())
+        .collect::<BTreeSet<_>>();
+    let found = digests
+        .iter()
+        .flat_map(|digest| digest.baselined.iter().map(|fingerprint| fingerprint.as_str()))
+        .collect::<BTreeSet<_>>();
+    let stale = baseline
+        .findings
+        .iter()
+        .filter(|entry| {
+            checked.contains(entry.crate_name.as_str())
+                && !found.contains(entry.fingerprint.as_str())
+        })
+        .collect::<Vec<_>>();
+    if stale.is_empty() {
+        return;
+    }
+    eprintln!(
+        "warning: {} baseline finding(s) no longer occur, refresh the baseline with --write-baseline:",
+        stale.len()
+    );
+    for entry in stale {
+        eprintln!(
+            "    {} in {} at {} ({})",
+            entry.kind, entry.crate_name, entry.location, entry.fingerprint
+        );
+    }
+}
+
+// Write the findings of the crates to the baseline at `path`: the new findings,
+// and the entries of the previous baseline that still occur.
+fn write_baseline(path: &Path, digests: &[&CrateFindingDigest], previous: Option<&BaselineFile>) {
+    // by fingerprint, a crate compiled more than once reports its findings more than once
+    let mut entries: BTreeMap<&str, BaselineEntry> = BTreeMap::new();
+    for digest in digests {
+        for finding in digest.fingerprints.iter() {
+            entries
+                .entry(finding.fingerprint.as_str())
+                .or_insert_with(|| BaselineEntry {
+                    fingerprint: finding.fingerprint.clone(),
+                    kind: finding.kind.clone(),
+                    crate_name: digest.crate_name.clone(),
+                    location: format!("{}:{}", finding.location.file, finding.location.start_line),
+                });
+        }
+    }
+    if let Some(previous) = previous {
+        let found = digests
+            .iter()
+            .flat_map(|digest| digest.baselined.iter().map(|fingerprint| fingerprint.as_str()))
+            .collect::<BTreeSet<_>>();
+        for entry in previous.findings.iter() {
+            if found.contains(entry.fingerprint.as_str()) {
+                entries
+                    .entry(entry.fingerprint.as_str())
+                    .or_insert_with(|| entry.clone());
+            }
+        }
+    }
+    let mut findings = entries.values().cloned().collect::<Vec<_>>();
+    findings.sort_by(|a, b| {
+        (&a.crate_name, &a.kind, &a.location, &a.fingerprint)
+            .cmp(&(&b.crate_name, &b.kind, &b.location, &b.fingerprint))
+    });
+    let baseline = BaselineFile {
+        version: BASELINE_VERSION,
+        findings,
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&baseline).unwrap() + "\n")
+        .unwrap_or_else(|e| show_error(format!("could not write {}: {}", path.display(), e)));
+    eprintln!(
+        "wrote {} finding(s) to the baseline {}",
+        baseline.findings.len(),
+        path.display()
+    );
+}
+
 fn in_cargo_lock_bug_detect() {
-    let (subcommand, skip) = match std::env::args().nth(2).as_deref() {
-        Some("double-lock") => (LockBugDetectCommand::DoubleLock, 3),
-        Some("conflict-lock") => (LockBugDetectCommand::ConflictLock, 3),
-        // Default double-lock
-        None => (LockBugDetectCommand::DoubleLock, 2),
-        // Invalid command.
-        Some(s) => show_error(format!("Unknown command `{}`", s)),
+    let (subcommands, skip) = match std::env::args().nth(2).as_deref() {
+        // The detectors of the config file
+        None => (None, 2),
+        Some(s) if s.starts_with('-') => (None, 2),
+        // One subcommand or several separated by commas
+        Some(s) => {
+            let subcommands = s
+                .split(',')
+                .map(|name| {
+                    LockBugDetectCommand::from_name(name)
+                        .unwrap_or_else(|| show_error(format!("Unknown command `{}`", name)))
+                })
+                .collect::<Vec<_>>();
+            (Some(subcommands), 3)
+        }
     };
     // Now we run `cargo check $FLAGS $ARGS`, giving the user the
     // change to add additional arguments. `FLAGS` is set to identify
     // this target.  The user gets to control what gets actually passed to lock-bug-detect.
     let mut cmd = cargo();
     cmd.arg("check");
-    match subcommand {
-        LockBugDetectCommand::DoubleLock => {
-            cmd.env("RUST_LOCK_DETECTOR_TYPE", "DoubleLockDetector")
-        }
-        LockBugDetectCommand::ConflictLock => {
-            cmd.env("RUST_LOCK_DETECTOR_TYPE", "ConflictLockDetector")
-        }
-    };
     cmd.env("RUSTC", "rust-lock-bug-detector");
     cmd.env("RUST_BACKTRACE", "full");
+    let current_dir = env::current_dir().expect("could not get current dir");
+    let mut config_path = None;
+    let mut output_format = None;
+    let mut output_path = None;
+    let mut manifest_path = None;
+    let mut target_dir_arg = None;
+    let mut selection = PackageSelection::default();
+    let mut threshold = FailureThreshold::default();
+    let mut baseline_path = None;
+    let mut write_baseline_path = None;
+    // the options of `cargo check` locating the artifacts to clean, for `cargo clean`
+    let mut clean_args = Vec::new();
     let mut args = std::env::args().skip(skip);
     while let Some(arg) = args.next() {
         if arg == "--" {
             break;
         }
+        if let Some(path) = arg_value("--config", &arg, &mut args) {
+            config_path = Some(current_dir.join(path));
+            continue;
+        }
+        if let Some(format) = arg_value("--output-format", &arg, &mut args) {
+            if !OUTPUT_FORMATS.contains(&format.as_str()) {
+                show_error(format!("Unknown output format `{}`", format));
+            }
+            output_format = Some(format);
+            continue;
+        }
+        // the crates compiled are read from the JSON messages of `cargo check`
+        if arg_value("--message-format", &arg, &mut args).is_some() {
+            show_error("`--message-format` is not supported, use `--output-format`".to_string());
+        }
+        if let Some(path) = arg_value("--output", &arg, &mut args) {
+            output_path = Some(current_dir.join(path));
+            continue;
+        }
+        // the package selection is passed on to `cargo check` too
+        if let Some(spec) =
+            arg_value("--package", &arg, &mut args).or_else(|| arg_value("-p", &arg, &mut args))
+        {
+            cmd.arg("--package").arg(&spec);
+            selection.packages.push(spec);
+            continue;
+        }
+        if let Some(spec) = arg_value("--exclude", &arg, &mut args) {
+            cmd.arg("--exclude").arg(&spec);
+            selection.exclude.push(spec);
+            continue;
+        }
+        if arg == "--workspace" || arg == "--all" {
+            selection.workspace = true;
+        }
+        if let Some(kinds) = arg_value("--deny", &arg, &mut args) {
+            for kind in kinds.split(',') {
+                if !DETECTORS.contains(&kind) {
+                    show_error(format!("Unknown bug kind `{}`", kind));
+                }
+                threshold.deny.push(kind.to_string());
+            }
+            continue;
+        }
+        if let Some(severity) = arg_value("--fail-on", &arg, &mut args) {
+            if !SEVERITIES.contains(&severity.as_str()) {
+                show_error(format!("Unknown severity `{}`", severity));
+            }
+            threshold.fail_on = Some(severity);
+            continue;
+        }
+        if let Some(path) = arg_value("--baseline", &arg, &mut args) {
+            baseline_path = Some(current_dir.join(path));
+            continue;
+        }
+        if let Some(path) = arg_value("--write-baseline", &arg, &mut args) {
+            write_baseline_path = Some(current_dir.join(path));
+            continue;
+        }
+        if let Some(path) = arg_value("--manifest-path", &arg, &mut args) {
+            cmd.arg("--manifest-path").arg(&path);
+            clean_args.push("--manifest-path".to_string());
+            clean_args.push(path.clone());
+            manifest_path = Some(current_dir.join(path));
+            continue;
+        }
+        if let Some((name, value)) = ["--target", "--target-dir", "--profile"]
+            .iter()
+            .find_map(|name| arg_value(name, &arg, &mut args).map(|value| (*name, value)))
+        {
+            cmd.arg(name).arg(&value);
+            if name == "--target-dir" {
+                target_dir_arg = Some(PathBuf::from(&value));
+            }
+            clean_args.push(name.to_string());
+            clean_args.push(value);
+            continue;
+        }
+        if arg == "--release" {
+            clean_args.push(arg.clone());
+        }
         cmd.arg(arg);
     }
-    cmd.env("RUST_LOCK_DETECTOR_BLACK_LISTS", "cc");
-    println!("{:?}", cmd);
-    let exit_status = cmd
-        .spawn()
-        .expect("could not run cargo")
-        .wait()
-        .expect("failed to wait for cargo?");
-
-    if !exit_status.success() {
-        std::process::exit(exit_status.code().unwrap_or(-1))
+    let metadata = cargo_metadata(manifest_path.as_deref());
+    let workspace_root = metadata.workspace_root.clone();
+    let config_path = config_path.or_else(|| {
+        let path = workspace_root.join(CONFIG_FILE);
+        if path.exists() {
+            Some(path)
+        } else {
+            None
+        }
+    });
+    let config = match config_path {
+        Some(path) => load_config_file(&path),
+        None => ConfigFile::default(),
     };
+    // the CLI overrides the config file
+    let detectors = match subcommands {
+        // every detector once, all run in the same `cargo check`
+        Some(subcommands) => {
+            let mut detectors: Vec<String> = Vec::new();
+            for detector in subcommands
+                .iter()
+                .flat_map(|subcommand| subcommand.detectors())
+            {
+                if !detectors.iter().any(|d| d == detector) {
+                    detectors.push(detector.to_string());
+                }
+            }
+            detectors
+        }
+        // Default double-lock
+        None => config
+            .detectors
+            .filter(|detectors| !detectors.is_empty())
+            .unwrap_or_else(|| vec!["double-lock".to_string()]),
+    };
+    if let Some(kind) = threshold
+        .deny
+        .iter()
+        .find(|kind| !detectors.contains(kind))
+    {
+        show_error(format!("`--deny {}` but the {} detector is not run", kind, kind));
+    }
+    // the config of the driver, all in the key of the results it caches
+    let mut driver_env = BTreeMap::new();
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_TYPE",
+        detectors
+            .iter()
+            .map(|detector| detector_type(detector))
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    // the crates selected on the command line, else the ones of the config file, else the workspace members
+    let config_include = config.crates.include;
+    let include = selection
+        .crate_names(&metadata)
+        .or_else(|| Some(config_include).filter(|include| !include.is_empty()))
+        .unwrap_or_else(|| {
+            workspace_members(&metadata)
+                .into_iter()
+                .flat_map(package_crate_names)
+                .collect()
+        });
+    driver_env.insert("RUST_LOCK_DETECTOR_WHITE_LISTS", include.join(","));
+    let exclude = config
+        .crates
+        .exclude
+        .unwrap_or_else(|| vec!["cc".to_string()]);
+    driver_env.insert("RUST_LOCK_DETECTOR_BLACK_LISTS", exclude.join(","));
+    if let Some(callchain_depth) = config.callchain_depth {
+        driver_env.insert(
+            "RUST_LOCK_DETECTOR_CALLCHAIN_DEPTH",
+            callchain_depth.to_string(),
+        );
+    }
+    if let Some(iteration_limit) = config.iteration_limit {
+        driver_env.insert("RUST_LOCK_DETECTOR_RUN_LIMIT", iteration_limit.to_string());
+    }
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_LINK_DYN_CALLS",
+        config.link_dyn_calls.to_string(),
+    );
+    if let Some(unknown_guard_move) = config.unknown_guard_move {
+        driver_env.insert("RUST_LOCK_DETECTOR_UNKNOWN_GUARD_MOVE", unknown_guard_move);
+    }
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_SEVERITY",
+        config
+            .severity
+            .iter()
+            .map(|(kind, severity)| format!("{}={}", kind, severity))
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_GUARD_TYPES",
+        serde_json::to_string(&config.guard_types).unwrap(),
+    );
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_BLOCKING_FUNCTIONS",
+        config.blocking_calls.functions.join(","),
+    );
+    driver_env.insert(
+        "RUST_LOCK_DETECTOR_BLOCKING_BUILTIN",
+        config.blocking_calls.builtin.to_string(),
+    );
+    let format = output_format
+        .or(config.output.format)
+        .unwrap_or_else(|| "text".to_string());
+    let config_output_path = config.output.path;
+    let output_path =
+        output_path.or_else(|| config_output_path.map(|path| workspace_root.join(path)));
+    let lock_bug_detect_dir =
+        target_dir(target_dir_arg.as_deref(), &metadata, &current_dir).join("lock-bug-detect");
+    // the crates checked are compiled in dependency order and leave the lock summaries of their fns
+    // for the checked crates depending on them
+    cmd.env(
+        "RUST_LOCK_DETECTOR_SUMMARY_DIR",
+        lock_bug_detect_dir.join("summaries"),
+    );
+    // the output format and the baseline are applied to the cached results, not by the driver
+    cmd.env_remove("RUST_LOCK_DETECTOR_OUTPUT_FORMAT");
+    cmd.env_remove("RUST_LOCK_DETECTOR_BASELINE");
+    let baseline = baseline_path.as_deref().map(load_baseline);
+    let baseline_fingerprints = baseline
+        .iter()
+        .flat_map(|baseline| baseline.findings.iter())
+        .map(|entry| entry.fingerprint.clone())
+        .collect::<BTreeSet<_>>();
+    let in_baseline = |fingerprint: &str| baseline_fingerprints.contains(fingerprint);
+    // the crates checked cache their results, cargo does not run the driver again on the fresh ones
+    let cache_dir = lock_bug_detect_dir.join("cache");
+    let cache_key = config_key(&driver_env);
+    cmd.env("RUST_LOCK_DETECTOR_CACHE_DIR", &cache_dir);
+    cmd.env("RUST_LOCK_DETECTOR_CACHE_KEY", &cache_key);
+    cmd.envs(&driver_env);
+    cmd.arg("--message-format=json-render-diagnostics");
+    let checked_crates = CheckedCrates::new(&include, &exclude);
+    let stale_crates = |crates: &[CompiledCrate]| {
+        crates
+            .iter()
+            .filter(|krate| checked_crates.contains(&krate.crate_name))
+            .filter(|krate| cached_results(&cache_dir, krate, &cache_key).is_none())
+            .map(|krate| package_spec(&krate.package_id))
+            .collect::<BTreeSet<_>>()
+    };
+    let mut crates = run_cargo_check(&mut cmd);
+    // fresh crates checked with another config, or before the cache, are cleaned to be checked again
+    let stale = stale_crates(&crates);
+    if !stale.is_empty() {
+        let mut clean = cargo();
+        clean.arg("clean").args(&clean_args);
+        for spec in stale.iter() {
+            clean.arg("--package").arg(spec);
+        }
+        let exit_status = clean.status().expect("could not run cargo clean");
+        if !exit_status.success() {
+            std::process::exit(exit_status.code().unwrap_or(-1))
+        };
+        crates = run_cargo_check(&mut cmd);
+    }
+    let mut results = Vec::new();
+    let mut seen = BTreeSet::new();
+    for krate in crates
+        .iter()
+        .filter(|krate| checked_crates.contains(&krate.crate_name))
+    {
+        if !seen.insert(krate.cache_file_name.as_str()) {
+            continue;
+        }
+        match cached_results(&cache_dir, krate, &cache_key) {
+            Some(mut crate_results) => {
+                crate_results.digest.apply_baseline(in_baseline);
+                results.push(crate_results);
+            }
+            None => eprintln!(
+                "warning: no lock bug results for crate {} of {}",
+                krate.crate_name, krate.package_id
+            ),
+        }
+    }
+    let digests = results
+        .iter()
+        .map(|crate_results| &crate_results.digest)
+        .collect::<Vec<_>>();
+    let totals = FindingTotals::new(&digests);
+    let report = merged_report(&results, &totals, &format, &in_baseline, &workspace_root);
+    match &output_path {
+        Some(path) => {
+            std::fs::write(path, &report).unwrap_or_else(|e| {
+                show_error(format!("could not write {}: {}", path.display(), e))
+            });
+            eprintln!(
+                "wrote {} finding(s) of {} crate(s) to {}",
+                totals.count,
+                totals.crates.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", report),
+    }
+    if let Some(path) = &write_baseline_path {
+        write_baseline(path, &digests, baseline.as_ref());
+    }
+    // a baseline written anew leaves out the entries no longer occurring
+    if let (Some(baseline), None) = (&baseline, &write_baseline_path) {
+        report_stale_baseline(baseline, &digests);
+    }
+    check_findings(&totals, &threshold);
 }
 
 fn main() {
@@ -104,3 +1274,287 @@ fn main() {
         in_cargo_lock_bug_detect();
     }
 }
+
+#[test]
+fn test_checked_crates() {
+    let all = CheckedCrates::new(&[], &[]);
+    assert!(all.contains("foo"));
+    let checked = CheckedCrates::new(
+        &["my-app".to_string(), "core_*".to_string()],
+        &["core_tests".to_string()],
+    );
+    assert!(checked.contains("my_app"));
+    assert!(checked.contains("core_sync"));
+    assert!(!checked.contains("core_tests"));
+    assert!(!checked.contains("serde"));
+}
+
+#[cfg(test)]
+fn test_metadata() -> Metadata {
+    let package = |name: &str, targets: &[(&str, &str)]| {
+        serde_json::json!({
+            "name": name,
+            "version": "0.1.0",
+            "id": format!("{} 0.1.0 (path+file:///ws/{})", name, name),
+            "dependencies": [],
+            "targets": targets
+                .iter()
+                .map(|(name, kind)| serde_json::json!({
+                    "name": name,
+                    "kind": [kind],
+                    "src_path": "/ws/src/lib.rs",
+                }))
+                .collect::<Vec<_>>(),
+            "features": {},
+            "manifest_path": format!("/ws/{}/Cargo.toml", name),
+        })
+    };
+    serde_json::from_value(serde_json::json!({
+        "packages": [
+            package("my-app", &[("my-app", "bin"), ("build-script-build", "custom-build")]),
+            package("my-lib", &[("my-lib", "lib"), ("bench-locks", "bench")]),
+            package("my-tests", &[("my-tests", "lib")]),
+        ],
+        "workspace_members": [
+            "my-app 0.1.0 (path+file:///ws/my-app)",
+            "my-lib 0.1.0 (path+file:///ws/my-lib)",
+            "my-tests 0.1.0 (path+file:///ws/my-tests)",
+        ],
+        "resolve": null,
+        "workspace_root": "/ws",
+        "target_directory": "/ws/target",
+        "version": 1,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_package_selection() {
+    let metadata = test_metadata();
+    assert_eq!(PackageSelection::default().crate_names(&metadata), None);
+    let packages = PackageSelection {
+        packages: vec![
+            "my-lib".to_string(),
+            "parking_lot@0.10.2".to_string(),
+            "path+file:///ws/my-app#0.1.0".to_string(),
+            "https://github.com/rust-lang/crates.io-index#lock_api@0.3.4".to_string(),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        packages.crate_names(&metadata),
+        Some(vec![
+            "my_lib".to_string(),
+            "bench_locks".to_string(),
+            "parking_lot".to_string(),
+            "my_app".to_string(),
+            "lock_api".to_string(),
+        ])
+    );
+    let foo = Some("foo".to_string());
+    let mut args = vec!["foo".to_string()].into_iter();
+    assert_eq!(arg_value("-p", "-p", &mut args), foo);
+    assert_eq!(arg_value("-p", "-pfoo", &mut args), foo);
+    assert_eq!(arg_value("-p", "-p=foo", &mut args), foo);
+    assert_eq!(arg_value("--package", "--packages", &mut args), None);
+    let workspace = PackageSelection {
+        workspace: true,
+        exclude: vec!["my-t*".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        workspace.crate_names(&metadata),
+        Some(vec![
+            "my_app".to_string(),
+            "my_lib".to_string(),
+            "bench_locks".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn test_failure_threshold() {
+    let finding = |kind: &str, severity: &str, count: usize| FindingCount {
+        kind: kind.to_string(),
+        severity: severity.to_string(),
+        count,
+    };
+    let totals = FindingTotals {
+        crates: Vec::new(),
+        kinds: vec![
+            finding("double-lock", "error", 0),
+            finding("conflict-lock", "warning", 2),
+            finding("blocking-call", "note", 1),
+        ],
+        count: 3,
+    };
+    let failing = |threshold: &FailureThreshold| {
+        threshold
+            .failing(&totals)
+            .iter()
+            .map(|finding| finding.kind.as_str())
+            .collect::<Vec<_>>()
+    };
+    // without `--deny` and `--fail-on` no finding fails the run
+    assert!(failing(&FailureThreshold::default()).is_empty());
+    let fail_on = |severity: &str| FailureThreshold {
+        fail_on: Some(severity.to_string()),
+        ..Default::default()
+    };
+    // a kind with no finding does not fail the run
+    assert!(failing(&fail_on("error")).is_empty());
+    assert_eq!(failing(&fail_on("warning")), vec!["conflict-lock"]);
+    assert_eq!(
+        failing(&fail_on("note")),
+        vec!["conflict-lock", "blocking-call"]
+    );
+    let deny = FailureThreshold {
+        deny: vec!["blocking-call".to_string(), "double-lock".to_string()],
+        fail_on: Some("error".to_string()),
+    };
+    assert_eq!(failing(&deny), vec!["blocking-call"]);
+}
+
+#[test]
+fn test_baseline_round_trip() {
+    use results::{FindingFingerprint, SpanInfo};
+    let fingerprint = |kind: &str, fingerprint: &str, line: usize| FindingFingerprint {
+        kind: kind.to_string(),
+        fingerprint: fingerprint.to_string(),
+        location: SpanInfo {
+            file: "src/lib.rs".to_string(),
+            start_line: line,
+            start_col: 9,
+            end_line: line,
+            end_col: 20,
+        },
+    };
+    let digest = CrateFindingDigest {
+        crate_name: "my_crate".to_string(),
+        findings: Vec::new(),
+        fingerprints: vec![
+            fingerprint("double-lock", "00000000000000aa", 12),
+            fingerprint("conflict-lock", "00000000000000bb", 30),
+        ],
+        baselined: Vec::new(),
+    };
+    let path = env::temp_dir().join(format!(
+        "lock-bug-detect-baseline-{}.json",
+        std::process::id()
+    ));
+    // a crate compiled as a lib and as a test reports its findings twice
+    write_baseline(&path, &[&digest, &digest], None);
+    let baseline = load_baseline(&path);
+    let entries = |baseline: &BaselineFile| {
+        baseline
+            .findings
+            .iter()
+            .map(|entry| format!("{} {} {}", entry.kind, entry.fingerprint, entry.location))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        entries(&baseline),
+        vec![
+            "conflict-lock 00000000000000bb src/lib.rs:30",
+            "double-lock 00000000000000aa src/lib.rs:12",
+        ]
+    );
+    // the next run drops the findings of the baseline, one of them no longer occurring,
+    // and finds a new one
+    let digest = CrateFindingDigest {
+        fingerprints: vec![fingerprint("double-lock", "00000000000000cc", 40)],
+        baselined: vec!["00000000000000aa".to_string()],
+        ..digest
+    };
+    write_baseline(&path, &[&digest], Some(&baseline));
+    let baseline = load_baseline(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(baseline.version, BASELINE_VERSION);
+    assert_eq!(
+        entries(&baseline),
+        vec![
+            "double-lock 00000000000000aa src/lib.rs:12",
+            "double-lock 00000000000000cc src/lib.rs:40",
+        ]
+    );
+}
+
+#[test]
+fn test_merged_report() {
+    use results::{AnalysisStats, TextReport};
+    let crate_results = |crate_name: &str, fingerprint: &str, fns: usize| {
+        let mut text = TextReport::new("double-locks", 0);
+        text.heading(0, "Must-alias double-locks:\n".to_string());
+        text.finding(fingerprint.to_string(), format!("{{ {} }}\n", fingerprint));
+        let finding = serde_json::json!({ "fingerprint": fingerprint });
+        let result =
+            serde_json::json!({ "partialFingerprints": { "lockBugDetect/v1": fingerprint } });
+        CachedResults {
+            key: "0123456789abcdef".to_string(),
+            crate_name: crate_name.to_string(),
+            stats: Some(AnalysisStats {
+                fns,
+                fns_with_locks: 1,
+                lockguards: 2,
+            }),
+            reports: vec![CachedReport {
+                text,
+                json: serde_json::json!({ "double_locks": [finding] }).to_string(),
+                sarif: serde_json::json!({
+                    "version": "2.1.0",
+                    "runs": [{ "results": [result], "properties": { "suppressed": 1 } }],
+                })
+                .to_string(),
+            }],
+            digest: CrateFindingDigest {
+                crate_name: crate_name.to_string(),
+                findings: vec![FindingCount {
+                    kind: "double-lock".to_string(),
+                    severity: "error".to_string(),
+                    count: 1,
+                }],
+                fingerprints: Vec::new(),
+                baselined: Vec::new(),
+            },
+        }
+    };
+    // `b` compiled as a lib and as a test, after `c`
+    let results = vec![
+        crate_results("c", "cc", 3),
+        crate_results("b", "bb", 4),
+        crate_results("b", "bb", 5),
+    ];
+    let digests = results
+        .iter()
+        .map(|results| &results.digest)
+        .collect::<Vec<_>>();
+    let totals = FindingTotals::new(&digests);
+    let none = |_: &str| false;
+    let workspace_root = Path::new("/ws");
+    assert_eq!(
+        merged_report(&results, &totals, "text", &none, workspace_root),
+        "b\nfn with locks: 1, lockguards num: 2, local fn num: 5\n\
+         Must-alias double-locks:\n{ bb }\n\
+         c\nfn with locks: 1, lockguards num: 2, local fn num: 3\n\
+         Must-alias double-locks:\n{ cc }\n"
+            .to_string()
+            + &totals.text()
+    );
+    let in_baseline = |fingerprint: &str| fingerprint == "cc";
+    let json = merged_report(&results, &totals, "json", &in_baseline, workspace_root);
+    assert_eq!(
+        json.lines().collect::<Vec<_>>()[..2],
+        [
+            r#"{"double_locks":[{"fingerprint":"bb"}]}"#,
+            r#"{"double_locks":[]}"#,
+        ]
+    );
+    let sarif = merged_report(&results, &totals, "sarif", &none, workspace_root);
+    let sarif: serde_json::Value = serde_json::from_str(&sarif).unwrap();
+    let run = &sarif["runs"][0];
+    assert_eq!(sarif["runs"].as_array().unwrap().len(), 1);
+    assert_eq!(run["results"].as_array().unwrap().len(), 2);
+    assert_eq!(run["properties"]["suppressed"], 2);
+    assert_eq!(run["properties"]["totals"]["count"], 2);
+    assert_eq!(run["originalUriBaseIds"]["%SRCROOT%"]["uri"], "file:///ws/");
+}
@@ -0,0 +1,82 @@
+//! Calls of blocking fns (joins, channel receives, sleeps, IO, ...) while a lockguard is held,
+//! in the fn holding it or in a local callee: every other thread waiting for the lock waits for the blocking call too.
+//! The lockguards live at each call come from the gen/kill of the double-lock detector,
+//! and are passed down the callgraph with `CrateContext::walk_callees`.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use super::config::{AnalysisLimits, BlockingFunctions, CrateFilter, LockDetectorConfig};
+use super::report::{BlockingCallBug, BlockingCallReports};
+use crate::crate_context::{callchain_spans, crate_findings, CrateContext};
+use crate::double_lock_checker::lock::{LockGuardId, LockGuardInfo};
+use crate::output::Findings;
+use crate::summary::resolve_callee;
+use rustc_hir::def_id::LOCAL_CRATE;
+use rustc_middle::ty::TyCtxt;
+use std::collections::HashMap;
+
+pub struct BlockingCallChecker {
+    crate_filter: CrateFilter,
+    limits: AnalysisLimits,
+    crate_lockguards: HashMap<LockGuardId, LockGuardInfo>,
+    crate_blocking_call_reports: BlockingCallReports,
+    blocking_functions: BlockingFunctions,
+}
+
+impl BlockingCallChecker {
+    pub fn new(config: &LockDetectorConfig) -> Self {
+        Self {
+            crate_filter: config.crate_filter.clone(),
+            limits: config.limits,
+            crate_lockguards: HashMap::new(),
+            crate_blocking_call_reports: BlockingCallReports::new(),
+            blocking_functions: config.blocking_functions.clone(),
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        tcx: TyCtxt,
+        crate_context: &CrateContext,
+    ) -> Option<Findings<BlockingCallBug>> {
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+        if !self.crate_filter.is_checked(&crate_name) {
+            return None;
+        }
+        let lockguards = &crate_context.lockguards;
+        for info in lockguards.values() {
+            self.crate_lockguards.extend(info.clone().into_iter());
+        }
+        let crate_lockguards = &self.crate_lockguards;
+        let blocking_functions = &self.blocking_functions;
+        let reports = &mut self.crate_blocking_call_reports;
+        for fn_id in lockguards.keys() {
+            // the double-locks are left to the double-lock detector
+            crate_context.walk_callees(
+                tcx,
+                *fn_id,
+                crate_lockguards,
+                self.limits,
+                |_, body, genkill, _, callchain| {
+                    let callchain = callchain_spans(tcx, callchain);
+                    for bb in body.basic_blocks().indices() {
+                        let callee = match resolve_callee(tcx, body, bb) {
+                            Some(callee) => tcx.def_path_str(callee),
+                            None => continue,
+                        };
+                        if !blocking_functions.is_blocking(&callee) {
+                            continue;
+                        }
+                        // the lockguards live at the call itself
+                        if let Some(live) = genkill.get_live_lockguards_after(&bb) {
+                            let call_span = body.basic_blocks()[bb].terminator().source_info.span;
+                            for id in live {
+                                reports.add(&crate_lockguards[id], &callee, call_span, &callchain);
+                            }
+                        }
+                    }
+                },
+            );
+        }
+        Some(crate_findings(tcx, &mut self.crate_blocking_call_reports))
+    }
+}
@@ -0,0 +1,5 @@
+mod checker;
+mod report;
+pub use self::checker::BlockingCallChecker;
+pub use self::report::BlockingCallBug;
+use super::config;
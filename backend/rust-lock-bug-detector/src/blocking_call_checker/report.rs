@@ -0,0 +1,228 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::baseline::Fingerprinter;
+use crate::config::Severities;
+use crate::crate_context::CrateReports;
+use crate::double_lock_checker::lock::{LockGuardInfo, LockGuardType};
+use crate::output::{CrateReport, Findings, LockSite, SpanInfo};
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct BlockingCallSite {
+    lock_type_name: (LockGuardType, String),
+    lock_span: Span,
+    callee: String,
+    call_span: Span,
+}
+
+/// One blocking call while a lockguard is held in the serialized report.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockingCallBug {
+    pub lock: LockSite,
+    pub callee: String,
+    pub call_span: SpanInfo,
+    /// the callsites from the fn holding the lock to the fn making the blocking call
+    pub callchains: Vec<Vec<SpanInfo>>,
+    /// identifies the blocking call in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct BlockingCallCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    blocking_calls: Vec<BlockingCallBug>,
+    suppressed: usize,
+}
+
+// BlockingCallSite, Callchains
+pub struct BlockingCallReports {
+    reports: HashMap<BlockingCallSite, HashSet<Vec<Span>>>,
+    // the number of reports suppressed in source
+    suppressed: usize,
+}
+
+impl BlockingCallReports {
+    pub fn new() -> Self {
+        Self {
+            reports: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// `lockguard` is live at the call of the blocking `callee` at `call_span`, reached through `callchain`.
+    pub fn add(
+        &mut self,
+        lockguard: &LockGuardInfo,
+        callee: &str,
+        call_span: Span,
+        callchain: &[Span],
+    ) {
+        self.reports
+            .entry(BlockingCallSite {
+                lock_type_name: lockguard.type_name.clone(),
+                lock_span: lockguard.span,
+                callee: callee.to_string(),
+                call_span,
+            })
+            .or_insert_with(HashSet::new)
+            .insert(callchain.to_vec());
+    }
+
+    /// A block for each blocking call with a lockguard held.
+    pub fn text(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> TextReport {
+        let mut sites = self.reports.iter().collect::<Vec<_>>();
+        sites.sort_by_key(|(site, _)| (site.call_span, site.lock_span));
+        let mut text = TextReport::new("blocking calls", self.suppressed);
+        for (site, chains) in sites {
+            let mut out = format!("BlockingCallReport: {}\n", site.callee);
+            out += &format!(
+                "\tLock: {:?}\n\t\t{:?}\n",
+                site.lock_type_name, site.lock_span
+            );
+            let mut sorted_chains = chains.iter().collect::<Vec<_>>();
+            sorted_chains.sort();
+            out += &format!(
+                "\tCall: {:?}\n\tCallchains: {:?}\n",
+                site.call_span, sorted_chains
+            );
+            text.finding(Self::bug(site, chains, tcx, fingerprinter).fingerprint, out);
+        }
+        text
+    }
+
+    fn bug(
+        site: &BlockingCallSite,
+        chains: &HashSet<Vec<Span>>,
+        tcx: TyCtxt,
+        fingerprinter: &Fingerprinter,
+    ) -> BlockingCallBug {
+        let source_map = tcx.sess.source_map();
+        let mut callchains = chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .iter()
+                    .map(|span| SpanInfo::new(*span, source_map))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        callchains.sort();
+        let mut bug = BlockingCallBug {
+            lock: LockSite {
+                lock_type: format!("{:?}", site.lock_type_name.0),
+                data_type: site.lock_type_name.1.clone(),
+                span: SpanInfo::new(site.lock_span, source_map),
+            },
+            callee: site.callee.clone(),
+            call_span: SpanInfo::new(site.call_span, source_map),
+            callchains,
+            fingerprint: String::new(),
+        };
+        bug.fingerprint = fingerprinter.fingerprint(
+            "blocking-call",
+            &[
+                fingerprinter.lock_site(&bug.lock),
+                bug.callee.clone(),
+                fingerprinter.position(&bug.call_span),
+            ],
+        );
+        bug
+    }
+
+    /// Resolve spans into serializable findings, sorted by location.
+    pub fn bugs(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<BlockingCallBug> {
+        let mut bugs = self
+            .reports
+            .iter()
+            .map(|(site, chains)| Self::bug(site, chains, tcx, fingerprinter))
+            .collect::<Vec<_>>();
+        bugs.sort_by(|a, b| (&a.call_span, &a.lock.span).cmp(&(&b.call_span, &b.lock.span)));
+        bugs
+    }
+}
+
+impl CrateReports for BlockingCallReports {
+    type Bug = BlockingCallBug;
+
+    /// Drop the reports whose lock or blocking call is suppressed in source, counting them.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        let before = self.reports.len();
+        self.reports.retain(|site, _| {
+            !suppressions.is_suppressed(BugKind::BlockingCall, site.lock_span)
+                && !suppressions.is_suppressed(BugKind::BlockingCall, site.call_span)
+        });
+        self.suppressed += before - self.reports.len();
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<BlockingCallBug> {
+        Findings {
+            bugs: self.bugs(tcx, fingerprinter),
+            suppressed: self.suppressed,
+            baselined: Vec::new(),
+            text: self.text(tcx, fingerprinter),
+        }
+    }
+}
+
+impl CrateReport for Findings<BlockingCallBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = BlockingCallCrateReport {
+            crate_name,
+            severity: severities.blocking_call.as_str(),
+            blocking_calls: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log, the blocking call being the primary location.
+    /// Each callchain becomes a code flow from the lock through the callsites to the blocking call.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let lock = format!("{}<{}>", bug.lock.lock_type, bug.lock.data_type);
+                let code_flows = bug
+                    .callchains
+                    .iter()
+                    .map(|chain| {
+                        let mut flow = vec![SarifLocation::new(
+                            bug.lock.span.clone(),
+                            format!("{} acquired", lock),
+                        )];
+                        flow.extend(chain.iter().map(|callsite| {
+                            SarifLocation::new(callsite.clone(), "call while holding the lock")
+                        }));
+                        flow.push(SarifLocation::new(
+                            bug.call_span.clone(),
+                            format!("{} blocks while holding the lock", bug.callee),
+                        ));
+                        flow
+                    })
+                    .collect();
+                SarifFinding {
+                    rule: SarifRule::BlockingCall,
+                    message: format!("Blocking call of {} while {} is held", bug.callee, lock),
+                    primary: SarifLocation::new(bug.call_span, format!("{} blocks", bug.callee)),
+                    related: vec![SarifLocation::new(
+                        bug.lock.span,
+                        format!("{} acquired", lock),
+                    )],
+                    code_flows,
+                    fingerprint: bug.fingerprint,
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
@@ -0,0 +1,90 @@
+//! The results of the crates checked, cached under `target/` for `cargo lock-bug-detect`.
+//!
+//! cargo does not run rustc again on the crates it considers fresh, so a run would miss their reports.
+//! Each compilation of a checked crate writes its results to `<cache dir>/<crate name><extra filename>.json`,
+//! the name of its `.rmeta` without the `lib` prefix, replacing the results of the previous compilation.
+//! The extra filename is the hash cargo computes from the compiler, the profile and the features,
+//! so `cargo lock-bug-detect` finds the results of every crate of a run, fresh or not, from its artifacts.
+//! Results computed with another config than the one of the run are stale, hence the config key.
+//! The output options are left out of the config: the reports are cached in every output format
+//! and with the findings of the baseline, which `cargo lock-bug-detect` drops itself.
+use crate::results::CachedResults;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub dir: String,
+    /// a hash of the config the results are computed with, from `cargo lock-bug-detect`
+    pub key: String,
+}
+
+/// The cache file name of the crate compiled with `rustc_args`, `None` unless cargo compiles a crate.
+pub fn cache_file_name(rustc_args: &[String]) -> Option<String> {
+    let mut crate_name = None;
+    let mut extra_filename = None;
+    let mut args = rustc_args.iter();
+    while let Some(arg) = args.next() {
+        let codegen_option = if arg == "--crate-name" {
+            crate_name = args.next();
+            continue;
+        } else if arg == "-C" {
+            args.next().map(String::as_str)
+        } else if arg.starts_with("-C") {
+            arg.get("-C".len()..)
+        } else {
+            None
+        };
+        let mut parts = codegen_option.unwrap_or_default().splitn(2, '=');
+        if parts.next() == Some("extra-filename") {
+            extra_filename = parts.next();
+        }
+    }
+    Some(format!("{}{}", crate_name?, extra_filename?))
+}
+
+/// Write `results` to `file_name` in the cache dir, through a temporary file
+/// so that an interrupted compilation leaves no partial results.
+pub fn write_results(cache: &CacheConfig, file_name: &str, results: &CachedResults) {
+    let dir = Path::new(&cache.dir);
+    fs::create_dir_all(dir)
+        .unwrap_or_else(|e| panic!("cannot create cache dir {}: {}", cache.dir, e));
+    let path = dir.join(format!("{}.json", file_name));
+    let tmp_path = dir.join(format!("{}.json.tmp", file_name));
+    fs::write(&tmp_path, serde_json::to_string(results).unwrap())
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .unwrap_or_else(|e| panic!("cannot write cached results {}: {}", path.display(), e));
+}
+
+#[test]
+fn test_cache_file_name() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        cache_file_name(&args(&[
+            "rust-lock-bug-detector",
+            "--crate-name",
+            "my_crate",
+            "--edition=2018",
+            "-C",
+            "metadata=0123456789abcdef",
+            "-C",
+            "extra-filename=-0123456789abcdef",
+        ])),
+        Some("my_crate-0123456789abcdef".to_string())
+    );
+    assert_eq!(
+        cache_file_name(&args(&[
+            "rustc",
+            "--crate-name",
+            "b",
+            "-Cextra-filename=-42"
+        ])),
+        Some("b-42".to_string())
+    );
+    // `rustc -vV` and the like, or a crate compiled outside cargo
+    assert_eq!(cache_file_name(&args(&["rustc", "-vV"])), None);
+    assert_eq!(
+        cache_file_name(&args(&["rustc", "--crate-name", "main", "main.rs"])),
+        None
+    );
+}
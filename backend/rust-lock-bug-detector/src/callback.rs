@@ -0,0 +1,234 @@
+//! Callbacks: local closures and fns passed as values to local higher-order fns.
+//! A callback is called inside the higher-order fn through its param, either as a fn pointer (`move _2(..)`)
+//! or through the `Fn*` traits (`<F as FnOnce>::call_once(move _2, ..)`), so the callgraph has no edge to it.
+//! The callbacks passed to each param are collected from the callsites of the fn, also through the params of callers
+//! passing their own params on, and every call of the param is linked to each of them.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use crate::summary::resolve_callee;
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::mir::{
+    BasicBlock, Body, Local, LocalKind, Operand, Rvalue, StatementKind, TerminatorKind,
+};
+use rustc_middle::ty::{Instance, ParamEnv, Ty, TyCtxt, TyKind, TypeFoldable};
+use std::collections::{HashMap, HashSet};
+
+/// A fn value as far as it is known inside one body.
+enum FnValue {
+    Fn(LocalDefId),
+    Param(Local),
+}
+
+/// The local fn or closure of a value of type `ty`, also behind a ref.
+fn fn_of_ty<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    crate_fn_ids: &[LocalDefId],
+) -> Option<LocalDefId> {
+    let fn_id = match ty.kind {
+        TyKind::Ref(_, ty, _) => return fn_of_ty(tcx, ty, crate_fn_ids),
+        TyKind::Closure(def_id, _) => def_id.as_local(),
+        TyKind::FnDef(def_id, substs) if !substs.has_param_types_or_consts() => {
+            match Instance::resolve(tcx, ParamEnv::reveal_all(), def_id, substs) {
+                Ok(Some(instance)) => instance.def.def_id().as_local(),
+                _ => def_id.as_local(),
+            }
+        }
+        TyKind::FnDef(def_id, _) => def_id.as_local(),
+        _ => None,
+    };
+    fn_id.filter(|fn_id| crate_fn_ids.contains(fn_id))
+}
+
+/// Whether a value of type `ty` may be called: a closure, a fn item or pointer,
+/// or a type param or trait object that may be one of them.
+fn may_be_fn(ty: Ty) -> bool {
+    match ty.kind {
+        TyKind::Ref(_, ty, _) => may_be_fn(ty),
+        TyKind::Closure(..)
+        | TyKind::FnDef(..)
+        | TyKind::FnPtr(_)
+        | TyKind::Param(_)
+        | TyKind::Dynamic(..) => true,
+        _ => false,
+    }
+}
+
+/// Trace `local` back through moves, copies, refs and fn pointer casts to the fn it holds or to a param.
+fn trace_fn_value<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    mut local: Local,
+    crate_fn_ids: &[LocalDefId],
+) -> Option<FnValue> {
+    let mut visited = Vec::new();
+    loop {
+        if body.local_kind(local) == LocalKind::Arg {
+            return Some(FnValue::Param(local));
+        }
+        if let Some(fn_id) = fn_of_ty(tcx, body.local_decls[local].ty, crate_fn_ids) {
+            return Some(FnValue::Fn(fn_id));
+        }
+        if visited.contains(&local) {
+            return None;
+        }
+        visited.push(local);
+        // MIR is not SSA, give up on locals with more than one def
+        let mut rvalues = body
+            .basic_blocks()
+            .iter()
+            .flat_map(|data| data.statements.iter())
+            .filter_map(|stmt| match &stmt.kind {
+                StatementKind::Assign(box (lhs, rvalue)) if lhs.as_local() == Some(local) => {
+                    Some(rvalue)
+                }
+                _ => None,
+            });
+        let rvalue = match (rvalues.next(), rvalues.next()) {
+            (Some(rvalue), None) => rvalue,
+            _ => return None,
+        };
+        local = match rvalue {
+            Rvalue::Use(Operand::Move(rhs))
+            | Rvalue::Use(Operand::Copy(rhs))
+            | Rvalue::Ref(_, _, rhs)
+            | Rvalue::Cast(_, Operand::Move(rhs), _)
+            | Rvalue::Cast(_, Operand::Copy(rhs), _) => rhs.local,
+            // e.g. `foo as fn() (Pointer(ReifyFnPointer))`
+            Rvalue::Use(Operand::Constant(box constant))
+            | Rvalue::Cast(_, Operand::Constant(box constant), _) => {
+                return fn_of_ty(tcx, constant.literal.ty, crate_fn_ids).map(FnValue::Fn)
+            }
+            _ => return None,
+        };
+    }
+}
+
+/// The fn value `operand` holds, if it may be called.
+fn operand_fn_value<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    operand: &Operand<'tcx>,
+    crate_fn_ids: &[LocalDefId],
+) -> Option<FnValue> {
+    match operand {
+        Operand::Move(place) | Operand::Copy(place) => {
+            if may_be_fn(place.ty(body, tcx).ty) {
+                trace_fn_value(tcx, body, place.local, crate_fn_ids)
+            } else {
+                None
+            }
+        }
+        Operand::Constant(box constant) => {
+            fn_of_ty(tcx, constant.literal.ty, crate_fn_ids).map(FnValue::Fn)
+        }
+    }
+}
+
+/// The fn value called by a call of `func` with `args`:
+/// the callee itself if it is not a constant, or the self arg of a `Fn*` trait method.
+fn called_fn_value<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    func: &Operand<'tcx>,
+    args: &[Operand<'tcx>],
+    crate_fn_ids: &[LocalDefId],
+) -> Option<FnValue> {
+    match func {
+        Operand::Move(place) | Operand::Copy(place) => {
+            trace_fn_value(tcx, body, place.local, crate_fn_ids)
+        }
+        Operand::Constant(box constant) => {
+            let method = match constant.literal.ty.kind {
+                TyKind::FnDef(method, _) => method,
+                _ => return None,
+            };
+            let trait_id = tcx.trait_of_item(method)?;
+            let lang_items = tcx.lang_items();
+            if ![
+                lang_items.fn_trait(),
+                lang_items.fn_mut_trait(),
+                lang_items.fn_once_trait(),
+            ]
+            .contains(&Some(trait_id))
+            {
+                return None;
+            }
+            operand_fn_value(tcx, body, args.get(0)?, crate_fn_ids)
+        }
+    }
+}
+
+/// The callsites of callbacks in the crate, as edges `(higher-order fn, bb, callback)`.
+pub fn callback_edges(
+    tcx: TyCtxt,
+    crate_fn_ids: &[LocalDefId],
+) -> Vec<(LocalDefId, BasicBlock, LocalDefId)> {
+    let mut edges = Vec::new();
+    // the params called in each fn, with the bbs calling them
+    let mut called_params: HashMap<(LocalDefId, Local), Vec<BasicBlock>> = HashMap::new();
+    // the callbacks passed to each param
+    let mut passed: HashMap<(LocalDefId, Local), HashSet<LocalDefId>> = HashMap::new();
+    // the params passed on to the params of other fns
+    let mut forwarded: Vec<((LocalDefId, Local), (LocalDefId, Local))> = Vec::new();
+    for fn_id in crate_fn_ids {
+        let body = tcx.optimized_mir(*fn_id);
+        for (bb, data) in body.basic_blocks().iter_enumerated() {
+            let (func, args) = match &data.terminator().kind {
+                TerminatorKind::Call { func, args, .. } => (func, args),
+                _ => continue,
+            };
+            match called_fn_value(tcx, body, func, args, crate_fn_ids) {
+                // a callback stored in a local
+                Some(FnValue::Fn(callback)) => edges.push((*fn_id, bb, callback)),
+                Some(FnValue::Param(param)) => {
+                    called_params.entry((*fn_id, param)).or_default().push(bb)
+                }
+                None => {}
+            }
+            // the args of closures are tupled, only follow callbacks into fns
+            let callee = match resolve_callee(tcx, body, bb).and_then(|callee| callee.as_local()) {
+                Some(callee)
+                    if crate_fn_ids.contains(&callee) && !tcx.is_closure(callee.to_def_id()) =>
+                {
+                    callee
+                }
+                _ => continue,
+            };
+            let callee_body = tcx.optimized_mir(callee);
+            for (arg, param) in args.iter().zip(callee_body.args_iter()) {
+                match operand_fn_value(tcx, body, arg, crate_fn_ids) {
+                    Some(FnValue::Fn(callback)) => {
+                        passed.entry((callee, param)).or_default().insert(callback);
+                    }
+                    Some(FnValue::Param(own_param)) => {
+                        forwarded.push(((*fn_id, own_param), (callee, param)))
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (from, to) in &forwarded {
+            let callbacks = match passed.get(from) {
+                Some(callbacks) => callbacks.clone(),
+                None => continue,
+            };
+            let to_callbacks = passed.entry(*to).or_default();
+            for callback in callbacks {
+                changed |= to_callbacks.insert(callback);
+            }
+        }
+    }
+    for ((fn_id, param), bbs) in called_params {
+        if let Some(callbacks) = passed.get(&(fn_id, param)) {
+            for bb in bbs {
+                edges.extend(callbacks.iter().map(|callback| (fn_id, bb, *callback)));
+            }
+        }
+    }
+    edges
+}
@@ -0,0 +1,269 @@
+//! Calls of the wait methods of `std::sync::Condvar` and `parking_lot::Condvar`.
+//! A wait releases the mutex of the guard passed and locks it again before returning.
+//! std takes the guard by value and returns it again in a `LockResult`,
+//! parking_lot takes the guard by `&mut` and keeps it held for the caller.
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_span;
+use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_middle::mir::{
+    BasicBlock, Body, Local, LocalInfo, LocalKind, Operand, Place, ProjectionElem, Rvalue,
+    StatementKind, TerminatorKind,
+};
+use rustc_middle::ty::{TyCtxt, TyKind};
+use rustc_span::Span;
+
+const CONDVAR_TYPES: [&str; 3] = [
+    "std::sync::Condvar",
+    "parking_lot::Condvar",
+    "parking_lot::condvar::Condvar",
+];
+
+const WAIT_METHODS: [&str; 8] = [
+    "wait",
+    "wait_timeout",
+    "wait_timeout_ms",
+    "wait_while",
+    "wait_timeout_while",
+    "wait_for",
+    "wait_until",
+    "wait_while_for",
+];
+
+#[derive(Debug, Clone)]
+pub struct CondvarWait<'tcx> {
+    pub bb: BasicBlock,
+    /// the `&Condvar` receiver
+    pub condvar: Place<'tcx>,
+    /// the guard arg as passed, a moved guard for std and a `&mut` of the guard for parking_lot
+    pub guard_arg: Place<'tcx>,
+    /// the lockguard local behind `guard_arg`, if it can be resolved
+    pub guard: Option<Local>,
+    /// the `LockResult` holding the guard again, for std only
+    pub destination: Option<Place<'tcx>>,
+    /// `wait_while` and the like check their predicate in a loop themselves
+    pub has_predicate: bool,
+    pub span: Span,
+}
+
+impl<'tcx> CondvarWait<'tcx> {
+    /// Whether the guard is taken by value and returned in `destination`.
+    pub fn takes_guard(&self) -> bool {
+        self.destination.is_some()
+    }
+
+    /// The lockguard unwrapped from `destination` and the bb it is held again from,
+    /// i.e. the call `guard = unwrap(move destination)` and its return target.
+    pub fn reacquired_guard(&self, body: &Body<'tcx>) -> Option<(Local, BasicBlock)> {
+        let destination = self.destination?;
+        body.basic_blocks()
+            .iter()
+            .find_map(|data| match &data.terminator().kind {
+                TerminatorKind::Call {
+                    args,
+                    destination: Some((lhs, target)),
+                    ..
+                } => match args.get(0) {
+                    Some(Operand::Move(rhs)) if *rhs == destination => {
+                        Some((lhs.as_local()?, *target))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+    }
+}
+
+/// The condvar waited on, identified like the src of a lockguard.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CondvarSrc {
+    ParamSrc {
+        struct_type: String,
+        fields: String,
+    },
+    GlobalSrc {
+        global: String,
+    },
+    /// a condvar local to one fn
+    LocalSrc {
+        fn_id: LocalDefId,
+        place: String,
+    },
+}
+
+impl CondvarSrc {
+    /// The condvar in the reports, e.g. `Shared.0,` of a condvar field of a param.
+    pub fn describe(&self) -> String {
+        match self {
+            CondvarSrc::ParamSrc {
+                struct_type,
+                fields,
+            } => format!("{}.{}", struct_type, fields),
+            CondvarSrc::GlobalSrc { global } => global.clone(),
+            CondvarSrc::LocalSrc { place, .. } => place.clone(),
+        }
+    }
+
+    /// Resolve the `&Condvar` receiver of `wait` back through refs, moves and copies to a param, static or local.
+    pub fn resolve<'tcx>(
+        tcx: TyCtxt<'tcx>,
+        fn_id: LocalDefId,
+        body: &Body<'tcx>,
+        wait: &CondvarWait<'tcx>,
+    ) -> Option<Self> {
+        let mut place = wait.condvar;
+        // MIR is not SSA, give up on locals with more than one def
+        let mut visited = Vec::new();
+        loop {
+            if body.local_kind(place.local) == LocalKind::Arg {
+                let fields = place
+                    .projection
+                    .iter()
+                    .filter_map(|e| {
+                        if let ProjectionElem::Field(field, _) = e {
+                            Some(field)
+                        } else {
+                            None
+                        }
+                    })
+                    .fold(String::new(), |acc, field| {
+                        acc + &format!("{:?}", field) + ","
+                    });
+                let mut struct_type = body.local_decls[place.local].ty.to_string();
+                if struct_type.starts_with('&') {
+                    struct_type = struct_type.chars().skip(1).collect();
+                }
+                return Some(CondvarSrc::ParamSrc {
+                    struct_type,
+                    fields,
+                });
+            }
+            if let Some(box LocalInfo::StaticRef { def_id, .. }) =
+                body.local_decls[place.local].local_info
+            {
+                return Some(CondvarSrc::GlobalSrc {
+                    global: tcx.def_path_str(def_id),
+                });
+            }
+            if visited.contains(&place.local) {
+                return None;
+            }
+            visited.push(place.local);
+            let mut rhses = body
+                .basic_blocks()
+                .iter()
+                .flat_map(|data| data.statements.iter())
+                .filter_map(|stmt| match &stmt.kind {
+                    StatementKind::Assign(box (lhs, rvalue))
+                        if lhs.as_local() == Some(place.local) =>
+                    {
+                        Some(rvalue)
+                    }
+                    _ => None,
+                });
+            let rhs = match (rhses.next(), rhses.next()) {
+                (Some(rhs), None) => Some(rhs),
+                (None, _) => None,
+                _ => return None,
+            };
+            match rhs {
+                Some(Rvalue::Use(Operand::Move(rhs)))
+                | Some(Rvalue::Use(Operand::Copy(rhs)))
+                | Some(Rvalue::Ref(_, _, rhs)) => {
+                    place = *rhs;
+                }
+                // created in this fn, e.g. by `Condvar::new()` or the tuple of `(Mutex::new(..), Condvar::new())`
+                _ => {
+                    return Some(CondvarSrc::LocalSrc {
+                        fn_id,
+                        place: format!("{:?}", place),
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// The wait method called if `callee` is one, and whether it is the one of std.
+fn condvar_wait_method(tcx: TyCtxt, callee: DefId) -> Option<(String, bool)> {
+    let impl_id = tcx.impl_of_method(callee)?;
+    let condvar_type = match tcx.type_of(impl_id).kind {
+        TyKind::Adt(adt_def, _) => tcx.def_path_str(adt_def.did),
+        _ => return None,
+    };
+    if !CONDVAR_TYPES.contains(&condvar_type.as_str()) {
+        return None;
+    }
+    let method = tcx.item_name(callee).to_string();
+    if !WAIT_METHODS.contains(&method.as_str()) {
+        return None;
+    }
+    Some((method, condvar_type.starts_with("std::")))
+}
+
+/// Resolve `place` through the moves, copies and refs assigned to it in `bb` up to a local.
+fn resolve_local(body: &Body, bb: BasicBlock, place: Place) -> Option<Local> {
+    let mut local = place.as_local()?;
+    for stmt in body.basic_blocks()[bb].statements.iter().rev() {
+        if let StatementKind::Assign(box (lhs, rvalue)) = &stmt.kind {
+            if lhs.as_local() != Some(local) {
+                continue;
+            }
+            let rhs = match rvalue {
+                Rvalue::Use(Operand::Move(rhs)) | Rvalue::Use(Operand::Copy(rhs)) => rhs,
+                Rvalue::Ref(_, _, rhs) => rhs,
+                _ => return Some(local),
+            };
+            match rhs.as_local() {
+                Some(rhs) => local = rhs,
+                None => return Some(local),
+            }
+        }
+    }
+    Some(local)
+}
+
+/// Every call of a `Condvar` wait method in `body`.
+pub fn condvar_waits<'tcx>(tcx: TyCtxt<'tcx>, body: &Body<'tcx>) -> Vec<CondvarWait<'tcx>> {
+    let mut waits = Vec::new();
+    for (bb, data) in body.basic_blocks().iter_enumerated() {
+        let terminator = data.terminator();
+        if let TerminatorKind::Call {
+            func: Operand::Constant(box constant),
+            args,
+            destination,
+            ..
+        } = &terminator.kind
+        {
+            let callee = match constant.literal.ty.kind {
+                TyKind::FnDef(callee, _) => callee,
+                _ => continue,
+            };
+            let (method, is_std) = match condvar_wait_method(tcx, callee) {
+                Some(method) => method,
+                None => continue,
+            };
+            let (condvar, guard_arg) = match (args.get(0), args.get(1)) {
+                (Some(condvar), Some(Operand::Move(guard_arg))) => match condvar {
+                    Operand::Move(condvar) | Operand::Copy(condvar) => (*condvar, *guard_arg),
+                    Operand::Constant(_) => continue,
+                },
+                _ => continue,
+            };
+            waits.push(CondvarWait {
+                bb,
+                condvar,
+                guard_arg,
+                guard: resolve_local(body, bb, guard_arg),
+                destination: if is_std {
+                    destination.as_ref().map(|(place, _)| *place)
+                } else {
+                    None
+                },
+                has_predicate: method.contains("_while"),
+                span: terminator.source_info.span,
+            });
+        }
+    }
+    waits
+}
@@ -0,0 +1,105 @@
+//! Misuse of `Condvar`, with the waits modeled as release-and-reacquire of the guard passed
+//! by the collector and gen/kill of the double-lock detector:
+//! - a wait while a lockguard other than the one passed is held, which blocks the notifier,
+//! - a wait without a predicate outside a loop, which misses spurious and earlier wakeups,
+//! - one condvar waited on with guards of different mutexes.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use super::config::{AnalysisLimits, CrateFilter, LockDetectorConfig};
+use super::report::{CondvarBug, CondvarReports};
+use crate::condvar::{condvar_waits, CondvarSrc};
+use crate::crate_context::{crate_findings, CrateContext};
+use crate::double_lock_checker::genkill::GenKill;
+use crate::double_lock_checker::lock::LockGuardId;
+use crate::output::Findings;
+use rustc_hir::def_id::{LocalDefId, LOCAL_CRATE};
+use rustc_middle::mir::{BasicBlock, Body};
+use rustc_middle::ty::TyCtxt;
+use std::collections::{HashMap, HashSet};
+
+pub struct CondvarChecker {
+    crate_filter: CrateFilter,
+    limits: AnalysisLimits,
+    crate_condvar_reports: CondvarReports,
+}
+
+impl CondvarChecker {
+    pub fn new(config: &LockDetectorConfig) -> Self {
+        Self {
+            crate_filter: config.crate_filter.clone(),
+            limits: config.limits,
+            crate_condvar_reports: CondvarReports::new(),
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        tcx: TyCtxt,
+        crate_context: &CrateContext,
+    ) -> Option<Findings<CondvarBug>> {
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+        if !self.crate_filter.is_checked(&crate_name) {
+            return None;
+        }
+        for fn_id in crate_context.fn_ids.iter() {
+            self.check_fn(tcx, crate_context, *fn_id);
+        }
+        Some(crate_findings(tcx, &mut self.crate_condvar_reports))
+    }
+
+    fn check_fn(&mut self, tcx: TyCtxt, crate_context: &CrateContext, fn_id: LocalDefId) {
+        let body = tcx.optimized_mir(fn_id);
+        let waits = condvar_waits(tcx, body);
+        if waits.is_empty() {
+            return;
+        }
+        let no_lockguards = HashMap::new();
+        let lockguards = crate_context
+            .lockguards
+            .get(&fn_id)
+            .unwrap_or(&no_lockguards);
+        let context = HashSet::new();
+        let mut genkill = GenKill::new(fn_id, body, lockguards, &context, self.limits.run_limit);
+        // the double-locks are left to the double-lock detector
+        genkill.analyze(body);
+        for wait in waits.iter() {
+            let passed = wait.guard.map(|local| LockGuardId::new(fn_id, local));
+            if let Some(live) = genkill.get_live_lockguards_after(&wait.bb) {
+                for id in live.iter().filter(|id| Some(**id) != passed) {
+                    self.crate_condvar_reports
+                        .add_held_lock(&lockguards[id], wait.span);
+                }
+            }
+            if !wait.has_predicate && !in_loop(body, wait.bb) {
+                self.crate_condvar_reports.add_no_loop(wait.span);
+            }
+            let guard = match passed.and_then(|id| lockguards.get(&id)) {
+                Some(guard) => guard,
+                None => continue,
+            };
+            if let Some(condvar) = CondvarSrc::resolve(tcx, fn_id, body, wait) {
+                self.crate_condvar_reports
+                    .add_wait(condvar, guard, wait.span);
+            }
+        }
+    }
+}
+
+/// Whether `bb` can reach itself, i.e. the wait is checked again after waking up.
+fn in_loop(body: &Body, bb: BasicBlock) -> bool {
+    let mut worklist: Vec<BasicBlock> = body.basic_blocks()[bb]
+        .terminator()
+        .successors()
+        .cloned()
+        .collect();
+    let mut visited: HashSet<BasicBlock> = HashSet::new();
+    while let Some(cur) = worklist.pop() {
+        if cur == bb {
+            return true;
+        }
+        if visited.insert(cur) {
+            worklist.extend(body.basic_blocks()[cur].terminator().successors().cloned());
+        }
+    }
+    false
+}
@@ -0,0 +1,5 @@
+mod checker;
+mod report;
+pub use self::checker::CondvarChecker;
+pub use self::report::{CondvarBug, CondvarBugKind};
+use super::config;
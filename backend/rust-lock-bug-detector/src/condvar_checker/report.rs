@@ -0,0 +1,400 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::baseline::Fingerprinter;
+use crate::condvar::CondvarSrc;
+use crate::config::Severities;
+use crate::crate_context::CrateReports;
+use crate::double_lock_checker::lock::{LockGuardInfo, LockGuardSrc, LockGuardType};
+use crate::output::{CrateReport, Findings, LockSite, SpanInfo};
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+enum CondvarSite {
+    /// `lock` is held while waiting at `wait_span`
+    HeldLock {
+        lock_type_name: (LockGuardType, String),
+        lock_span: Span,
+        wait_span: Span,
+    },
+    /// the wait at `wait_span` has no predicate and is not in a loop
+    NoLoop { wait_span: Span },
+}
+
+/// A wait on a condvar with the guard passed and the mutex it locks.
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct CondvarWaitSite {
+    lock_src: LockGuardSrc,
+    lock_type_name: (LockGuardType, String),
+    lock_span: Span,
+    wait_span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CondvarBugKind {
+    HeldLock,
+    NoLoop,
+    MixedLocks,
+}
+
+/// One condvar misuse in the serialized report.
+#[derive(Debug, Clone, Serialize)]
+pub struct CondvarBug {
+    pub kind: CondvarBugKind,
+    pub wait_span: SpanInfo,
+    /// held-lock: the other lock held, mixed-locks: the guard passed to a wait of each mutex
+    pub locks: Vec<LockSite>,
+    /// mixed-locks: the condvar and the waits with a guard of another mutex
+    pub condvar: Option<String>,
+    pub other_waits: Vec<SpanInfo>,
+    /// identifies the misuse in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct CondvarCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    condvars: Vec<CondvarBug>,
+    suppressed: usize,
+}
+
+pub struct CondvarReports {
+    reports: HashSet<CondvarSite>,
+    waits: HashMap<CondvarSrc, HashSet<CondvarWaitSite>>,
+    // the number of reports suppressed in source
+    suppressed: usize,
+}
+
+impl CondvarReports {
+    pub fn new() -> Self {
+        Self {
+            reports: HashSet::new(),
+            waits: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    pub fn add_held_lock(&mut self, lockguard: &LockGuardInfo, wait_span: Span) {
+        self.reports.insert(CondvarSite::HeldLock {
+            lock_type_name: lockguard.type_name.clone(),
+            lock_span: lockguard.span,
+            wait_span,
+        });
+    }
+
+    pub fn add_no_loop(&mut self, wait_span: Span) {
+        self.reports.insert(CondvarSite::NoLoop { wait_span });
+    }
+
+    /// Record that `condvar` is waited on with `guard`, to find condvars used with different mutexes.
+    /// A lock local to one fn is only compared with the other locks of a condvar local to the same fn.
+    pub fn add_wait(&mut self, condvar: CondvarSrc, guard: &LockGuardInfo, wait_span: Span) {
+        let lock_src = match &guard.src {
+            Some(LockGuardSrc::LocalSrc(_)) if !matches!(condvar, CondvarSrc::LocalSrc { .. }) => {
+                return
+            }
+            Some(src) => src.clone(),
+            None => return,
+        };
+        self.waits
+            .entry(condvar)
+            .or_insert_with(HashSet::new)
+            .insert(CondvarWaitSite {
+                lock_src,
+                lock_type_name: guard.type_name.clone(),
+                lock_span: guard.span,
+                wait_span,
+            });
+    }
+
+    /// The waits of each condvar used with guards of more than one mutex, in the order of their first wait.
+    fn mixed_locks(&self) -> Vec<(&CondvarSrc, Vec<&CondvarWaitSite>)> {
+        let mut mixed = Vec::new();
+        for (condvar, waits) in self.waits.iter() {
+            let locks = waits
+                .iter()
+                .map(|wait| (&wait.lock_src, &wait.lock_type_name.1))
+                .collect::<HashSet<_>>();
+            if locks.len() > 1 {
+                let mut waits = waits.iter().collect::<Vec<_>>();
+                waits.sort_by_key(|wait| (wait.wait_span, wait.lock_span));
+                mixed.push((condvar, waits));
+            }
+        }
+        mixed.sort_by_key(|(_, waits)| (waits[0].wait_span, waits[0].lock_span));
+        mixed
+    }
+
+    /// A block for each wait with a lock held or out of a loop, then for each condvar used with different mutexes.
+    pub fn text(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> TextReport {
+        let mut sites = self.reports.iter().collect::<Vec<_>>();
+        sites.sort_by_key(|site| match site {
+            CondvarSite::HeldLock {
+                lock_span,
+                wait_span,
+                ..
+            } => (*wait_span, Some(*lock_span)),
+            CondvarSite::NoLoop { wait_span } => (*wait_span, None),
+        });
+        let mut text = TextReport::new("condvar misuses", self.suppressed);
+        for site in sites {
+            let out = match site {
+                CondvarSite::HeldLock {
+                    lock_type_name,
+                    lock_span,
+                    wait_span,
+                } => format!(
+                    "CondvarReport: HeldLock {:?}\n\tLock: {:?}\n\tWait: {:?}\n",
+                    lock_type_name, lock_span, wait_span
+                ),
+                CondvarSite::NoLoop { wait_span } => {
+                    format!("CondvarReport: NoLoop\n\tWait: {:?}\n", wait_span)
+                }
+            };
+            text.finding(Self::site_bug(site, tcx, fingerprinter).fingerprint, out);
+        }
+        for (condvar, waits) in self.mixed_locks() {
+            let mut out = format!("CondvarReport: MixedLocks {}\n", condvar.describe());
+            for wait in waits.iter() {
+                out += &format!("\tLock: {:?} {:?}\n", wait.lock_src, wait.lock_type_name);
+                out += &format!("\t\t{:?}\n\tWait: {:?}\n", wait.lock_span, wait.wait_span);
+            }
+            text.finding(
+                Self::mixed_locks_bug(condvar, waits, tcx, fingerprinter).fingerprint,
+                out,
+            );
+        }
+        text
+    }
+
+    fn site_bug(site: &CondvarSite, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> CondvarBug {
+        let source_map = tcx.sess.source_map();
+        let bug = match site {
+            CondvarSite::HeldLock {
+                lock_type_name,
+                lock_span,
+                wait_span,
+            } => CondvarBug {
+                kind: CondvarBugKind::HeldLock,
+                wait_span: SpanInfo::new(*wait_span, source_map),
+                locks: vec![lock_site(lock_type_name, *lock_span, tcx)],
+                condvar: None,
+                other_waits: Vec::new(),
+                fingerprint: String::new(),
+            },
+            CondvarSite::NoLoop { wait_span } => CondvarBug {
+                kind: CondvarBugKind::NoLoop,
+                wait_span: SpanInfo::new(*wait_span, source_map),
+                locks: Vec::new(),
+                condvar: None,
+                other_waits: Vec::new(),
+                fingerprint: String::new(),
+            },
+        };
+        with_fingerprint(bug, fingerprinter)
+    }
+
+    fn mixed_locks_bug(
+        condvar: &CondvarSrc,
+        waits: Vec<&CondvarWaitSite>,
+        tcx: TyCtxt,
+        fingerprinter: &Fingerprinter,
+    ) -> CondvarBug {
+        let source_map = tcx.sess.source_map();
+        // the first wait of each mutex
+        let mut seen = HashSet::new();
+        let waits = waits
+            .into_iter()
+            .filter(|wait| seen.insert((&wait.lock_src, &wait.lock_type_name.1)))
+            .collect::<Vec<_>>();
+        let bug = CondvarBug {
+            kind: CondvarBugKind::MixedLocks,
+            wait_span: SpanInfo::new(waits[0].wait_span, source_map),
+            locks: waits
+                .iter()
+                .map(|wait| lock_site(&wait.lock_type_name, wait.lock_span, tcx))
+                .collect(),
+            condvar: Some(condvar.describe()),
+            other_waits: waits[1..]
+                .iter()
+                .map(|wait| SpanInfo::new(wait.wait_span, source_map))
+                .collect(),
+            fingerprint: String::new(),
+        };
+        with_fingerprint(bug, fingerprinter)
+    }
+
+    /// Resolve spans into serializable findings, sorted by location.
+    pub fn bugs(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<CondvarBug> {
+        let mut bugs = self
+            .reports
+            .iter()
+            .map(|site| Self::site_bug(site, tcx, fingerprinter))
+            .collect::<Vec<_>>();
+        for (condvar, waits) in self.mixed_locks() {
+            bugs.push(Self::mixed_locks_bug(condvar, waits, tcx, fingerprinter));
+        }
+        bugs.sort_by(|a, b| (&a.wait_span, a.kind).cmp(&(&b.wait_span, b.kind)));
+        bugs
+    }
+}
+
+impl CrateReports for CondvarReports {
+    type Bug = CondvarBug;
+
+    /// Drop the reports whose wait or lock is suppressed in source, counting them.
+    /// The waits suppressed are not compared with the other waits of their condvar.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        let before = self.reports.len() + self.mixed_locks().len();
+        self.reports.retain(|site| match site {
+            CondvarSite::HeldLock {
+                lock_span,
+                wait_span,
+                ..
+            } => {
+                !suppressions.is_suppressed(BugKind::Condvar, *lock_span)
+                    && !suppressions.is_suppressed(BugKind::Condvar, *wait_span)
+            }
+            CondvarSite::NoLoop { wait_span } => {
+                !suppressions.is_suppressed(BugKind::Condvar, *wait_span)
+            }
+        });
+        for waits in self.waits.values_mut() {
+            waits.retain(|wait| !suppressions.is_suppressed(BugKind::Condvar, wait.wait_span));
+        }
+        self.suppressed += before - self.reports.len() - self.mixed_locks().len();
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<CondvarBug> {
+        Findings {
+            bugs: self.bugs(tcx, fingerprinter),
+            suppressed: self.suppressed,
+            baselined: Vec::new(),
+            text: self.text(tcx, fingerprinter),
+        }
+    }
+}
+
+fn lock_site(lock_type_name: &(LockGuardType, String), span: Span, tcx: TyCtxt) -> LockSite {
+    LockSite {
+        lock_type: format!("{:?}", lock_type_name.0),
+        data_type: lock_type_name.1.clone(),
+        span: SpanInfo::new(span, tcx.sess.source_map()),
+    }
+}
+
+/// The misuse is identified by the wait and the locks,
+/// the wait being the first wait of the condvar for a mixed-locks misuse.
+fn with_fingerprint(mut bug: CondvarBug, fingerprinter: &Fingerprinter) -> CondvarBug {
+    let mut parts = vec![
+        format!("{:?}", bug.kind),
+        fingerprinter.position(&bug.wait_span),
+    ];
+    parts.extend(bug.locks.iter().map(|lock| fingerprinter.lock_site(lock)));
+    bug.fingerprint = fingerprinter.fingerprint("condvar", &parts);
+    bug
+}
+
+impl CrateReport for Findings<CondvarBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = CondvarCrateReport {
+            crate_name,
+            severity: severities.condvar.as_str(),
+            condvars: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log, the wait being the primary location.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let locks = bug
+                    .locks
+                    .iter()
+                    .map(|lock| format!("{}<{}>", lock.lock_type, lock.data_type))
+                    .collect::<Vec<_>>();
+                match bug.kind {
+                    CondvarBugKind::HeldLock => SarifFinding {
+                        rule: SarifRule::Condvar,
+                        message: format!("Condvar wait while {} is held", locks[0]),
+                        primary: SarifLocation::new(
+                            bug.wait_span.clone(),
+                            format!("waiting while holding {}", locks[0]),
+                        ),
+                        related: vec![SarifLocation::new(
+                            bug.locks[0].span.clone(),
+                            format!("{} acquired", locks[0]),
+                        )],
+                        code_flows: vec![vec![
+                            SarifLocation::new(
+                                bug.locks[0].span.clone(),
+                                format!("{} acquired", locks[0]),
+                            ),
+                            SarifLocation::new(bug.wait_span, "waiting while holding the lock"),
+                        ]],
+                        fingerprint: bug.fingerprint,
+                    },
+                    CondvarBugKind::NoLoop => SarifFinding {
+                        rule: SarifRule::Condvar,
+                        message: "Condvar wait without a predicate outside a loop".to_string(),
+                        primary: SarifLocation::new(
+                            bug.wait_span,
+                            "not waiting again after a spurious wakeup",
+                        ),
+                        related: Vec::new(),
+                        code_flows: Vec::new(),
+                        fingerprint: bug.fingerprint,
+                    },
+                    CondvarBugKind::MixedLocks => {
+                        let condvar = bug.condvar.unwrap_or_default();
+                        let mut related = bug
+                            .locks
+                            .iter()
+                            .zip(locks.iter())
+                            .map(|(lock, name)| {
+                                SarifLocation::new(lock.span.clone(), format!("{} acquired", name))
+                            })
+                            .collect::<Vec<_>>();
+                        related.extend(bug.other_waits.into_iter().zip(locks.iter().skip(1)).map(
+                            |(wait, name)| {
+                                SarifLocation::new(
+                                    wait,
+                                    format!("waiting on {} with {}", condvar, name),
+                                )
+                            },
+                        ));
+                        SarifFinding {
+                            rule: SarifRule::Condvar,
+                            message: format!(
+                                "Condvar {} is waited on with guards of different mutexes: {}",
+                                condvar,
+                                locks.join(", ")
+                            ),
+                            primary: SarifLocation::new(
+                                bug.wait_span,
+                                format!("waiting on {} with {}", condvar, locks[0]),
+                            ),
+                            related,
+                            code_flows: Vec::new(),
+                            fingerprint: bug.fingerprint,
+                        }
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
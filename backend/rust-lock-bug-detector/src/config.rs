@@ -1,68 +1,505 @@
+//! The config of the detector, read from env vars set by `cargo lock-bug-detect`.
+//! `cargo lock-bug-detect` merges `lock-bug-detect.toml` with its CLI flags into these env vars.
+use crate::baseline::Baseline;
+use crate::cache::CacheConfig;
+use glob::Pattern;
+use serde::Deserialize;
 use std::env;
-pub enum CrateNameLists {
-    White(Vec<String>),
-    Black(Vec<String>),
-}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LockDetectorType {
     DoubleLockDetector,
     ConflictLockDetector,
+    AwaitLockDetector,
+    CondvarDetector,
+    DoubleBorrowDetector,
+    BlockingCallDetector,
+}
+
+/// The crates to check: the ones matching any `include` pattern (every crate if there is none)
+/// and no `exclude` pattern.
+#[derive(Debug, Clone)]
+pub struct CrateFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl CrateFilter {
+    /// Parse comma-separated glob patterns. rustc crate names use `_` where package names use `-`.
+    fn parse_patterns(patterns: &str, env_var: &str) -> Result<Vec<Pattern>, String> {
+        patterns
+            .split(',')
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                Pattern::new(&pattern.replace('-', "_")).map_err(|e| {
+                    format!("Env var \"{}\" has an invalid pattern \"{}\": {}", env_var, pattern, e)
+                })
+            })
+            .collect()
+    }
+
+    pub fn is_checked(&self, crate_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(crate_name)))
+            && !self.exclude.iter().any(|p| p.matches(crate_name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    /// The name in the config, which is also the SARIF level.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+
+    fn parse(severity: &str) -> Option<Self> {
+        match severity {
+            "error" => Some(Severity::Error),
+            "warning" => Some(Severity::Warning),
+            "note" => Some(Severity::Note),
+            _ => None,
+        }
+    }
+}
+
+/// The severity of each bug kind.
+#[derive(Debug, Clone, Copy)]
+pub struct Severities {
+    pub double_lock: Severity,
+    pub conflict_lock: Severity,
+    pub await_lock: Severity,
+    pub condvar: Severity,
+    pub double_borrow: Severity,
+    pub blocking_call: Severity,
+}
+
+impl Default for Severities {
+    fn default() -> Self {
+        Self {
+            double_lock: Severity::Error,
+            conflict_lock: Severity::Warning,
+            await_lock: Severity::Warning,
+            condvar: Severity::Warning,
+            double_borrow: Severity::Error,
+            blocking_call: Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    /// print to stdout if not provided
+    pub path: Option<String>,
+    pub severities: Severities,
+}
+
+/// How a lockguard holds its lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardKind {
+    Exclusive,
+    Shared,
+    Upgradable,
+}
+
+impl GuardKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GuardKind::Exclusive => "exclusive",
+            GuardKind::Shared => "shared",
+            GuardKind::Upgradable => "upgradable",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "exclusive" => Some(GuardKind::Exclusive),
+            "shared" => Some(GuardKind::Shared),
+            "upgradable" => Some(GuardKind::Upgradable),
+            _ => None,
+        }
+    }
+
+    /// Whether acquiring `other` blocks while `self` of the same lock is held.
+    /// Shared guards block each other as a waiting writer blocks new readers, as in std and parking_lot.
+    /// An upgradable guard only excludes writers and other upgradable guards.
+    pub fn deadlock_with(self, other: Self) -> bool {
+        !matches!(
+            (self, other),
+            (GuardKind::Shared, GuardKind::Upgradable) | (GuardKind::Upgradable, GuardKind::Shared)
+        )
+    }
+}
+
+/// A lockguard type declared by the user, e.g. the guard of an instrumented mutex wrapper.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuardTypeConfig {
+    /// the path of the guard type, as rustc prints it or its full def path
+    pub path: String,
+    pub kind: GuardKind,
+    /// the index of the type argument that is the protected data, the last one if not provided
+    #[serde(default)]
+    pub data: Option<usize>,
+    /// guards of the same lock deadlock with each other, the guard type itself if not provided
+    #[serde(default)]
+    pub lock: Option<String>,
+    /// whether the guard is unwrapped from a `Result` like `std::sync::MutexGuard`
+    #[serde(default)]
+    pub result: bool,
+}
+
+/// What a fn the detector cannot follow is assumed to do with a lockguard moved into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownGuardMove {
+    /// drop it before returning, the default
+    Release,
+    /// keep it, e.g. store it somewhere, so the lock stays held
+    Hold,
+}
+
+/// The fns that block the calling thread, as glob patterns of their def paths without generic args,
+/// e.g. `std::thread::JoinHandle::join` or `<std::fs::File as std::io::Read>::read`.
+#[derive(Debug, Clone)]
+pub struct BlockingFunctions {
+    patterns: Vec<Pattern>,
+}
+
+impl BlockingFunctions {
+    pub fn is_blocking(&self, def_path: &str) -> bool {
+        let def_path = strip_generic_args(def_path);
+        self.patterns.iter().any(|p| p.matches(&def_path))
+    }
+}
+
+/// `std::thread::JoinHandle::<T>::join` to `std::thread::JoinHandle::join`.
+pub fn strip_generic_args(def_path: &str) -> String {
+    let mut stripped = String::new();
+    let mut depth = 0;
+    let mut rest = def_path;
+    while !rest.is_empty() {
+        if depth == 0 && rest.starts_with("::<") {
+            depth = 1;
+            rest = &rest[3..];
+            continue;
+        }
+        // the `->` of a fn pointer type in the generic args
+        if depth > 0 && rest.starts_with("->") {
+            rest = &rest[2..];
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        rest = &rest[c.len_utf8()..];
+        match (depth, c) {
+            (0, _) => stripped.push(c),
+            (_, '<') => depth += 1,
+            (_, '>') => depth -= 1,
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnalysisLimits {
+    /// limit the callchain depth when doing inter-procedural analysis
+    pub callchain_depth: usize,
+    /// limit the GenKill iteration inside one function
+    pub run_limit: u32,
+}
+
+impl AnalysisLimits {
+    /// Whether the callees of a fn `calls` calls away from the entry fn are left out:
+    /// with a callchain depth of N, the fns at most N calls away from the entry fn are analyzed.
+    pub fn stops_at(&self, calls: usize) -> bool {
+        calls >= self.callchain_depth
+    }
 }
 
 pub struct LockDetectorConfig {
-    pub lock_detector_type: LockDetectorType,
-    pub crate_name_lists: CrateNameLists,
+    pub lock_detector_types: Vec<LockDetectorType>,
+    pub crate_filter: CrateFilter,
+    pub limits: AnalysisLimits,
+    pub output: OutputConfig,
+    pub guard_types: Vec<GuardTypeConfig>,
+    pub blocking_functions: BlockingFunctions,
+    /// link `dyn Trait` method calls to every local impl of the method in the callgraphs
+    pub link_dyn_calls: bool,
+    pub unknown_guard_move: UnknownGuardMove,
+    /// where the lock summaries of analyzed crates are written and loaded, no summaries if not provided
+    pub summary_dir: Option<String>,
+    /// where the results of each analyzed crate are cached for `cargo lock-bug-detect`,
+    /// the reports are emitted to the output instead if not provided
+    pub cache: Option<CacheConfig>,
+    /// the findings not to report
+    pub baseline: Option<Baseline>,
+}
+
+fn parse_number<T: std::str::FromStr>(env_var: &str, default: T) -> Result<T, String> {
+    match env::var(env_var) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("Env var \"{}\" is provided with wrong value.\nPlease set it to a positive integer", env_var)),
+        Err(_) => Ok(default),
+    }
 }
 
 impl LockDetectorConfig {
-    pub fn from_env() -> Result<Self, &'static str> {
+    pub fn from_env() -> Result<Self, String> {
         let lock_detector_type = "RUST_LOCK_DETECTOR_TYPE";
         let black_crate_name_lists = "RUST_LOCK_DETECTOR_BLACK_LISTS";
         let white_crate_name_lists = "RUST_LOCK_DETECTOR_WHITE_LISTS";
-        let lock_detector_type = match env::var(lock_detector_type) {
-            Ok(detector) => {
-                if &detector == "DoubleLockDetector" {
-                    LockDetectorType::DoubleLockDetector
-                } else if &detector == "ConflictLockDetector" {
-                    LockDetectorType::ConflictLockDetector
-                } else {
-                    return Err("Env var \"RUST_LOCK_DETECTOR_TYPE\" is not set or provided with wrong value.\nPlease set it to \"DoubleLockDetector\" or \"ConflictLockDetector\"");
+        let callchain_depth = "RUST_LOCK_DETECTOR_CALLCHAIN_DEPTH";
+        let run_limit = "RUST_LOCK_DETECTOR_RUN_LIMIT";
+        let severity = "RUST_LOCK_DETECTOR_SEVERITY";
+        let output_format = "RUST_LOCK_DETECTOR_OUTPUT_FORMAT";
+        let output_path = "RUST_LOCK_DETECTOR_OUTPUT_PATH";
+        let summary_dir = "RUST_LOCK_DETECTOR_SUMMARY_DIR";
+        let guard_types = "RUST_LOCK_DETECTOR_GUARD_TYPES";
+        let blocking_functions = "RUST_LOCK_DETECTOR_BLOCKING_FUNCTIONS";
+        let blocking_builtin = "RUST_LOCK_DETECTOR_BLOCKING_BUILTIN";
+        let link_dyn_calls = "RUST_LOCK_DETECTOR_LINK_DYN_CALLS";
+        let unknown_guard_move = "RUST_LOCK_DETECTOR_UNKNOWN_GUARD_MOVE";
+        let cache_dir = "RUST_LOCK_DETECTOR_CACHE_DIR";
+        let cache_key = "RUST_LOCK_DETECTOR_CACHE_KEY";
+        let baseline = "RUST_LOCK_DETECTOR_BASELINE";
+        let wrong_detector_type = "Env var \"RUST_LOCK_DETECTOR_TYPE\" is not set or provided with wrong value.\nPlease set it to \"DoubleLockDetector\", \"ConflictLockDetector\", \"AwaitLockDetector\", \"CondvarDetector\", \"DoubleBorrowDetector\" or \"BlockingCallDetector\" separated by commas".to_string();
+        let lock_detector_types = match env::var(lock_detector_type) {
+            Ok(detectors) => detectors
+                .split(',')
+                .map(|detector| match detector {
+                    "DoubleLockDetector" => Ok(LockDetectorType::DoubleLockDetector),
+                    "ConflictLockDetector" => Ok(LockDetectorType::ConflictLockDetector),
+                    "AwaitLockDetector" => Ok(LockDetectorType::AwaitLockDetector),
+                    "CondvarDetector" => Ok(LockDetectorType::CondvarDetector),
+                    "DoubleBorrowDetector" => Ok(LockDetectorType::DoubleBorrowDetector),
+                    "BlockingCallDetector" => Ok(LockDetectorType::BlockingCallDetector),
+                    _ => Err(wrong_detector_type.clone()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => return Err(wrong_detector_type),
+        };
+        // the white list is the crates to include and the black list the crates to exclude
+        let crate_filter = CrateFilter {
+            include: CrateFilter::parse_patterns(
+                &env::var(white_crate_name_lists).unwrap_or_default(),
+                white_crate_name_lists,
+            )?,
+            exclude: CrateFilter::parse_patterns(
+                &env::var(black_crate_name_lists).unwrap_or_default(),
+                black_crate_name_lists,
+            )?,
+        };
+        let limits = AnalysisLimits {
+            callchain_depth: parse_number(callchain_depth, CALLCHAIN_DEPTH)?,
+            run_limit: parse_number(run_limit, RUN_LIMIT)?,
+        };
+        let mut severities = Severities::default();
+        if let Ok(kind_severities) = env::var(severity) {
+            for kind_severity in kind_severities.split(',').filter(|s| !s.is_empty()) {
+                let wrong_severity = || format!("Env var \"RUST_LOCK_DETECTOR_SEVERITY\" is provided with wrong value \"{}\".\nPlease set it to \"<bug kind>=<error|warning|note>\" separated by commas", kind_severity);
+                let mut parts = kind_severity.splitn(2, '=');
+                let kind = parts.next().unwrap();
+                let level = parts.next().and_then(Severity::parse).ok_or_else(wrong_severity)?;
+                match kind {
+                    "double-lock" => severities.double_lock = level,
+                    "conflict-lock" => severities.conflict_lock = level,
+                    "await-lock" => severities.await_lock = level,
+                    "condvar" => severities.condvar = level,
+                    "double-borrow" => severities.double_borrow = level,
+                    "blocking-call" => severities.blocking_call = level,
+                    _ => return Err(wrong_severity()),
                 }
+            }
+        }
+        let output_format = match env::var(output_format) {
+            Ok(format) => match format.as_str() {
+                "text" => OutputFormat::Text,
+                "json" => OutputFormat::Json,
+                "sarif" => OutputFormat::Sarif,
+                _ => return Err("Env var \"RUST_LOCK_DETECTOR_OUTPUT_FORMAT\" is provided with wrong value.\nPlease set it to \"text\", \"json\" or \"sarif\"".to_string()),
             },
-            Err(_) => return Err("Env var \"RUST_LOCK_DETECTOR_TYPE\" is not set or provided with wrong value.\nPlease set it to \"DoubleLockDetector\" or \"ConflictLockDetector\""),
+            Err(_) => OutputFormat::Text,
         };
-        let black_crate_name_lists: Vec<String> = match env::var(black_crate_name_lists) {
-            Ok(black_crate_name_lists) => black_crate_name_lists
-                .split(',')
-                .map(|s| s.to_string())
-                .collect(),
-            Err(_) => Vec::new(),
+        let output = OutputConfig {
+            format: output_format,
+            path: env::var(output_path).ok().filter(|path| !path.is_empty()),
+            severities,
         };
-        let white_crate_name_lists: Vec<String> = match env::var(white_crate_name_lists) {
-            Ok(white_crate_name_lists) => white_crate_name_lists
-                .split(',')
-                .map(|s| s.to_string())
-                .collect(),
-            Err(_) => Vec::new(),
+        // a JSON array of `GuardTypeConfig`
+        let guard_types = match env::var(guard_types) {
+            Ok(types) if !types.is_empty() => serde_json::from_str(&types).map_err(|e| {
+                format!("Env var \"RUST_LOCK_DETECTOR_GUARD_TYPES\" is provided with wrong value: {}", e)
+            })?,
+            _ => Vec::new(),
         };
-        if !black_crate_name_lists.is_empty() && !white_crate_name_lists.is_empty() {
-            Err("Env var \"RUST_LOCK_DETECTOR_BLACK_LISTS\" and \"RUST_LOCK_DETECTOR_WHITE_LISTS\" are \nboth provided values. Please clear the values in one of them")
-        } else if !black_crate_name_lists.is_empty() {
-            Ok(Self {
-                lock_detector_type,
-                crate_name_lists: CrateNameLists::Black(black_crate_name_lists),
-            })
-        } else {
-            Ok(Self {
-                lock_detector_type,
-                crate_name_lists: CrateNameLists::White(white_crate_name_lists),
-            })
+        // comma-separated patterns added to the built-in ones, unless the built-in ones are turned off
+        let mut patterns = Vec::new();
+        if env::var(blocking_builtin).map_or(true, |builtin| builtin != "false") {
+            patterns.extend(BLOCKING_FUNCTIONS.iter().map(|pattern| Pattern::new(pattern).unwrap()));
+        }
+        for pattern in env::var(blocking_functions).unwrap_or_default().split(',').filter(|p| !p.is_empty()) {
+            patterns.push(Pattern::new(pattern).map_err(|e| {
+                format!("Env var \"{}\" has an invalid pattern \"{}\": {}", blocking_functions, pattern, e)
+            })?);
         }
+        let blocking_functions = BlockingFunctions { patterns };
+        let link_dyn_calls = env::var(link_dyn_calls).ok().as_deref() == Some("true");
+        let unknown_guard_move = match env::var(unknown_guard_move) {
+            Ok(assumption) => match assumption.as_str() {
+                "release" => UnknownGuardMove::Release,
+                "hold" => UnknownGuardMove::Hold,
+                _ => return Err("Env var \"RUST_LOCK_DETECTOR_UNKNOWN_GUARD_MOVE\" is provided with wrong value.\nPlease set it to \"release\" or \"hold\"".to_string()),
+            },
+            Err(_) => UnknownGuardMove::Release,
+        };
+        let summary_dir = env::var(summary_dir).ok().filter(|dir| !dir.is_empty());
+        let cache = env::var(cache_dir).ok().filter(|dir| !dir.is_empty()).map(|dir| CacheConfig {
+            dir,
+            key: env::var(cache_key).unwrap_or_default(),
+        });
+        let baseline = match env::var(baseline) {
+            Ok(path) if !path.is_empty() => Some(Baseline::load(&path).map_err(|e| {
+                format!("Env var \"RUST_LOCK_DETECTOR_BASELINE\" is provided with wrong value: {}", e)
+            })?),
+            _ => None,
+        };
+        Ok(Self {
+            lock_detector_types,
+            crate_filter,
+            limits,
+            output,
+            guard_types,
+            blocking_functions,
+            link_dyn_calls,
+            unknown_guard_move,
+            summary_dir,
+            cache,
+            baseline,
+        })
     }
 }
 
-/// limit the callchain depth when doing inter-procedural analysis
+/// default limit of the callchain depth when doing inter-procedural analysis
 pub const CALLCHAIN_DEPTH: usize = 4;
 
-/// limit the GenKill iteration inside one function
+/// default limit of the GenKill iteration inside one function
 pub const RUN_LIMIT: u32 = 10000;
+
+/// the built-in blocking fns: joins, channel receives, sleeps, barriers, child processes and file and socket IO.
+/// Only the fns doing the IO are listed, not the getters of the same types, e.g. `DirEntry::file_name`.
+pub const BLOCKING_FUNCTIONS: [&str; 33] = [
+    "std::thread::JoinHandle::join",
+    "std::thread::sleep",
+    "std::thread::park*",
+    "std::sync::mpsc::Receiver::recv*",
+    "std::sync::mpsc::SyncSender::send",
+    "std::sync::Barrier::wait",
+    "std::process::Child::wait*",
+    "std::process::Command::output",
+    "std::process::Command::status",
+    "std::io::Stdin::read*",
+    // the free fns of std::fs
+    "std::fs::read*",
+    "std::fs::write",
+    "std::fs::copy",
+    "std::fs::rename",
+    "std::fs::remove_*",
+    "std::fs::create_dir*",
+    "std::fs::metadata",
+    "std::fs::symlink_metadata",
+    "std::fs::canonicalize",
+    "std::fs::hard_link",
+    "std::fs::set_permissions",
+    "std::fs::File::open",
+    "std::fs::File::create",
+    "std::fs::File::sync_*",
+    "std::fs::OpenOptions::open",
+    "<*std::fs::File as std::io::Read>::read*",
+    "<*std::fs::File as std::io::Write>::write*",
+    "std::net::TcpStream::connect*",
+    "std::net::TcpListener::accept",
+    "std::net::UdpSocket::recv*",
+    "std::net::UdpSocket::send*",
+    "<*std::net::TcpStream as std::io::Read>::read*",
+    "<*std::net::TcpStream as std::io::Write>::write*",
+];
+
+#[test]
+fn test_guard_kind_deadlock_with() {
+    use GuardKind::*;
+    assert!(Exclusive.deadlock_with(Shared));
+    assert!(Shared.deadlock_with(Shared));
+    assert!(Upgradable.deadlock_with(Upgradable));
+    assert!(!Upgradable.deadlock_with(Shared));
+    assert!(!Shared.deadlock_with(Upgradable));
+}
+
+#[test]
+fn test_blocking_functions() {
+    let blocking = BlockingFunctions {
+        patterns: BLOCKING_FUNCTIONS.iter().map(|pattern| Pattern::new(pattern).unwrap()).collect(),
+    };
+    assert_eq!(strip_generic_args("std::thread::JoinHandle::<T>::join"), "std::thread::JoinHandle::join");
+    assert_eq!(
+        strip_generic_args("std::sync::mpsc::Receiver::<std::vec::Vec<u8>>::recv"),
+        "std::sync::mpsc::Receiver::recv"
+    );
+    assert!(blocking.is_blocking("std::thread::JoinHandle::<T>::join"));
+    assert!(blocking.is_blocking("std::sync::mpsc::Receiver::<T>::recv_timeout"));
+    assert!(blocking.is_blocking("std::fs::read_to_string::<&str>"));
+    assert!(blocking.is_blocking("<std::fs::File as std::io::Read>::read"));
+    assert!(blocking.is_blocking("<&std::net::TcpStream as std::io::Write>::write_all"));
+    assert!(!blocking.is_blocking("std::sync::mpsc::Sender::<T>::send"));
+    assert!(!blocking.is_blocking("std::net::SocketAddr::new"));
+    assert!(blocking.is_blocking("std::net::UdpSocket::recv_from"));
+    assert!(blocking.is_blocking("std::fs::File::sync_all"));
+    assert!(!blocking.is_blocking("std::fs::OpenOptions::new"));
+    assert!(!blocking.is_blocking("std::fs::DirEntry::file_name"));
+    assert!(!blocking.is_blocking("std::net::TcpStream::local_addr"));
+    assert!(!blocking.is_blocking("<std::net::TcpStream as std::fmt::Debug>::fmt"));
+}
+
+#[test]
+fn test_callchain_depth() {
+    // the fns analyzed on a chain of calls from the entry fn, the entry fn included
+    let analyzed = |callchain_depth| {
+        let limits = AnalysisLimits { callchain_depth, run_limit: 0 };
+        (0..10).take_while(|calls| *calls == 0 || !limits.stops_at(calls - 1)).count()
+    };
+    assert_eq!(analyzed(0), 1);
+    assert_eq!(analyzed(1), 2);
+    assert_eq!(analyzed(4), 5);
+}
+
+#[test]
+fn test_strip_generic_args() {
+    assert_eq!(strip_generic_args("std::fs::read_to_string::<&std::path::Path>"), "std::fs::read_to_string");
+    assert_eq!(
+        strip_generic_args("std::sync::mpsc::Receiver::<std::sync::Arc<std::vec::Vec<u8>>>::recv_timeout"),
+        "std::sync::mpsc::Receiver::recv_timeout"
+    );
+    assert_eq!(strip_generic_args("std::sync::mpsc::Receiver::<fn() -> i32>::recv"), "std::sync::mpsc::Receiver::recv");
+    // the self type and the trait of an impl keep their generic args
+    assert_eq!(
+        strip_generic_args("<std::io::BufReader<std::fs::File> as std::io::Read>::read::<'_>"),
+        "<std::io::BufReader<std::fs::File> as std::io::Read>::read"
+    );
+    assert_eq!(strip_generic_args("std::thread::sleep"), "std::thread::sleep");
+}
@@ -1,37 +1,80 @@
 extern crate rustc_hir;
 extern crate rustc_middle;
 
+use crate::callback::callback_edges;
+use crate::trait_call::{trait_callees, TraitImpls};
 use rustc_hir::def_id::LocalDefId;
 use rustc_middle::mir::{BasicBlock, Body, Operand, TerminatorKind};
-use rustc_middle::ty::TyKind;
+use rustc_middle::ty::{TyCtxt, TyKind, TypeFoldable};
 use std::collections::HashMap;
 pub struct Callgraph {
-    pub direct: HashMap<LocalDefId, HashMap<BasicBlock, LocalDefId>>,
+    /// a callsite of a `dyn Trait` call may have more than one callee
+    pub direct: HashMap<LocalDefId, HashMap<BasicBlock, Vec<LocalDefId>>>,
+    /// the local impls `dyn Trait` calls are linked to, `dyn Trait` calls are dropped if not provided
+    dyn_impls: Option<TraitImpls>,
 }
 
 impl Callgraph {
     pub fn new() -> Self {
         Self {
             direct: HashMap::new(),
+            dyn_impls: None,
+        }
+    }
+
+    /// Conservatively link every `dyn Trait` call to all the local impls of the method.
+    pub fn link_dyn_calls(&mut self, tcx: TyCtxt, crate_fn_ids: &[LocalDefId]) {
+        self.dyn_impls = Some(TraitImpls::new(tcx, crate_fn_ids));
+    }
+
+    /// Link the calls of fn pointer and `Fn*` params to the local callbacks passed to them, see `callback`.
+    pub fn link_callbacks(&mut self, tcx: TyCtxt, crate_fn_ids: &[LocalDefId]) {
+        for (caller, bb, callback) in callback_edges(tcx, crate_fn_ids) {
+            if caller != callback {
+                self.insert_direct(caller, bb, callback);
+            }
         }
     }
 
     fn insert_direct(&mut self, caller: LocalDefId, bb: BasicBlock, callee: LocalDefId) {
-        if let Some(callees) = self.direct.get_mut(&caller) {
-            callees.insert(bb, callee);
-        } else {
-            let mut callees: HashMap<BasicBlock, LocalDefId> = HashMap::new();
-            callees.insert(bb, callee);
-            self.direct.insert(caller, callees);
+        let callees = self
+            .direct
+            .entry(caller)
+            .or_insert_with(HashMap::new)
+            .entry(bb)
+            .or_insert_with(Vec::new);
+        if !callees.contains(&callee) {
+            callees.push(callee);
         }
     }
 
-    pub fn generate(&mut self, caller: LocalDefId, body: &Body, crate_fn_ids: &[LocalDefId]) {
+    pub fn generate<'tcx>(
+        &mut self,
+        caller: LocalDefId,
+        body: &Body<'tcx>,
+        crate_fn_ids: &[LocalDefId],
+        tcx: TyCtxt<'tcx>,
+    ) {
         for (bb, bb_data) in body.basic_blocks().iter_enumerated() {
             let terminator = bb_data.terminator();
             if let TerminatorKind::Call { ref func, .. } = terminator.kind {
                 if let Operand::Constant(box constant) = func {
                     match constant.literal.ty.kind {
+                        // statically dispatched trait calls, e.g. `<Local as Iterator>::next`
+                        TyKind::FnDef(callee_def_id, substs)
+                            if tcx.trait_of_item(callee_def_id).is_some()
+                                && !substs.has_param_types_or_consts() =>
+                        {
+                            for callee in trait_callees(
+                                tcx,
+                                callee_def_id,
+                                substs,
+                                crate_fn_ids,
+                                self.dyn_impls.as_ref(),
+                            ) {
+                                self.insert_direct(caller, bb, callee);
+                            }
+                        }
                         TyKind::FnDef(callee_def_id, _) | TyKind::Closure(callee_def_id, _) => {
                             if let Some(local_callee_def_id) = callee_def_id.as_local() {
                                 if crate_fn_ids.contains(&local_callee_def_id) {
@@ -48,10 +91,18 @@ impl Callgraph {
         }
     }
 
-    pub fn get(&self, fn_id: &LocalDefId) -> Option<&HashMap<BasicBlock, LocalDefId>> {
+    /// Every callsite of `fn_id` with each of its callees.
+    pub fn get(
+        &self,
+        fn_id: &LocalDefId,
+    ) -> Option<impl Iterator<Item = (&BasicBlock, &LocalDefId)>> {
         if let Some(callsites) = self.direct.get(fn_id) {
             if !callsites.is_empty() {
-                return Some(callsites);
+                return Some(
+                    callsites
+                        .iter()
+                        .flat_map(|(bb, callees)| callees.iter().map(move |callee| (bb, callee))),
+                );
             } else {
                 return None;
             }
@@ -3,11 +3,17 @@ extern crate rustc_middle;
 extern crate rustc_span;
 use super::callgraph::Callgraph;
 use super::collector::collect_lockguard_info;
-use super::config::{CrateNameLists, CALLCHAIN_DEPTH};
+use super::config::{AnalysisLimits, CrateFilter, LockDetectorConfig, UnknownGuardMove};
 use super::genkill::GenKill;
 use super::lock::{ConflictLockInfo, LockGuardId, LockGuardInfo};
+use super::report::{ConflictLockBug, ConflictLockReports};
+use crate::crate_context::{crate_findings, CrateContext};
+use crate::custom_guard::CustomGuardTypes;
+use crate::output::Findings;
+use crate::results::AnalysisStats;
+use crate::summary::{extern_callsites, LockSummaries};
 use rustc_hir::def_id::{LocalDefId, LOCAL_CRATE};
-use rustc_middle::mir::BasicBlock;
+use rustc_middle::mir::{BasicBlock, Body};
 use rustc_middle::ty::TyCtxt;
 use rustc_span::Span;
 
@@ -15,64 +21,70 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 pub struct ConflictLockChecker {
-    crate_name_lists: CrateNameLists,
+    crate_filter: CrateFilter,
+    limits: AnalysisLimits,
     crate_lockguards: HashMap<LockGuardId, LockGuardInfo>,
     crate_callgraph: Callgraph,
-    crate_lock_pairs: RefCell<Vec<ConflictLockInfo>>,
+    link_dyn_calls: bool,
+    crate_conflict_lock_reports: RefCell<ConflictLockReports>,
+    custom_guard_types: CustomGuardTypes,
+    unknown_guard_move: UnknownGuardMove,
+    stats: AnalysisStats,
 }
 
 impl ConflictLockChecker {
-    pub fn new(is_white: bool, crate_name_lists: Vec<String>) -> Self {
-        if is_white {
-            Self {
-                crate_name_lists: CrateNameLists::White(crate_name_lists),
-                crate_lockguards: HashMap::new(),
-                crate_callgraph: Callgraph::new(),
-                crate_lock_pairs: RefCell::new(Vec::new()),
-            }
-        } else {
-            Self {
-                crate_name_lists: CrateNameLists::Black(crate_name_lists),
-                crate_lockguards: HashMap::new(),
-                crate_callgraph: Callgraph::new(),
-                crate_lock_pairs: RefCell::new(Vec::new()),
-            }
+    pub fn new(config: &LockDetectorConfig) -> Self {
+        Self {
+            crate_filter: config.crate_filter.clone(),
+            limits: config.limits,
+            crate_lockguards: HashMap::new(),
+            crate_callgraph: Callgraph::new(),
+            link_dyn_calls: config.link_dyn_calls,
+            crate_conflict_lock_reports: RefCell::new(ConflictLockReports::new()),
+            custom_guard_types: CustomGuardTypes::new(&config.guard_types),
+            unknown_guard_move: config.unknown_guard_move,
+            stats: AnalysisStats::default(),
         }
     }
-    pub fn check(&mut self, tcx: TyCtxt) {
+
+    /// What `check` analyzed in the crate.
+    pub fn stats(&self) -> AnalysisStats {
+        self.stats
+    }
+
+    /// The lockguards are collected again with the lock types of this detector,
+    /// only the fns and summaries of `crate_context` are shared.
+    pub fn check(
+        &mut self,
+        tcx: TyCtxt,
+        crate_context: &CrateContext,
+    ) -> Option<Findings<ConflictLockBug>> {
         let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
-        match &self.crate_name_lists {
-            CrateNameLists::White(lists) => {
-                if !lists.contains(&crate_name) {
-                    return;
-                }
-            }
-            CrateNameLists::Black(lists) => {
-                if lists.contains(&crate_name) {
-                    return;
-                }
-            }
+        if !self.crate_filter.is_checked(&crate_name) {
+            return None;
         }
         // println!("{}", crate_name);
-        // collect fn
-        let ids = tcx.mir_keys(LOCAL_CRATE);
-        let fn_ids: Vec<LocalDefId> = ids
-            .clone()
-            .into_iter()
-            .filter(|id| {
-                let hir = tcx.hir();
-                hir.body_owner_kind(hir.as_local_hir_id(*id))
-                    .is_fn_or_closure()
-            })
-            .collect();
-        // println!("fn_ids: {:#?}", fn_ids);
+        let fn_ids = &crate_context.fn_ids;
+        let summaries = &crate_context.summaries;
         // collect lockguard_info
         let lockguards: HashMap<LocalDefId, HashMap<LockGuardId, LockGuardInfo>> = fn_ids
             .clone()
             .into_iter()
             .filter_map(|fn_id| {
                 let body = tcx.optimized_mir(fn_id);
-                let lockguards = collect_lockguard_info(fn_id, body);
+                let mut lockguards = collect_lockguard_info(
+                    tcx,
+                    fn_id,
+                    body,
+                    &self.custom_guard_types,
+                    self.unknown_guard_move,
+                );
+                let returned_lock_keys = summaries.returned_lock_keys(tcx, body);
+                for (id, info) in lockguards.iter_mut() {
+                    if let Some(key) = returned_lock_keys.get(&id.local) {
+                        info.lock_key = Some(key.clone());
+                    }
+                }
                 if lockguards.is_empty() {
                     None
                 } else {
@@ -80,76 +92,107 @@ impl ConflictLockChecker {
                 }
             })
             .collect();
+        self.stats = AnalysisStats {
+            fns: fn_ids.len(),
+            fns_with_locks: lockguards.len(),
+            lockguards: lockguards.values().map(HashMap::len).sum(),
+        };
         if lockguards.is_empty() {
-            return;
+            return Some(crate_findings(tcx, &mut *self.crate_conflict_lock_reports.borrow_mut()));
         }
         for (_, info) in lockguards.iter() {
             self.crate_lockguards.extend(info.clone().into_iter());
         }
-        println!(
-            "fn with locks: {}, lockguards num: {}, local fn num: {}",
-            lockguards.len(),
-            self.crate_lockguards.len(),
-            fn_ids.len()
-        );
         // generate callgraph
-        for fn_id in &fn_ids {
+        if self.link_dyn_calls {
+            self.crate_callgraph.link_dyn_calls(tcx, fn_ids);
+        }
+        for fn_id in fn_ids {
             self.crate_callgraph
-                .generate(*fn_id, tcx.optimized_mir(*fn_id), &fn_ids);
+                .generate(*fn_id, tcx.optimized_mir(*fn_id), fn_ids, tcx);
         }
+        self.crate_callgraph.link_callbacks(tcx, fn_ids);
         // self.crate_callgraph.print();
         for (fn_id, _) in lockguards.iter() {
-            self.check_entry_fn(&tcx, *fn_id);
+            self.check_entry_fn(&tcx, summaries, *fn_id);
         }
+        Some(crate_findings(tcx, &mut *self.crate_conflict_lock_reports.borrow_mut()))
     }
 
-    fn check_entry_fn(&self, tcx: &TyCtxt, fn_id: LocalDefId) {
-        type ConflictLockBugType<'a> = (
-            (&'a LockGuardInfo, &'a LockGuardInfo),
-            (&'a LockGuardInfo, &'a LockGuardInfo),
-        );
-        // println!("checking entry fn: {:?}", fn_id);
-        let body = tcx.optimized_mir(fn_id);
-        let context = HashSet::new();
-        let mut genkill = GenKill::new(fn_id, body, &self.crate_lockguards, &context);
-        let conflict_lock_pairs = genkill.analyze(body);
-
-        let mut conflict_lock_bugs: Vec<ConflictLockBugType> = Vec::new();
-        for lhs in conflict_lock_pairs.iter() {
-            for rhs in conflict_lock_pairs.iter() {
-                let lf = self.crate_lockguards.get(&lhs.first).unwrap();
-                let ls = self.crate_lockguards.get(&lhs.second).unwrap();
-                let rf = self.crate_lockguards.get(&rhs.first).unwrap();
-                let rs = self.crate_lockguards.get(&rhs.second).unwrap();
-                if *lf == *rs && *ls == *rf {
-                    conflict_lock_bugs.push(((lf, ls), (rf, rs)));
-                }
-            }
-        }
-        for lhs in conflict_lock_pairs.iter() {
-            for rhs in self.crate_lock_pairs.borrow().iter() {
-                let lf = self.crate_lockguards.get(&lhs.first).unwrap();
-                let ls = self.crate_lockguards.get(&lhs.second).unwrap();
-                let rf = self.crate_lockguards.get(&rhs.first).unwrap();
-                let rs = self.crate_lockguards.get(&rhs.second).unwrap();
-                if *lf == *rs && *ls == *rf {
-                    conflict_lock_bugs.push(((lf, ls), (rf, rs)));
+    /// Add the locks acquired by extern callees, read from their summaries, while the context is held.
+    fn add_extern_reports(
+        &self,
+        tcx: &TyCtxt,
+        summaries: &LockSummaries,
+        fn_id: LocalDefId,
+        body: &Body,
+        genkill: &GenKill,
+        callchain: &Vec<(LocalDefId, BasicBlock)>,
+    ) {
+        for (bb, callee_id) in extern_callsites(*tcx, body) {
+            let summary = match summaries.get(*tcx, callee_id) {
+                Some(summary) => summary,
+                None => continue,
+            };
+            if let Some(context) = genkill.get_live_lockguards(&bb) {
+                let callchain_reports = callchain
+                    .iter()
+                    .chain(std::iter::once(&(fn_id, bb)))
+                    .map(|(fn_id, bb)| {
+                        tcx.optimized_mir(*fn_id).basic_blocks()[*bb]
+                            .terminator()
+                            .source_info
+                            .span
+                    })
+                    .collect::<Vec<Span>>();
+                let mut reports = self.crate_conflict_lock_reports.borrow_mut();
+                for id in context {
+                    let first = self.crate_lockguards.get(id).unwrap();
+                    for second in summary.acquired.iter() {
+                        reports.add_extern(first, second, &callchain_reports);
+                    }
                 }
             }
         }
-        if !conflict_lock_bugs.is_empty() {
-            println!("ConflictLockReport: {:#?}", conflict_lock_bugs);
+    }
+
+    fn add_reports(&self, conflict_lock_pairs: Vec<ConflictLockInfo>, callchain: &Vec<Span>) {
+        let mut reports = self.crate_conflict_lock_reports.borrow_mut();
+        for ConflictLockInfo { first, second } in conflict_lock_pairs {
+            reports.add(
+                (
+                    self.crate_lockguards.get(&first).unwrap(),
+                    self.crate_lockguards.get(&second).unwrap(),
+                ),
+                callchain,
+            );
         }
-        self.crate_lock_pairs
-            .borrow_mut()
-            .extend(conflict_lock_pairs.into_iter());
+    }
+
+    fn check_entry_fn(&self, tcx: &TyCtxt, summaries: &LockSummaries, fn_id: LocalDefId) {
+        // println!("checking entry fn: {:?}", fn_id);
+        let body = tcx.optimized_mir(fn_id);
+        let context = HashSet::new();
+        let mut genkill = GenKill::new(
+            fn_id,
+            body,
+            &self.crate_lockguards,
+            &context,
+            self.limits.run_limit,
+        );
+        let conflict_lock_pairs = genkill.analyze(body);
+        self.add_reports(conflict_lock_pairs, &Vec::new());
 
         let mut callchain: Vec<(LocalDefId, BasicBlock)> = Vec::new();
+        self.add_extern_reports(tcx, summaries, fn_id, body, &genkill, &callchain);
+        if self.limits.stops_at(callchain.len()) {
+            return;
+        }
         if let Some(callsites) = self.crate_callgraph.get(&fn_id) {
             for (bb, callee_id) in callsites {
                 if let Some(context) = genkill.get_live_lockguards(bb) {
                     callchain.push((fn_id, *bb));
-                    self.check_fn(&tcx, *callee_id, context, &mut callchain);
+                    self.check_fn(&tcx, summaries, *callee_id, context, &mut callchain);
                     callchain.pop();
                 }
             }
@@ -159,46 +202,22 @@ impl ConflictLockChecker {
     fn check_fn(
         &self,
         tcx: &TyCtxt,
+        summaries: &LockSummaries,
         fn_id: LocalDefId,
         context: &HashSet<LockGuardId>,
         callchain: &mut Vec<(LocalDefId, BasicBlock)>,
     ) {
-        type ConflictLockBugType<'a> = (
-            (&'a LockGuardInfo, &'a LockGuardInfo),
-            (&'a LockGuardInfo, &'a LockGuardInfo),
-        );
-        if callchain.len() > CALLCHAIN_DEPTH {
-            return;
-        }
         // println!("checking fn: {:?}", fn_id);
         let body = tcx.optimized_mir(fn_id);
-        let mut genkill = GenKill::new(fn_id, body, &self.crate_lockguards, context);
+        let mut genkill = GenKill::new(
+            fn_id,
+            body,
+            &self.crate_lockguards,
+            context,
+            self.limits.run_limit,
+        );
         let conflict_lock_pairs = genkill.analyze(body);
-        let mut conflict_lock_bugs: Vec<ConflictLockBugType> = Vec::new();
-        for lhs in conflict_lock_pairs.iter() {
-            for rhs in conflict_lock_pairs.iter() {
-                let lf = self.crate_lockguards.get(&lhs.first).unwrap();
-                let ls = self.crate_lockguards.get(&lhs.second).unwrap();
-                let rf = self.crate_lockguards.get(&rhs.first).unwrap();
-                let rs = self.crate_lockguards.get(&rhs.second).unwrap();
-                if *lf == *rs && *ls == *rf {
-                    conflict_lock_bugs.push(((lf, ls), (rf, rs)));
-                }
-            }
-        }
-        for lhs in conflict_lock_pairs.iter() {
-            for rhs in self.crate_lock_pairs.borrow().iter() {
-                let lf = self.crate_lockguards.get(&lhs.first).unwrap();
-                let ls = self.crate_lockguards.get(&lhs.second).unwrap();
-                let rf = self.crate_lockguards.get(&rhs.first).unwrap();
-                let rs = self.crate_lockguards.get(&rhs.second).unwrap();
-                if *lf == *rs && *ls == *rf {
-                    conflict_lock_bugs.push(((lf, ls), (rf, rs)));
-                }
-            }
-        }
-        if !conflict_lock_bugs.is_empty() {
-            println!("ConflictLockReport: {:#?}", conflict_lock_bugs);
+        if !conflict_lock_pairs.is_empty() {
             let callchain_reports = callchain
                 .iter()
                 .map(move |(fn_id, bb)| {
@@ -208,18 +227,18 @@ impl ConflictLockChecker {
                         .span
                 })
                 .collect::<Vec<Span>>();
-            println!("{:#?}", callchain_reports);
+            self.add_reports(conflict_lock_pairs, &callchain_reports);
+        }
+        self.add_extern_reports(tcx, summaries, fn_id, body, &genkill, callchain);
+        // the local callees are left out past the callchain depth, the extern ones have summaries
+        if self.limits.stops_at(callchain.len()) {
+            return;
         }
-
-        self.crate_lock_pairs
-            .borrow_mut()
-            .extend(conflict_lock_pairs.into_iter());
-
         if let Some(callsites) = self.crate_callgraph.get(&fn_id) {
             for (bb, callee_id) in callsites {
                 if let Some(context) = genkill.get_live_lockguards(bb) {
                     callchain.push((fn_id, *bb));
-                    self.check_fn(tcx, *callee_id, context, callchain);
+                    self.check_fn(tcx, summaries, *callee_id, context, callchain);
                     callchain.pop();
                 }
             }
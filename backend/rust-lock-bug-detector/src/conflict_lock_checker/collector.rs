@@ -4,27 +4,41 @@ extern crate rustc_mir;
 
 use super::dataflow::*;
 use super::lock::*;
-use super::lock::{parse_lockguard_type, LockGuardId, LockGuardInfo, LockGuardSrc, LockGuardType};
+use super::lock::{
+    parse_lockguard_type_or_custom, LockGuardId, LockGuardInfo, LockGuardSrc, LockGuardType,
+};
 use super::tracker::{Tracker, TrackerState};
+use crate::condvar::{condvar_waits, CondvarWait};
+use crate::config::UnknownGuardMove;
+use crate::custom_guard::CustomGuardTypes;
+use crate::guard_move::{classify_guard_move, GuardMove};
+use crate::summary::{canonical_def_path, lock_key, param_src_key, SrcKey};
 use rustc_hir::def_id::LocalDefId;
 use rustc_middle::mir::visit::{
     MutatingUseContext, NonMutatingUseContext, NonUseContext, PlaceContext,
 };
-use rustc_middle::mir::{Body, Local, LocalInfo, Place, ProjectionElem};
+use rustc_middle::mir::{Body, Local, LocalInfo, Place, ProjectionElem, RETURN_PLACE};
+use rustc_middle::ty::TyCtxt;
 use rustc_mir::util::def_use::DefUseAnalysis;
 use std::collections::{HashMap, HashSet};
 
-pub fn collect_lockguard_info(
+pub fn collect_lockguard_info<'tcx>(
+    tcx: TyCtxt<'tcx>,
     fn_id: LocalDefId,
-    body: &Body,
+    body: &Body<'tcx>,
+    custom_guard_types: &CustomGuardTypes,
+    unknown_guard_move: UnknownGuardMove,
 ) -> HashMap<LockGuardId, LockGuardInfo> {
     let mut lockguards: HashMap<LockGuardId, LockGuardInfo> = HashMap::new();
     for (local, local_decl) in body.local_decls.iter_enumerated() {
-        if let Some(type_name) = parse_lockguard_type(&local_decl.ty) {
+        if let Some(type_name) =
+            parse_lockguard_type_or_custom(tcx, local_decl.ty, custom_guard_types)
+        {
             let lockguard_id = LockGuardId::new(fn_id, local);
             let lockguard_info = LockGuardInfo {
                 type_name,
                 src: None,
+                lock_key: None,
                 span: local_decl.source_info.span,
                 gen_bbs: Vec::new(),
                 kill_bbs: Vec::new(),
@@ -34,16 +48,71 @@ pub fn collect_lockguard_info(
     }
     let mut def_use_analysis = DefUseAnalysis::new(body);
     def_use_analysis.analyze(body);
-    let lockguards = collect_lockguard_src_info(lockguards, body, &def_use_analysis);
-    collect_gen_kill_bbs(lockguards, body, &def_use_analysis)
+    let condvar_waits = condvar_waits(tcx, body);
+    let lockguards = collect_lockguard_src_info(
+        tcx,
+        lockguards,
+        body,
+        &def_use_analysis,
+        &condvar_waits,
+        custom_guard_types,
+    );
+    collect_gen_kill_bbs(
+        tcx,
+        lockguards,
+        body,
+        &def_use_analysis,
+        &condvar_waits,
+        unknown_guard_move,
+    )
+}
+
+/// The lockguard a fn returns still held, with its src tracked the same as a local lockguard.
+/// The return place is not in `collect_lockguard_info` because it has no gen/kill of its own.
+pub fn collect_returned_lockguard<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    fn_id: LocalDefId,
+    body: &Body<'tcx>,
+    custom_guard_types: &CustomGuardTypes,
+) -> Option<LockGuardInfo> {
+    let local_decl = &body.local_decls[RETURN_PLACE];
+    let type_name = parse_lockguard_type_or_custom(tcx, local_decl.ty, custom_guard_types)?;
+    let mut lockguards: HashMap<LockGuardId, LockGuardInfo> = HashMap::new();
+    lockguards.insert(
+        LockGuardId::new(fn_id, RETURN_PLACE),
+        LockGuardInfo {
+            type_name,
+            src: None,
+            lock_key: None,
+            span: local_decl.source_info.span,
+            gen_bbs: Vec::new(),
+            kill_bbs: Vec::new(),
+        },
+    );
+    let mut def_use_analysis = DefUseAnalysis::new(body);
+    def_use_analysis.analyze(body);
+    let condvar_waits = condvar_waits(tcx, body);
+    collect_lockguard_src_info(
+        tcx,
+        lockguards,
+        body,
+        &def_use_analysis,
+        &condvar_waits,
+        custom_guard_types,
+    )
+    .into_iter()
+    .map(|(_, info)| info)
+    .next()
 }
 
 fn batch_gen_depends_for_all<'a, 'b, 'tcx>(
     lockguards: &HashMap<LockGuardId, LockGuardInfo>,
     body: &'a Body<'tcx>,
     def_use_analysis: &'b DefUseAnalysis,
+    condvar_waits: &[CondvarWait<'tcx>],
 ) -> BatchDependResults<'a, 'b, 'tcx> {
-    let mut batch_depend_results = BatchDependResults::new(body, def_use_analysis);
+    let mut batch_depend_results =
+        BatchDependResults::new(body, def_use_analysis, condvar_waits.to_vec());
     for id in lockguards.keys() {
         batch_gen_depends(id.local, &mut batch_depend_results);
     }
@@ -70,29 +139,41 @@ fn batch_gen_depends(local: Local, batch_depend_results: &mut BatchDependResults
     }
 }
 
-fn collect_lockguard_src_info(
+fn collect_lockguard_src_info<'tcx>(
+    tcx: TyCtxt<'tcx>,
     lockguards: HashMap<LockGuardId, LockGuardInfo>,
-    body: &Body,
+    body: &Body<'tcx>,
     def_use_analysis: &DefUseAnalysis,
+    condvar_waits: &[CondvarWait<'tcx>],
+    custom_guard_types: &CustomGuardTypes,
 ) -> HashMap<LockGuardId, LockGuardInfo> {
     if lockguards.is_empty() {
         return lockguards;
     }
-    let batch_depends = batch_gen_depends_for_all(&lockguards, body, def_use_analysis);
+    let batch_depends =
+        batch_gen_depends_for_all(&lockguards, body, def_use_analysis, condvar_waits);
     lockguards
         .into_iter()
         .map(|(id, mut info)| {
+            let guard_ty = body.local_decls[id.local].ty;
+            let custom_guard = custom_guard_types.parse(tcx, guard_ty).map(|(guard, _)| guard);
             let (place, tracker_result) = match info.type_name.0 {
                 LockGuardType::StdMutexGuard | LockGuardType::StdRwLockGuard => {
                     let mut tracker = Tracker::new(Place::from(id.local), true, &batch_depends);
                     tracker.track()
                 }
+                LockGuardType::Custom(_) => {
+                    let contain_result = custom_guard.map_or(false, |guard| guard.result);
+                    let mut tracker =
+                        Tracker::new(Place::from(id.local), contain_result, &batch_depends);
+                    tracker.track()
+                }
                 _ => {
                     let mut tracker = Tracker::new(Place::from(id.local), false, &batch_depends);
                     tracker.track()
                 }
             };
-            info.src = match tracker_result {
+            let (src, src_key) = match tracker_result {
                 TrackerState::ParamSrc => {
                     let fields = place
                         .projection
@@ -107,6 +188,7 @@ fn collect_lockguard_src_info(
                         .fold(String::new(), |acc, field| {
                             acc + &format!("{:?}", field) + ","
                         });
+                    let src_key = param_src_key(tcx, body.local_decls[place.local].ty, &fields);
                     let mut struct_type = body.local_decls[place.local].ty.to_string();
                     if struct_type.starts_with('&') {
                         struct_type = struct_type.chars().skip(1).collect();
@@ -115,13 +197,13 @@ fn collect_lockguard_src_info(
                         struct_type,
                         fields,
                     });
-                    Some(lockguard_src)
+                    (Some(lockguard_src), Some(src_key))
                 }
                 TrackerState::LocalSrc => {
                     let lockguard_src = LockGuardSrc::LocalSrc(LocalSrcContext {
                         place: format!("{:?}", place),
                     });
-                    Some(lockguard_src)
+                    (Some(lockguard_src), None)
                 }
                 TrackerState::WrapperLock => {
                     match body.local_decls[place.local].local_info {
@@ -131,57 +213,108 @@ fn collect_lockguard_src_info(
                         }) => {
                             let lockguard_src =
                                 LockGuardSrc::GlobalSrc(GlobalSrcContext { global_id: def_id });
-                            Some(lockguard_src)
+                            let src_key = SrcKey::GlobalSrc {
+                                global: canonical_def_path(tcx, def_id),
+                            };
+                            (Some(lockguard_src), Some(src_key))
                         }
                         _ => {
                             // TODO(boqin): any other non-static-ref lock wrapper?
-                            None
+                            (None, None)
                         }
                     }
                 }
-                _ => None,
+                _ => (None, None),
             };
+            info.src = src;
+            let data = custom_guard.and_then(|guard| guard.data);
+            info.lock_key = lock_key(tcx, guard_ty, data, src_key);
             (id, info)
         })
         .collect()
 }
 
-fn collect_gen_kill_bbs(
+fn collect_gen_kill_bbs<'tcx>(
+    tcx: TyCtxt<'tcx>,
     lockguards: HashMap<LockGuardId, LockGuardInfo>,
-    _body: &Body,
+    body: &Body<'tcx>,
     def_use_analysis: &DefUseAnalysis,
+    condvar_waits: &[CondvarWait<'tcx>],
+    unknown_guard_move: UnknownGuardMove,
 ) -> HashMap<LockGuardId, LockGuardInfo> {
     if lockguards.is_empty() {
         return lockguards;
     }
+    // the guard moved into a wait of std is killed by the move and held again once unwrapped
+    let reacquired = condvar_waits
+        .iter()
+        .filter_map(|wait| Some((wait.guard, wait.reacquired_guard(body)?)))
+        .collect::<Vec<_>>();
     lockguards
         .into_iter()
         .filter_map(|(id, mut info)| {
             let mut retain = true;
+            let mut leaked = false;
+            // the bbs where another lockguard is moved into this one, e.g. `let g2 = g;`
+            let mut moved_into = Vec::new();
+            let mut storage_live = Vec::new();
+            let mut defined_by_call = false;
             let use_info = def_use_analysis.local_info(id.local);
             for u in &use_info.defs_and_uses {
                 match u.context {
                     PlaceContext::NonUse(context) => match context {
-                        NonUseContext::StorageLive => info.gen_bbs.push(u.location.block),
+                        NonUseContext::StorageLive => storage_live.push(u.location.block),
                         NonUseContext::StorageDead => info.kill_bbs.push(u.location.block),
                         _ => {}
                     },
                     PlaceContext::NonMutatingUse(context) => {
                         if let NonMutatingUseContext::Move = context {
-                            info.kill_bbs.push(u.location.block);
+                            if classify_guard_move(tcx, body, u.location, condvar_waits)
+                                .keeps_held(unknown_guard_move)
+                            {
+                                leaked = true;
+                            } else {
+                                info.kill_bbs.push(u.location.block);
+                            }
                         }
                     }
                     PlaceContext::MutatingUse(context) => match context {
                         MutatingUseContext::Drop => info.kill_bbs.push(u.location.block),
                         MutatingUseContext::Store => {
+                            // held from the move on, the lockguard moved from is killed there
+                            if classify_guard_move(tcx, body, u.location, condvar_waits)
+                                == GuardMove::Moved(id.local)
+                            {
+                                moved_into.push(u.location.block);
+                                continue;
+                            }
                             retain = false;
                             break;
                         }
-                        MutatingUseContext::Call => {}
+                        MutatingUseContext::Call => defined_by_call = true,
                         _ => {}
                     },
                 }
             }
+            // a leaked lockguard is held until the end of the fn
+            if leaked {
+                info.kill_bbs.clear();
+            }
+            // a lockguard only moved into is not held from its StorageLive
+            if defined_by_call || moved_into.is_empty() {
+                info.gen_bbs.extend(storage_live);
+            }
+            info.gen_bbs.extend(moved_into);
+            for (passed, (local, bb)) in &reacquired {
+                if *local != id.local {
+                    continue;
+                }
+                // a new lockguard is not held from its StorageLive before the wait
+                if *passed != Some(id.local) {
+                    info.gen_bbs.clear();
+                }
+                info.gen_bbs.push(*bb);
+            }
             if retain {
                 Some((id, info))
             } else {
@@ -8,6 +8,8 @@
 /// then A depends on B by ref
 /// if A = call func(move B)
 /// then A depends on B by call
+/// if A = call Condvar::wait(C, move B) of std
+/// then A depends on B by wait: A holds the lock of B again
 extern crate rustc_hir;
 extern crate rustc_middle;
 extern crate rustc_mir;
@@ -16,6 +18,8 @@ use rustc_middle::mir::visit::*;
 use rustc_middle::mir::*;
 use rustc_mir::util::def_use::DefUseAnalysis;
 
+use crate::condvar::CondvarWait;
+
 use std::collections::HashMap;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -29,20 +33,27 @@ pub enum DependResult {
     CopyDepend,
     RefDepend,
     CallDepend,
+    WaitDepend,
 }
 
 pub struct BatchDependResults<'a, 'b, 'tcx> {
     depend_query_info: DependQueryInfo<'tcx>,
     pub body: &'a Body<'tcx>,
     def_use_analysis: &'b DefUseAnalysis,
+    condvar_waits: Vec<CondvarWait<'tcx>>,
 }
 
 impl<'a, 'b, 'tcx> BatchDependResults<'a, 'b, 'tcx> {
-    pub fn new(body: &'a Body<'tcx>, def_use_analysis: &'b DefUseAnalysis) -> Self {
+    pub fn new(
+        body: &'a Body<'tcx>,
+        def_use_analysis: &'b DefUseAnalysis,
+        condvar_waits: Vec<CondvarWait<'tcx>>,
+    ) -> Self {
         Self {
             depend_query_info: DependQueryInfo::<'tcx>::new(),
             body,
             def_use_analysis,
+            condvar_waits,
         }
     }
 
@@ -105,6 +116,26 @@ impl<'a, 'b, 'tcx> BatchDependResults<'a, 'b, 'tcx> {
                         if lhs != place {
                             continue;
                         }
+                        // the guard passed to a wait of std is returned holding the same lock
+                        if let Some(wait) = self
+                            .condvar_waits
+                            .iter()
+                            .find(|wait| wait.bb == u.location.block && wait.takes_guard())
+                        {
+                            self.depend_query_info.add_depend(
+                                DependPair(lhs, wait.guard_arg),
+                                DependResult::WaitDepend,
+                            );
+                            continue;
+                        }
+                        // `guard = wait(move guard).unwrap()` locks the same lock again, not another def of it
+                        if let Some(Operand::Move(rhs)) = args.get(0) {
+                            if self.condvar_waits.iter().any(|wait| {
+                                wait.destination == Some(*rhs) && wait.guard == lhs.as_local()
+                            }) {
+                                continue;
+                            }
+                        }
                         // heuristically consider the first move arg to be associated with return.
                         // TODO: check the type relations to decide if they are related.
                         for arg in args {
@@ -1,7 +1,6 @@
 extern crate rustc_hir;
 extern crate rustc_middle;
 
-use super::config::RUN_LIMIT;
 use super::lock::ConflictLockInfo;
 use super::lock::{LockGuardId, LockGuardInfo};
 use rustc_hir::def_id::LocalDefId;
@@ -15,6 +14,7 @@ pub struct GenKill<'a> {
     after: HashMap<BasicBlock, HashSet<LockGuardId>>,
     worklist: Vec<BasicBlock>,
     crate_lockguards: &'a HashMap<LockGuardId, LockGuardInfo>,
+    run_limit: u32,
 }
 
 impl<'a> GenKill<'a> {
@@ -23,6 +23,7 @@ impl<'a> GenKill<'a> {
         body: &Body,
         crate_lockguards: &'a HashMap<LockGuardId, LockGuardInfo>,
         context: &HashSet<LockGuardId>,
+        run_limit: u32,
     ) -> GenKill<'a> {
         let mut gen: HashMap<BasicBlock, HashSet<LockGuardId>> = HashMap::new();
         let mut kill: HashMap<BasicBlock, HashSet<LockGuardId>> = HashMap::new();
@@ -56,12 +57,13 @@ impl<'a> GenKill<'a> {
             after,
             worklist,
             crate_lockguards,
+            run_limit,
         }
     }
     pub fn analyze(&mut self, body: &Body) -> Vec<ConflictLockInfo> {
         let mut conflict_lock_info: Vec<ConflictLockInfo> = Vec::new();
         let mut count: u32 = 0;
-        while !self.worklist.is_empty() && count <= RUN_LIMIT {
+        while !self.worklist.is_empty() && count <= self.run_limit {
             count += 1;
             let cur = self.worklist.pop().unwrap();
             let mut new_before: HashSet<LockGuardId> = HashSet::new();
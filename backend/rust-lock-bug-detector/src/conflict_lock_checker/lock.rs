@@ -2,40 +2,26 @@ extern crate rustc_hir;
 extern crate rustc_middle;
 extern crate rustc_span;
 
-use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_hir::def_id::LocalDefId;
 use rustc_middle::mir::{BasicBlock, Local};
-use rustc_middle::ty::Ty;
+use rustc_middle::ty::{Ty, TyCtxt};
+use rustc_span::symbol::Symbol;
 use rustc_span::Span;
 
+use crate::custom_guard::{CustomGuard, CustomGuardTypes};
+// the srcs are the ones of the double-lock detector, their reports are made alike
+pub use crate::double_lock_checker::lock::{
+    GlobalSrcContext, LocalSrcContext, LockGuardSrc, ParamSrcContext,
+};
+use crate::summary::LockKey;
 use std::hash::Hash;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum LockGuardSrc {
-    ParamSrc(ParamSrcContext),
-    LocalSrc(LocalSrcContext),
-    GlobalSrc(GlobalSrcContext),
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct ParamSrcContext {
-    pub struct_type: String,
-    pub fields: String,
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct LocalSrcContext {
-    pub place: String,
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct GlobalSrcContext {
-    pub global_id: DefId,
-}
-
 #[derive(Debug, Clone)]
 pub struct LockGuardInfo {
     pub type_name: (LockGuardType, String),
     pub src: Option<LockGuardSrc>,
+    /// the crate-independent identity of the lock, see `crate::summary`
+    pub lock_key: Option<LockKey>,
     pub span: Span,
     pub gen_bbs: Vec<BasicBlock>,
     pub kill_bbs: Vec<BasicBlock>,
@@ -66,7 +52,7 @@ impl LockGuardId {
         Self { fn_id, local }
     }
 }
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
 pub enum LockGuardType {
     StdMutexGuard,
     StdRwLockGuard,
@@ -74,6 +60,29 @@ pub enum LockGuardType {
     ParkingLotRwLockGuard,
     SpinMutexGuard,
     SpinRwLockGuard,
+    /// the lock of a guard declared in the config, whatever its kind
+    Custom(Symbol),
+}
+
+impl LockGuardType {
+    /// Parse the `lock_type` of a summary lock, written with the lockguard types of the double-lock detector.
+    pub fn from_summary_name(name: &str) -> Option<Self> {
+        match name {
+            "StdMutexGuard" => Some(LockGuardType::StdMutexGuard),
+            "StdRwLockGuard" | "StdRwLockReadGuard" | "StdRwLockWriteGuard" => {
+                Some(LockGuardType::StdRwLockGuard)
+            }
+            "ParkingLotMutexGuard" => Some(LockGuardType::ParkingLotMutexGuard),
+            "ParkingLotRwLockGuard" | "ParkingLotRwLockReadGuard" | "ParkingLotRwLockWriteGuard" => {
+                Some(LockGuardType::ParkingLotRwLockGuard)
+            }
+            "SpinMutexGuard" => Some(LockGuardType::SpinMutexGuard),
+            "SpinRwLockGuard" | "SpinRwLockReadGuard" | "SpinRwLockWriteGuard" => {
+                Some(LockGuardType::SpinRwLockGuard)
+            }
+            _ => CustomGuard::from_summary_name(name).map(|guard| LockGuardType::Custom(guard.lock)),
+        }
+    }
 }
 
 pub fn parse_lockguard_type(ty: &Ty) -> Option<(LockGuardType, String)> {
@@ -143,6 +152,19 @@ pub fn parse_lockguard_type(ty: &Ty) -> Option<(LockGuardType, String)> {
     }
 }
 
+/// The built-in lockguard types, then the ones declared in the config.
+pub fn parse_lockguard_type_or_custom<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    custom_guard_types: &CustomGuardTypes,
+) -> Option<(LockGuardType, String)> {
+    parse_lockguard_type(&ty).or_else(|| {
+        custom_guard_types
+            .parse(tcx, ty)
+            .map(|(guard, data_type)| (LockGuardType::Custom(guard.lock), data_type))
+    })
+}
+
 fn extract_data_type(lockguard_type: &str, type_name: &str) -> String {
     assert!(type_name.starts_with(lockguard_type) && type_name.ends_with('>'));
     type_name[lockguard_type.len()..type_name.len() - 1].to_string()
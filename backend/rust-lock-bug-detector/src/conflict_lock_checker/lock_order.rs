@@ -0,0 +1,188 @@
+//! Cycle detection on the lock-order graph.
+//! Nodes are indices of locks, an edge a -> b means b is acquired while a is held.
+use std::collections::HashSet;
+
+struct Tarjan<'a> {
+    adj: &'a [Vec<usize>],
+    index: usize,
+    indices: Vec<Option<usize>>,
+    lowlinks: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn strong_connect(&mut self, v: usize) {
+        self.indices[v] = Some(self.index);
+        self.lowlinks[v] = self.index;
+        self.index += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for &w in self.adj[v].iter() {
+            match self.indices[w] {
+                None => {
+                    self.strong_connect(w);
+                    self.lowlinks[v] = self.lowlinks[v].min(self.lowlinks[w]);
+                }
+                Some(w_index) => {
+                    if self.on_stack[w] {
+                        self.lowlinks[v] = self.lowlinks[v].min(w_index);
+                    }
+                }
+            }
+        }
+        if Some(self.lowlinks[v]) == self.indices[v] {
+            let mut scc = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                scc.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+/// Tarjan's strongly connected components of the graph `adj`.
+pub fn strongly_connected_components(adj: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut tarjan = Tarjan {
+        adj,
+        index: 0,
+        indices: vec![None; adj.len()],
+        lowlinks: vec![0; adj.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; adj.len()],
+        sccs: Vec::new(),
+    };
+    for v in 0..adj.len() {
+        if tarjan.indices[v].is_none() {
+            tarjan.strong_connect(v);
+        }
+    }
+    tarjan.sccs
+}
+
+/// Johnson's search for the elementary cycles through `start` in `scc`, past the nodes below `start`.
+/// A node is blocked once on the path and stays blocked until a cycle is found through it,
+/// so the search takes linear time per cycle instead of exploring every path.
+struct Johnson<'a> {
+    adj: &'a [Vec<usize>],
+    scc: &'a HashSet<usize>,
+    start: usize,
+    blocked: Vec<bool>,
+    // the nodes to unblock once the node is
+    blocked_by: Vec<HashSet<usize>>,
+    path: Vec<usize>,
+    cycles: Vec<Vec<usize>>,
+}
+
+impl<'a> Johnson<'a> {
+    fn in_subgraph(&self, v: usize) -> bool {
+        v >= self.start && self.scc.contains(&v)
+    }
+
+    fn unblock(&mut self, v: usize) {
+        self.blocked[v] = false;
+        for w in std::mem::take(&mut self.blocked_by[v]) {
+            if self.blocked[w] {
+                self.unblock(w);
+            }
+        }
+    }
+
+    fn circuit(&mut self, v: usize) -> bool {
+        let adj = self.adj;
+        let mut found = false;
+        self.path.push(v);
+        self.blocked[v] = true;
+        for &w in adj[v].iter() {
+            if !self.in_subgraph(w) {
+                continue;
+            }
+            if w == self.start {
+                self.cycles.push(self.path.clone());
+                found = true;
+            } else if !self.blocked[w] && self.circuit(w) {
+                found = true;
+            }
+        }
+        if found {
+            self.unblock(v);
+        } else {
+            for &w in adj[v].iter() {
+                if self.in_subgraph(w) {
+                    self.blocked_by[w].insert(v);
+                }
+            }
+        }
+        self.path.pop();
+        found
+    }
+}
+
+/// Every elementary cycle of two or more nodes in the graph `adj`, with Johnson's algorithm.
+/// Each cycle is reported once, starting from its smallest node.
+/// Only the nodes of one SCC can form a cycle, so the search is done per SCC.
+pub fn elementary_cycles(adj: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut cycles = Vec::new();
+    for scc in strongly_connected_components(adj) {
+        if scc.len() < 2 {
+            continue;
+        }
+        let mut starts = scc.clone();
+        starts.sort();
+        let scc: HashSet<usize> = scc.into_iter().collect();
+        for start in starts {
+            let mut johnson = Johnson {
+                adj,
+                scc: &scc,
+                start,
+                blocked: vec![false; adj.len()],
+                blocked_by: vec![HashSet::new(); adj.len()],
+                path: Vec::new(),
+                cycles: Vec::new(),
+            };
+            johnson.circuit(start);
+            cycles.extend(johnson.cycles);
+        }
+    }
+    cycles
+}
+
+#[test]
+fn test_elementary_cycles() {
+    // 0 -> 1 -> 2 -> 0, 1 -> 0, 3 -> 3, 2 -> 4
+    let adj = vec![vec![1], vec![0, 2], vec![0, 4], vec![3], vec![]];
+    let mut cycles = elementary_cycles(&adj);
+    cycles.sort();
+    assert_eq!(cycles, vec![vec![0, 1], vec![0, 1, 2]]);
+    let mut sccs = strongly_connected_components(&adj)
+        .into_iter()
+        .map(|mut scc| {
+            scc.sort();
+            scc
+        })
+        .collect::<Vec<_>>();
+    sccs.sort();
+    assert_eq!(sccs, vec![vec![0, 1, 2], vec![3], vec![4]]);
+}
+
+#[test]
+fn test_elementary_cycles_complete_graph() {
+    // every pair of the 4 nodes has edges both ways:
+    // 6 cycles of 2 nodes, 4 * 2 of 3 nodes and 3! of 4 nodes
+    let adj = (0..4)
+        .map(|v| (0..4).filter(|w| *w != v).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut cycles = elementary_cycles(&adj);
+    assert_eq!(cycles.len(), 20);
+    cycles.sort();
+    cycles.dedup();
+    assert_eq!(cycles.len(), 20);
+    assert!(cycles
+        .iter()
+        .all(|cycle| cycle[0] == *cycle.iter().min().unwrap()));
+}
@@ -4,6 +4,9 @@ mod collector;
 mod dataflow;
 mod genkill;
 mod lock;
+mod lock_order;
+mod report;
 mod tracker;
 pub use self::checker::ConflictLockChecker;
+pub use self::report::{ConflictLockBug, LockNode, LockOrderReport};
 use super::config;
@@ -0,0 +1,470 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+use super::lock::{LockGuardInfo, LockGuardSrc, LockGuardType};
+use super::lock_order::elementary_cycles;
+use crate::baseline::{self, Fingerprinter};
+use crate::crate_context::CrateReports;
+use crate::output::{lock_src_info, CrateReport, Findings, LockSrcInfo, ReportSpan, SpanInfo};
+use crate::config::Severities;
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::summary::{SrcKey, SummaryLock};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Where a lock comes from: the src of its lock key if it has one, so that the locks acquired
+/// by extern callees, read from their summaries, are the same nodes as the local ones.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+enum ConflictLockSrc {
+    Key(SrcKey),
+    Src(LockGuardSrc),
+}
+
+impl fmt::Debug for ConflictLockSrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictLockSrc::Key(src) => write!(f, "{:?}", src),
+            ConflictLockSrc::Src(src) => write!(f, "{:?}", src),
+        }
+    }
+}
+
+/// The identity of a lock: two lockguards come from the same lock if they have the same type and src.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone)]
+struct ConflictLock {
+    type_name: (LockGuardType, String),
+    src: ConflictLockSrc,
+}
+
+impl ConflictLock {
+    fn new(info: &LockGuardInfo) -> Option<Self> {
+        match (&info.lock_key, &info.src) {
+            (Some(key), _) => Some(Self {
+                type_name: (info.type_name.0, key.data_type.clone()),
+                src: ConflictLockSrc::Key(key.src.clone()),
+            }),
+            (None, Some(src)) => Some(Self {
+                type_name: info.type_name.clone(),
+                src: ConflictLockSrc::Src(src.clone()),
+            }),
+            // cannot tell which lock it is
+            (None, None) => None,
+        }
+    }
+
+    fn from_summary(lock: &SummaryLock) -> Option<Self> {
+        Some(Self {
+            type_name: (
+                LockGuardType::from_summary_name(&lock.lock_type)?,
+                lock.key.data_type.clone(),
+            ),
+            src: ConflictLockSrc::Key(lock.key.src.clone()),
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct LockOrderSite {
+    first_lock_span: Span,
+    // in another crate if the second lock is read from the summary of an extern callee
+    second_lock_span: ReportSpan,
+}
+
+// the acquisition sites of one edge and the callchains reaching them
+type LockOrderSites = HashMap<LockOrderSite, HashSet<Vec<ReportSpan>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockNode {
+    pub lock_src: LockSrcInfo,
+    pub lock_type: String,
+    pub data_type: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockOrderReport {
+    pub first_lock: SpanInfo,
+    pub second_lock: SpanInfo,
+    pub callchains: Vec<Vec<SpanInfo>>,
+}
+
+/// One cycle in the lock-order graph in the serialized report:
+/// `locks[i + 1]` is acquired while `locks[i]` is held at `edges[i]`,
+/// and the first lock is acquired while the last is held at the last edge.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictLockBug {
+    pub locks: Vec<LockNode>,
+    pub edges: Vec<Vec<LockOrderReport>>,
+    /// identifies the cycle in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct ConflictLockCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    conflict_locks: Vec<ConflictLockBug>,
+    suppressed: usize,
+}
+
+/// The crate-wide lock-order graph.
+/// Nodes are locks, an edge a -> b holds every site where b is acquired while a is held.
+/// Every cycle in the graph is a potential deadlock and is reported once.
+pub struct ConflictLockReports {
+    locks: Vec<ConflictLock>,
+    lock_ids: HashMap<ConflictLock, usize>,
+    edges: HashMap<(usize, usize), LockOrderSites>,
+    // the edges left without sites by the suppressions in source, the cycles through them are suppressed
+    suppressed_edges: HashSet<(usize, usize)>,
+}
+
+impl ConflictLockReports {
+    pub fn new() -> Self {
+        Self {
+            locks: Vec::new(),
+            lock_ids: HashMap::new(),
+            edges: HashMap::new(),
+            suppressed_edges: HashSet::new(),
+        }
+    }
+
+    fn lock_id(&mut self, lock: ConflictLock) -> usize {
+        if let Some(id) = self.lock_ids.get(&lock) {
+            return *id;
+        }
+        let id = self.locks.len();
+        self.locks.push(lock.clone());
+        self.lock_ids.insert(lock, id);
+        id
+    }
+
+    /// Add the edge `pair.0` -> `pair.1`: `pair.1` is acquired while `pair.0` is held.
+    pub fn add(&mut self, pair: (&LockGuardInfo, &LockGuardInfo), callchain: &Vec<Span>) {
+        let (first, second) = match (ConflictLock::new(pair.0), ConflictLock::new(pair.1)) {
+            (Some(first), Some(second)) => (first, second),
+            _ => return,
+        };
+        let site = LockOrderSite {
+            first_lock_span: pair.0.span,
+            second_lock_span: ReportSpan::from(pair.1.span),
+        };
+        let callchain = callchain.iter().map(|span| ReportSpan::from(*span)).collect();
+        self.add_edge(first, second, site, callchain);
+    }
+
+    /// Add the edge `first` -> `second` where `second` is acquired by an extern callee,
+    /// `callchain` ending with the callsite of the extern callee.
+    pub fn add_extern(&mut self, first: &LockGuardInfo, second: &SummaryLock, callchain: &Vec<Span>) {
+        let (first_lock, second_lock) = match (ConflictLock::new(first), ConflictLock::from_summary(second)) {
+            (Some(first_lock), Some(second_lock)) => (first_lock, second_lock),
+            _ => return,
+        };
+        let site = LockOrderSite {
+            first_lock_span: first.span,
+            second_lock_span: ReportSpan::Extern(second.span.clone()),
+        };
+        let callchain = callchain
+            .iter()
+            .map(|span| ReportSpan::from(*span))
+            .chain(second.callchain.iter().map(|call| ReportSpan::Extern(call.span.clone())))
+            .collect();
+        self.add_edge(first_lock, second_lock, site, callchain);
+    }
+
+    fn add_edge(
+        &mut self,
+        first: ConflictLock,
+        second: ConflictLock,
+        site: LockOrderSite,
+        callchain: Vec<ReportSpan>,
+    ) {
+        if first == second {
+            return;
+        }
+        let edge = (self.lock_id(first), self.lock_id(second));
+        self.edges
+            .entry(edge)
+            .or_insert_with(HashMap::new)
+            .entry(site)
+            .or_insert_with(HashSet::new)
+            .insert(callchain);
+    }
+
+    /// Every cycle of the lock-order graph, rotated to start from its smallest lock
+    /// so that the output does not depend on the order the locks were found,
+    /// and the number of cycles through a suppressed edge, which are left out.
+    fn cycles(&self) -> (Vec<Vec<usize>>, usize) {
+        let mut adj = vec![Vec::new(); self.locks.len()];
+        for (first, second) in self.edges.keys().chain(self.suppressed_edges.iter()) {
+            adj[*first].push(*second);
+        }
+        for succs in adj.iter_mut() {
+            succs.sort();
+        }
+        let mut cycles = elementary_cycles(&adj)
+            .into_iter()
+            .map(|mut cycle| {
+                let min = (0..cycle.len())
+                    .min_by(|i, j| self.locks[cycle[*i]].cmp(&self.locks[cycle[*j]]))
+                    .unwrap();
+                cycle.rotate_left(min);
+                cycle
+            })
+            .collect::<Vec<_>>();
+        cycles.sort_by(|a, b| {
+            let a = a.iter().map(|id| &self.locks[*id]).collect::<Vec<_>>();
+            let b = b.iter().map(|id| &self.locks[*id]).collect::<Vec<_>>();
+            a.cmp(&b)
+        });
+        let before = cycles.len();
+        cycles.retain(|cycle| {
+            (0..cycle.len()).all(|i| {
+                !self
+                    .suppressed_edges
+                    .contains(&(cycle[i], cycle[(i + 1) % cycle.len()]))
+            })
+        });
+        let suppressed = before - cycles.len();
+        (cycles, suppressed)
+    }
+
+    fn cycle_edges<'a>(&'a self, cycle: &'a [usize]) -> impl Iterator<Item = &'a LockOrderSites> + 'a {
+        (0..cycle.len()).map(move |i| &self.edges[&(cycle[i], cycle[(i + 1) % cycle.len()])])
+    }
+
+    /// A block for each cycle, with the acquisition sites of each of its edges.
+    pub fn text(
+        &self,
+        cycles: &[Vec<usize>],
+        suppressed: usize,
+        tcx: TyCtxt,
+        fingerprinter: &Fingerprinter,
+    ) -> TextReport {
+        let mut text = TextReport::new("conflict-locks", suppressed);
+        for cycle in cycles {
+            let mut out = format!("ConflictLockReport: cycle of {} locks\n", cycle.len());
+            for (i, id) in cycle.iter().enumerate() {
+                let lock = &self.locks[*id];
+                out += &format!("\tLock{}: {:?} {:?}\n", i, lock.type_name, lock.src);
+            }
+            for (i, sites) in self.cycle_edges(cycle).enumerate() {
+                out += &format!("\tLock{} -> Lock{}:\n", i, (i + 1) % cycle.len());
+                // not in the order of the hash maps, which changes from run to run
+                let mut sites = sites.iter().collect::<Vec<_>>();
+                sites.sort_by_cached_key(|(site, _)| (site.first_lock_span, format!("{:?}", site.second_lock_span)));
+                for (site, chains) in sites {
+                    let mut chains = chains.iter().map(|chain| format!("{:?}", chain)).collect::<Vec<_>>();
+                    chains.sort();
+                    out += &format!("\t{{\tFirstLock: {:?}\n", site.first_lock_span);
+                    out += &format!("\t\tSecondLock: {:?}\n", site.second_lock_span);
+                    out += &format!("\t\tCallchains: [{}]\n\t}}\n", chains.join(", "));
+                }
+            }
+            text.finding(self.bug(cycle, tcx, fingerprinter).fingerprint, out);
+        }
+        text
+    }
+
+    fn bug(&self, cycle: &[usize], tcx: TyCtxt, fingerprinter: &Fingerprinter) -> ConflictLockBug {
+        let source_map = tcx.sess.source_map();
+        let lock_node = |lock: &ConflictLock| LockNode {
+            lock_src: match &lock.src {
+                ConflictLockSrc::Key(src) => src.src_info(),
+                ConflictLockSrc::Src(src) => lock_src_info(src, tcx),
+            },
+            lock_type: format!("{:?}", lock.type_name.0),
+            data_type: lock.type_name.1.clone(),
+        };
+        let order_reports = |sites: &LockOrderSites| {
+            let mut reports = sites
+                .iter()
+                .map(|(site, chains)| {
+                    let mut callchains = chains
+                        .iter()
+                        .map(|chain| {
+                            chain
+                                .iter()
+                                .map(|span| span.resolve(source_map))
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>();
+                    callchains.sort();
+                    LockOrderReport {
+                        first_lock: SpanInfo::new(site.first_lock_span, source_map),
+                        second_lock: site.second_lock_span.resolve(source_map),
+                        callchains,
+                    }
+                })
+                .collect::<Vec<_>>();
+            reports.sort_by(|a, b| (&a.second_lock, &a.first_lock).cmp(&(&b.second_lock, &b.first_lock)));
+            reports
+        };
+        let mut bug = ConflictLockBug {
+            locks: cycle.iter().map(|id| lock_node(&self.locks[*id])).collect(),
+            edges: self.cycle_edges(cycle).map(|sites| order_reports(sites)).collect(),
+            fingerprint: String::new(),
+        };
+        bug.fingerprint = cycle_fingerprint(&bug, fingerprinter);
+        bug
+    }
+
+    /// Resolve spans and lock srcs of `cycles` into serializable findings.
+    pub fn bugs(&self, cycles: &[Vec<usize>], tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<ConflictLockBug> {
+        cycles
+            .iter()
+            .map(|cycle| self.bug(cycle, tcx, fingerprinter))
+            .collect()
+    }
+}
+
+impl CrateReports for ConflictLockReports {
+    type Bug = ConflictLockBug;
+
+    /// Drop the acquisition sites with a lock suppressed in source.
+    /// The cycles through an edge left without sites are gone and counted as suppressed.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        for (edge, sites) in self.edges.iter_mut() {
+            sites.retain(|site, _| {
+                !suppressions.is_suppressed(BugKind::ConflictLock, site.first_lock_span)
+                    && !site
+                        .second_lock_span
+                        .local()
+                        .map_or(false, |span| suppressions.is_suppressed(BugKind::ConflictLock, span))
+            });
+            if sites.is_empty() {
+                self.suppressed_edges.insert(*edge);
+            }
+        }
+        self.edges.retain(|_, sites| !sites.is_empty());
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    /// The cycles are searched once for the text and the serialized findings.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<ConflictLockBug> {
+        let (cycles, suppressed) = self.cycles();
+        Findings {
+            bugs: self.bugs(&cycles, tcx, fingerprinter),
+            suppressed,
+            baselined: Vec::new(),
+            text: self.text(&cycles, suppressed, tcx, fingerprinter),
+        }
+    }
+}
+
+/// A cycle is identified by its edges, each the locks and the positions of its acquisition sites.
+/// The edges are sorted, as the lock a cycle starts from is ordered by MIR places that change with any edit.
+fn cycle_fingerprint(bug: &ConflictLockBug, fingerprinter: &Fingerprinter) -> String {
+    let lock = |node: &LockNode| {
+        format!("{}<{}>:{}", node.lock_type, node.data_type, baseline::lock_src(&node.lock_src))
+    };
+    let mut edges = bug
+        .edges
+        .iter()
+        .enumerate()
+        .map(|(i, reports)| {
+            let mut sites = reports
+                .iter()
+                .map(|report| {
+                    format!(
+                        "{}/{}",
+                        fingerprinter.position(&report.first_lock),
+                        fingerprinter.position(&report.second_lock)
+                    )
+                })
+                .collect::<Vec<_>>();
+            sites.sort();
+            sites.dedup();
+            format!(
+                "{} -> {}: {}",
+                lock(&bug.locks[i]),
+                lock(&bug.locks[(i + 1) % bug.locks.len()]),
+                sites.join(",")
+            )
+        })
+        .collect::<Vec<_>>();
+    edges.sort();
+    fingerprinter.fingerprint("conflict-lock", &edges)
+}
+
+impl CrateReport for Findings<ConflictLockBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = ConflictLockCrateReport {
+            crate_name,
+            severity: severities.conflict_lock.as_str(),
+            conflict_locks: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log, one result per cycle.
+    /// The second lock of the first site of the first edge is the primary location, the other locks
+    /// are related locations, and every callchain of every edge becomes a code flow.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let locks = bug
+                    .locks
+                    .iter()
+                    .map(|lock| format!("{}<{}>", lock.lock_type, lock.data_type))
+                    .collect::<Vec<_>>();
+                let mut related = Vec::new();
+                let mut code_flows = Vec::new();
+                for (i, sites) in bug.edges.iter().enumerate() {
+                    let first = &locks[i];
+                    let second = &locks[(i + 1) % locks.len()];
+                    for (j, site) in sites.iter().enumerate() {
+                        related.push(SarifLocation::new(
+                            site.first_lock.clone(),
+                            format!("{} acquired first", first),
+                        ));
+                        // the second lock of the first site of the first edge is the primary location
+                        if i != 0 || j != 0 {
+                            related.push(SarifLocation::new(
+                                site.second_lock.clone(),
+                                format!("{} acquired while holding {}", second, first),
+                            ));
+                        }
+                        for chain in site.callchains.iter() {
+                            let mut flow = vec![SarifLocation::new(
+                                site.first_lock.clone(),
+                                format!("first lock {}", first),
+                            )];
+                            flow.extend(chain.iter().map(|callsite| {
+                                SarifLocation::new(callsite.clone(), "call while holding the first lock")
+                            }));
+                            flow.push(SarifLocation::new(
+                                site.second_lock.clone(),
+                                format!("second lock {}", second),
+                            ));
+                            code_flows.push(flow);
+                        }
+                    }
+                }
+                let mut order = locks.clone();
+                order.push(locks[0].clone());
+                SarifFinding {
+                    rule: SarifRule::ConflictLock,
+                    message: format!(
+                        "Possible deadlock: locks are acquired in a cyclic order {}",
+                        order.join(" -> ")
+                    ),
+                    primary: SarifLocation::new(
+                        bug.edges[0][0].second_lock.clone(),
+                        format!("{} acquired while holding {}", locks[1], locks[0]),
+                    ),
+                    related,
+                    code_flows,
+                    fingerprint: bug.fingerprint,
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
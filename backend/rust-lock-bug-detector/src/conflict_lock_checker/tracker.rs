@@ -147,7 +147,9 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
         }
         let rhses = self.batch_depend_results.get_depends(place);
         let mut defs = rhses.iter().filter(|(_, result)| {
-            *result == DependResult::CallDepend || *result == DependResult::MoveDepend
+            *result == DependResult::CallDepend
+                || *result == DependResult::MoveDepend
+                || *result == DependResult::WaitDepend
         });
         if defs.clone().count() > 1 {
             return None;
@@ -161,6 +163,11 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
                 self.state = TrackerState::Result;
                 Some(*place)
             }
+            // the guard passed to a condvar wait, tracked on to its lock
+            Some((place, DependResult::WaitDepend)) => {
+                self.state = TrackerState::Guard;
+                Some(*place)
+            }
             _ => None,
         }
     }
@@ -0,0 +1,391 @@
+//! What the detectors share on the crate being compiled, computed once in `analyze` for all of them.
+//! The lock summary of the crate is written from it too, whichever detectors are run.
+//! The detectors passing their guards down the callgraph walk it with `walk_callees`,
+//! and every detector makes its reports into findings with `crate_findings`.
+//!
+//! The conflict-lock detector only shares the fns and the summaries: its collector merges the
+//! read and write lockguards of a `RwLock` into one lock type, so it keeps its own lockguards and callgraph.
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::baseline::Fingerprinter;
+use crate::config::{AnalysisLimits, LockDetectorConfig, LockDetectorType};
+use crate::custom_guard::CustomGuardTypes;
+use crate::double_lock_checker::alias::{ParamBinding, PointsTo};
+use crate::double_lock_checker::callgraph::Callgraph;
+use crate::double_lock_checker::collector::{
+    collect_lockguard_info, collect_refcell_guard_info, collect_returned_lockguard, moved_locals,
+};
+use crate::double_lock_checker::genkill::GenKill;
+use crate::double_lock_checker::lock::{DoubleLockInfo, LockGuardId, LockGuardInfo};
+use crate::output::Findings;
+use crate::summary::{write_crate_summary, LockSummaries, SummaryLockSite};
+use crate::suppression::Suppressions;
+use rustc_hir::def_id::{LocalDefId, LOCAL_CRATE};
+use rustc_middle::mir::{BasicBlock, Body, Local, Operand, TerminatorKind, RETURN_PLACE};
+use rustc_middle::ty::{TyCtxt, TyKind};
+use rustc_span::Span;
+use std::collections::{HashMap, HashSet};
+
+/// The lockguards created in each fn, for the fns creating one.
+pub type FnLockGuards = HashMap<LocalDefId, HashMap<LockGuardId, LockGuardInfo>>;
+
+pub struct CrateContext {
+    /// the fns and closures of the crate
+    pub fn_ids: Vec<LocalDefId>,
+    pub summaries: LockSummaries,
+    /// of the double-lock collector, with the lock keys of the lockguards returned by extern callees;
+    /// empty unless the double-lock, blocking-call or condvar detector is enabled or summaries are written
+    pub lockguards: FnLockGuards,
+    /// the `Ref`s and `RefMut`s, empty unless the double-borrow detector is enabled
+    pub refcell_guards: FnLockGuards,
+    /// of the double-lock detector, empty unless an enabled detector has guards to pass down it
+    pub callgraph: Callgraph,
+}
+
+impl CrateContext {
+    pub fn new(tcx: TyCtxt, config: &LockDetectorConfig) -> Self {
+        let enabled = |detector_type| config.lock_detector_types.contains(&detector_type);
+        let fn_ids: Vec<LocalDefId> = tcx
+            .mir_keys(LOCAL_CRATE)
+            .iter()
+            .cloned()
+            .filter(|id| {
+                let hir = tcx.hir();
+                hir.body_owner_kind(hir.as_local_hir_id(*id))
+                    .is_fn_or_closure()
+            })
+            .collect();
+        let summaries = LockSummaries::load(tcx, &config.summary_dir);
+        let lockguards = if enabled(LockDetectorType::DoubleLockDetector)
+            || enabled(LockDetectorType::BlockingCallDetector)
+            || enabled(LockDetectorType::CondvarDetector)
+            || config.summary_dir.is_some()
+        {
+            let custom_guard_types = CustomGuardTypes::new(&config.guard_types);
+            collect_fn_guards(&fn_ids, |fn_id| {
+                let body = tcx.optimized_mir(fn_id);
+                let mut lockguards = collect_lockguard_info(
+                    tcx,
+                    fn_id,
+                    body,
+                    &custom_guard_types,
+                    config.unknown_guard_move,
+                );
+                let returned_lock_keys = summaries.returned_lock_keys(tcx, body);
+                for (id, info) in lockguards.iter_mut() {
+                    if let Some(key) = returned_lock_keys.get(&id.local) {
+                        info.lock_key = Some(key.clone());
+                    }
+                }
+                lockguards
+            })
+        } else {
+            HashMap::new()
+        };
+        let refcell_guards = if enabled(LockDetectorType::DoubleBorrowDetector) {
+            collect_fn_guards(&fn_ids, |fn_id| {
+                collect_refcell_guard_info(
+                    tcx,
+                    fn_id,
+                    tcx.optimized_mir(fn_id),
+                    config.unknown_guard_move,
+                )
+            })
+        } else {
+            HashMap::new()
+        };
+        // the condvar detector checks each fn on its own
+        let walks_lockguards = enabled(LockDetectorType::DoubleLockDetector)
+            || enabled(LockDetectorType::BlockingCallDetector);
+        let mut callgraph = Callgraph::new();
+        if walks_lockguards && !lockguards.is_empty() || !refcell_guards.is_empty() {
+            if config.link_dyn_calls {
+                callgraph.link_dyn_calls(tcx, &fn_ids);
+            }
+            let mono_map = callgraph.gen_mono(&fn_ids, tcx);
+            for fn_id in &fn_ids {
+                callgraph.generate(*fn_id, &fn_ids, &mono_map, tcx);
+            }
+            callgraph.link_callbacks(tcx, &fn_ids);
+        }
+        Self {
+            fn_ids,
+            summaries,
+            lockguards,
+            refcell_guards,
+            callgraph,
+        }
+    }
+
+    /// Write the lock summary of the crate to the summary dir of `config` for the crates depending on it.
+    /// The double-lock lockguards tell read from write lockguards, unlike the ones of the conflict-lock detector.
+    pub fn write_summary(&self, tcx: TyCtxt, config: &LockDetectorConfig) {
+        let summary_dir = match &config.summary_dir {
+            Some(summary_dir) => summary_dir,
+            None => return,
+        };
+        let custom_guard_types = CustomGuardTypes::new(&config.guard_types);
+        let summary_site = |info: &LockGuardInfo| {
+            info.lock_key.as_ref().map(|key| SummaryLockSite {
+                lock_type: info.type_name.0.summary_name(),
+                key: key.clone(),
+                span: info.span,
+            })
+        };
+        let own: HashMap<LocalDefId, Vec<SummaryLockSite>> = self
+            .lockguards
+            .iter()
+            .map(|(fn_id, lockguards)| {
+                (
+                    *fn_id,
+                    lockguards.values().filter_map(summary_site).collect(),
+                )
+            })
+            .collect();
+        let returned: HashMap<LocalDefId, Vec<SummaryLockSite>> = self
+            .fn_ids
+            .iter()
+            .filter_map(|fn_id| {
+                let body = tcx.optimized_mir(*fn_id);
+                let mut info = collect_returned_lockguard(tcx, *fn_id, body, &custom_guard_types)?;
+                if info.lock_key.is_none() {
+                    info.lock_key = self
+                        .summaries
+                        .returned_lock_keys(tcx, body)
+                        .remove(&RETURN_PLACE);
+                }
+                Some((*fn_id, summary_site(&info).into_iter().collect()))
+            })
+            .collect();
+        write_crate_summary(
+            tcx,
+            summary_dir,
+            &self.fn_ids,
+            &own,
+            &returned,
+            &self.summaries,
+            config.limits,
+        );
+    }
+
+    /// Check `fn_id` and its local callees, each with the `guards` live at its callsite:
+    /// `check_fn` is given each fn with its body, its gen/kill, the double-locks of the gen/kill
+    /// and the callsites from `fn_id`.
+    /// The guards moved into a callee are released with it, and its params are bound to the args of the callsite.
+    /// A fn is visited at most twice, and the callees are left out past the callchain depth.
+    pub fn walk_callees<'tcx>(
+        &self,
+        tcx: TyCtxt<'tcx>,
+        fn_id: LocalDefId,
+        guards: &HashMap<LockGuardId, LockGuardInfo>,
+        limits: AnalysisLimits,
+        mut check_fn: impl FnMut(
+            LocalDefId,
+            &Body<'tcx>,
+            &GenKill,
+            Vec<DoubleLockInfo>,
+            &[(LocalDefId, BasicBlock)],
+        ),
+    ) {
+        let mut worklist = vec![FnGuardContext {
+            fn_id,
+            context: HashSet::new(),
+            callchain: Vec::new(),
+            moved_guards: Vec::new(),
+            bindings: Vec::new(),
+        }];
+        let mut visited: HashMap<LocalDefId, u8> = HashMap::new();
+        visited.insert(fn_id, 1);
+        while let Some(FnGuardContext {
+            fn_id,
+            context,
+            callchain,
+            moved_guards,
+            bindings,
+        }) = worklist.pop()
+        {
+            let body = tcx.optimized_mir(fn_id);
+            let mut genkill = GenKill::new(fn_id, body, guards, &context, limits.run_limit);
+            genkill.kill_moved_guards(fn_id, &moved_guards);
+            genkill.bind_params(&bindings);
+            let double_locks = genkill.analyze(body);
+            check_fn(fn_id, body, &genkill, double_locks, &callchain);
+            if limits.stops_at(callchain.len()) {
+                continue;
+            }
+            let callsites = match self.callgraph.get(&fn_id) {
+                Some(callsites) => callsites,
+                None => continue,
+            };
+            let points_to = PointsTo::new(body);
+            for (bb, callee_id) in callsites {
+                let context = match genkill.get_live_lockguards(bb) {
+                    Some(context) => context,
+                    None => continue,
+                };
+                let times = visited.entry(*callee_id).or_insert(0);
+                if *times >= 2 {
+                    continue;
+                }
+                *times += 1;
+                let mut callchain = callchain.clone();
+                callchain.push((fn_id, *bb));
+                let mut callee_bindings = bindings.clone();
+                callee_bindings.push(points_to.bind_params(
+                    fn_id,
+                    body,
+                    call_args(tcx, body, *bb, *callee_id).unwrap_or(&[]),
+                    tcx.optimized_mir(*callee_id).args_iter(),
+                ));
+                worklist.push(FnGuardContext {
+                    fn_id: *callee_id,
+                    context: context.clone(),
+                    callchain,
+                    moved_guards: bind_moved_guards(
+                        tcx,
+                        fn_id,
+                        body,
+                        *bb,
+                        *callee_id,
+                        context,
+                        &moved_guards,
+                    ),
+                    bindings: callee_bindings,
+                });
+            }
+        }
+    }
+}
+
+/// The spans of the calls of `callchain`.
+pub fn callchain_spans(tcx: TyCtxt, callchain: &[(LocalDefId, BasicBlock)]) -> Vec<Span> {
+    callchain
+        .iter()
+        .map(|(fn_id, bb)| {
+            tcx.optimized_mir(*fn_id).basic_blocks()[*bb]
+                .terminator()
+                .source_info
+                .span
+        })
+        .collect()
+}
+
+/// The reports a detector collects on the crate, made into findings once the crate is checked.
+pub trait CrateReports {
+    type Bug;
+
+    /// Drop the reports suppressed in source, counting them.
+    fn suppress(&mut self, suppressions: &Suppressions);
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<Self::Bug>;
+}
+
+/// The findings of `reports` without the ones suppressed in source.
+pub fn crate_findings<R: CrateReports>(tcx: TyCtxt, reports: &mut R) -> Findings<R::Bug> {
+    reports.suppress(&Suppressions::new(tcx));
+    reports.findings(tcx, &Fingerprinter::new(tcx))
+}
+
+struct FnGuardContext {
+    fn_id: LocalDefId,
+    context: HashSet<LockGuardId>,
+    callchain: Vec<(LocalDefId, BasicBlock)>,
+    /// the params of `fn_id` holding guards of the context moved into them
+    moved_guards: Vec<(Local, LockGuardId)>,
+    /// the args bound to the params of each call on the callchain
+    bindings: Vec<ParamBinding>,
+}
+
+/// The args of the call at `bb` in the order of the params of `callee`,
+/// `None` for a closure, whose args are tupled, and a fn called through a `Fn*` trait.
+fn call_args<'a, 'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &'a Body<'tcx>,
+    bb: BasicBlock,
+    callee: LocalDefId,
+) -> Option<&'a [Operand<'tcx>]> {
+    let (func, args) = match &body.basic_blocks()[bb].terminator().kind {
+        TerminatorKind::Call { func, args, .. } => (func, args),
+        _ => return None,
+    };
+    if tcx.is_closure(callee.to_def_id()) {
+        return None;
+    }
+    if let Operand::Constant(box constant) = func {
+        if let TyKind::FnDef(method, _) = constant.literal.ty.kind {
+            let lang_items = tcx.lang_items();
+            let fn_traits = [
+                lang_items.fn_trait(),
+                lang_items.fn_mut_trait(),
+                lang_items.fn_once_trait(),
+            ];
+            if tcx
+                .trait_of_item(method)
+                .map_or(false, |trait_id| fn_traits.contains(&Some(trait_id)))
+            {
+                return None;
+            }
+        }
+    }
+    Some(args.as_slice())
+}
+
+/// The lockguards of `context` moved into the args of the call at `bb`, with the params of `callee` holding them.
+/// A lockguard moved through the params of `fn_id` is the one of the caller in `moved_guards`.
+fn bind_moved_guards(
+    tcx: TyCtxt,
+    fn_id: LocalDefId,
+    body: &Body,
+    bb: BasicBlock,
+    callee: LocalDefId,
+    context: &HashSet<LockGuardId>,
+    moved_guards: &[(Local, LockGuardId)],
+) -> Vec<(Local, LockGuardId)> {
+    let args = match call_args(tcx, body, bb, callee) {
+        Some(args) => args,
+        None => return Vec::new(),
+    };
+    args.iter()
+        .zip(tcx.optimized_mir(callee).args_iter())
+        .filter_map(|(arg, param)| {
+            let local = match arg {
+                Operand::Move(place) => place.as_local()?,
+                _ => return None,
+            };
+            moved_locals(body, bb, local)
+                .into_iter()
+                .find_map(|local| {
+                    let id = LockGuardId::new(fn_id, local);
+                    if context.contains(&id) {
+                        return Some(id);
+                    }
+                    moved_guards
+                        .iter()
+                        .find(|(moved_param, guard)| {
+                            *moved_param == local && context.contains(guard)
+                        })
+                        .map(|(_, guard)| *guard)
+                })
+                .map(|guard| (param, guard))
+        })
+        .collect()
+}
+
+fn collect_fn_guards(
+    fn_ids: &[LocalDefId],
+    mut collect: impl FnMut(LocalDefId) -> HashMap<LockGuardId, LockGuardInfo>,
+) -> FnLockGuards {
+    fn_ids
+        .iter()
+        .filter_map(|fn_id| {
+            let guards = collect(*fn_id);
+            if guards.is_empty() {
+                None
+            } else {
+                Some((*fn_id, guards))
+            }
+        })
+        .collect()
+}
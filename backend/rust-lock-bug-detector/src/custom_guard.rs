@@ -0,0 +1,105 @@
+//! Lockguard types declared by users in `lock-bug-detect.toml`, e.g. the guards of their own mutex wrappers.
+//! A declared guard is matched by the def path of its type and treated like a built-in guard of its kind.
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::config::{GuardKind, GuardTypeConfig};
+use crate::summary::{canonical_def_path, canonical_ty};
+use rustc_middle::ty::{Ty, TyCtxt, TyKind};
+use rustc_span::symbol::Symbol;
+use std::fmt;
+
+const SUMMARY_PREFIX: &str = "Custom:";
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct CustomGuard {
+    pub guard: Symbol,
+    pub lock: Symbol,
+    pub kind: GuardKind,
+    /// the index of the type argument that is the protected data, the last one if not provided
+    pub data: Option<usize>,
+    /// whether the guard is unwrapped from a `Result`
+    pub result: bool,
+}
+
+impl CustomGuard {
+    pub fn deadlock_with(&self, other: &Self) -> bool {
+        self.lock == other.lock && self.kind.deadlock_with(other.kind)
+    }
+
+    /// The `lock_type` in the summaries, `Custom:<kind>:<lock>`.
+    pub fn summary_name(&self) -> String {
+        format!("{}{}:{}", SUMMARY_PREFIX, self.kind.as_str(), self.lock)
+    }
+
+    /// Parse the `lock_type` of a summary lock. Only the lock and the kind are kept in the summary.
+    pub fn from_summary_name(name: &str) -> Option<Self> {
+        if !name.starts_with(SUMMARY_PREFIX) {
+            return None;
+        }
+        let mut parts = name[SUMMARY_PREFIX.len()..].splitn(2, ':');
+        let kind = GuardKind::parse(parts.next()?)?;
+        let lock = Symbol::intern(parts.next()?);
+        Some(Self {
+            guard: lock,
+            lock,
+            kind,
+            data: None,
+            result: false,
+        })
+    }
+}
+
+// printed as the guard type in the reports
+impl fmt::Debug for CustomGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.guard)
+    }
+}
+
+pub struct CustomGuardTypes {
+    guards: Vec<(String, CustomGuard)>,
+}
+
+impl CustomGuardTypes {
+    pub fn new(configs: &[GuardTypeConfig]) -> Self {
+        let guards = configs
+            .iter()
+            .map(|config| {
+                let guard = Symbol::intern(&config.path);
+                let lock = match &config.lock {
+                    Some(lock) => Symbol::intern(lock),
+                    None => guard,
+                };
+                (
+                    config.path.clone(),
+                    CustomGuard {
+                        guard,
+                        lock,
+                        kind: config.kind,
+                        data: config.data,
+                        result: config.result,
+                    },
+                )
+            })
+            .collect();
+        Self { guards }
+    }
+
+    /// The declared guard of `ty` and its data type, printed with `canonical_ty` as in the summaries.
+    pub fn parse<'tcx>(&self, tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> Option<(CustomGuard, String)> {
+        if self.guards.is_empty() {
+            return None;
+        }
+        let (did, substs) = match ty.kind {
+            TyKind::Adt(adt_def, substs) => (adt_def.did, substs),
+            _ => return None,
+        };
+        let paths = [tcx.def_path_str(did), canonical_def_path(tcx, did)];
+        let (_, guard) = self.guards.iter().find(|(path, _)| paths.contains(path))?;
+        let data_ty = match guard.data {
+            Some(data) => substs.types().nth(data)?,
+            None => substs.types().last()?,
+        };
+        Some((*guard, canonical_ty(tcx, data_ty)))
+    }
+}
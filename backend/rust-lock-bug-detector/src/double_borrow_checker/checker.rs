@@ -0,0 +1,65 @@
+//! `RefCell` double-borrows: `borrow_mut` while a `borrow` or `borrow_mut` of the same cell is live,
+//! which panics with `BorrowMutError` (`BorrowError` the other way around).
+//! `Ref` and `RefMut` are collected as lockguards and checked with the gen/kill of the double-lock detector
+//! down the callgraph with `CrateContext::walk_callees`, within the crate only: no summaries are written or read for them.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use super::config::{AnalysisLimits, CrateFilter, LockDetectorConfig};
+use super::report::{DoubleBorrowBug, DoubleBorrowReports};
+use crate::crate_context::{callchain_spans, crate_findings, CrateContext};
+use crate::double_lock_checker::lock::{DoubleLockInfo, LockGuardId, LockGuardInfo};
+use crate::output::Findings;
+use rustc_hir::def_id::LOCAL_CRATE;
+use rustc_middle::ty::TyCtxt;
+use std::collections::HashMap;
+
+pub struct DoubleBorrowChecker {
+    crate_filter: CrateFilter,
+    limits: AnalysisLimits,
+    crate_guards: HashMap<LockGuardId, LockGuardInfo>,
+    crate_double_borrow_reports: DoubleBorrowReports,
+}
+
+impl DoubleBorrowChecker {
+    pub fn new(config: &LockDetectorConfig) -> Self {
+        Self {
+            crate_filter: config.crate_filter.clone(),
+            limits: config.limits,
+            crate_guards: HashMap::new(),
+            crate_double_borrow_reports: DoubleBorrowReports::new(),
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        tcx: TyCtxt,
+        crate_context: &CrateContext,
+    ) -> Option<Findings<DoubleBorrowBug>> {
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+        if !self.crate_filter.is_checked(&crate_name) {
+            return None;
+        }
+        let guards = &crate_context.refcell_guards;
+        for info in guards.values() {
+            self.crate_guards.extend(info.clone().into_iter());
+        }
+        // the double-locks of the `Ref`s and `RefMut`s live at each callsite are double-borrows
+        let crate_guards = &self.crate_guards;
+        let reports = &mut self.crate_double_borrow_reports;
+        for fn_id in guards.keys() {
+            crate_context.walk_callees(
+                tcx,
+                *fn_id,
+                crate_guards,
+                self.limits,
+                |_, _, _, double_borrows, callchain| {
+                    let callchain = callchain_spans(tcx, callchain);
+                    for DoubleLockInfo { first, second, .. } in double_borrows {
+                        reports.add((&crate_guards[&first], &crate_guards[&second]), &callchain);
+                    }
+                },
+            );
+        }
+        Some(crate_findings(tcx, &mut self.crate_double_borrow_reports))
+    }
+}
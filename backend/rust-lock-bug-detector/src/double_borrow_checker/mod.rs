@@ -0,0 +1,5 @@
+mod checker;
+mod report;
+pub use self::checker::DoubleBorrowChecker;
+pub use self::report::DoubleBorrowBug;
+use super::config;
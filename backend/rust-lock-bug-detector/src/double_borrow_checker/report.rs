@@ -0,0 +1,265 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+use crate::baseline::{self, Fingerprinter};
+use crate::config::Severities;
+use crate::crate_context::CrateReports;
+use crate::double_lock_checker::lock::{LockGuardInfo, LockGuardSrc, LockGuardType};
+use crate::output::{lock_src_info, CrateReport, Findings, LockSite, LockSrcInfo, SpanInfo};
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct DoubleBorrowPair {
+    first_borrow_type_name: (LockGuardType, String),
+    first_borrow_span: Span,
+    second_borrow_type_name: (LockGuardType, String),
+    second_borrow_span: Span,
+}
+
+/// One double-borrow finding in the serialized report.
+#[derive(Debug, Clone, Serialize)]
+pub struct DoubleBorrowBug {
+    pub cell_src: LockSrcInfo,
+    pub first_borrow: LockSite,
+    pub second_borrow: LockSite,
+    pub callchains: Vec<Vec<SpanInfo>>,
+    /// identifies the double-borrow in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct DoubleBorrowCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    double_borrows: Vec<DoubleBorrowBug>,
+    suppressed: usize,
+}
+
+// the RefCell, DoubleBorrowPair, Callchains
+pub struct DoubleBorrowReports {
+    reports: HashMap<LockGuardSrc, HashMap<DoubleBorrowPair, HashSet<Vec<Span>>>>,
+    // the number of double-borrows suppressed in source
+    suppressed: usize,
+}
+
+impl DoubleBorrowReports {
+    pub fn new() -> Self {
+        Self {
+            reports: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    pub fn add(&mut self, pair: (&LockGuardInfo, &LockGuardInfo), callchain: &[Span]) {
+        let src = match &pair.0.src {
+            Some(src) => src.clone(),
+            None => return,
+        };
+        self.reports
+            .entry(src)
+            .or_insert_with(HashMap::new)
+            .entry(DoubleBorrowPair {
+                first_borrow_type_name: pair.0.type_name.clone(),
+                first_borrow_span: pair.0.span,
+                second_borrow_type_name: pair.1.type_name.clone(),
+                second_borrow_span: pair.1.span,
+            })
+            .or_insert_with(HashSet::new)
+            .insert(callchain.to_vec());
+    }
+
+    /// The double-borrows of each cell, a block for each pair of borrows.
+    pub fn text(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> TextReport {
+        let mut text = TextReport::new("double-borrows", self.suppressed);
+        // not in the order of the hash maps, which changes from run to run
+        let mut srcs = self.reports.iter().collect::<Vec<_>>();
+        srcs.sort_by_cached_key(|(src, pairs_chains)| {
+            (
+                pairs_chains.keys().map(|pair| pair.first_borrow_span).min(),
+                format!("{:?}", src),
+            )
+        });
+        for (src, pairs_chains) in srcs {
+            text.heading(0, format!("RefCellSrc: {:?}\n", src));
+            let mut pairs_chains = pairs_chains.iter().collect::<Vec<_>>();
+            pairs_chains.sort_by_key(|(pair, _)| (pair.first_borrow_span, pair.second_borrow_span));
+            for (pair, chains) in pairs_chains {
+                let mut out = format!(
+                    "{{\tFirstBorrow: {:?}\n\t\t{:?}\n",
+                    pair.first_borrow_type_name, pair.first_borrow_span
+                );
+                out += &format!(
+                    "\tSecondBorrow: {:?}\n\t\t{:?}\n",
+                    pair.second_borrow_type_name, pair.second_borrow_span
+                );
+                let mut sorted_chains = chains.iter().collect::<Vec<_>>();
+                sorted_chains.sort();
+                out += &format!("\tCallchains: {:?}\n}}\n", sorted_chains);
+                text.finding(
+                    Self::bug(src, pair, chains, tcx, fingerprinter).fingerprint,
+                    out,
+                );
+            }
+        }
+        text
+    }
+
+    fn bug(
+        src: &LockGuardSrc,
+        pair: &DoubleBorrowPair,
+        chains: &HashSet<Vec<Span>>,
+        tcx: TyCtxt,
+        fingerprinter: &Fingerprinter,
+    ) -> DoubleBorrowBug {
+        let source_map = tcx.sess.source_map();
+        let borrow_site = |type_name: &(LockGuardType, String), span: Span| LockSite {
+            lock_type: format!("{:?}", type_name.0),
+            data_type: type_name.1.clone(),
+            span: SpanInfo::new(span, source_map),
+        };
+        let mut callchains = chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .iter()
+                    .map(|span| SpanInfo::new(*span, source_map))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        callchains.sort();
+        let mut bug = DoubleBorrowBug {
+            cell_src: lock_src_info(src, tcx),
+            first_borrow: borrow_site(&pair.first_borrow_type_name, pair.first_borrow_span),
+            second_borrow: borrow_site(&pair.second_borrow_type_name, pair.second_borrow_span),
+            callchains,
+            fingerprint: String::new(),
+        };
+        bug.fingerprint = fingerprinter.fingerprint(
+            "double-borrow",
+            &[
+                baseline::lock_src(&bug.cell_src),
+                fingerprinter.lock_site(&bug.first_borrow),
+                fingerprinter.lock_site(&bug.second_borrow),
+            ],
+        );
+        bug
+    }
+
+    /// Resolve spans and cell srcs into serializable findings, sorted by location.
+    pub fn bugs(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<DoubleBorrowBug> {
+        let mut bugs = Vec::new();
+        for (src, pairs_chains) in self.reports.iter() {
+            for (pair, chains) in pairs_chains {
+                bugs.push(Self::bug(src, pair, chains, tcx, fingerprinter));
+            }
+        }
+        bugs.sort_by(|a, b| {
+            (&a.second_borrow.span, &a.first_borrow.span)
+                .cmp(&(&b.second_borrow.span, &b.first_borrow.span))
+        });
+        bugs
+    }
+}
+
+impl CrateReports for DoubleBorrowReports {
+    type Bug = DoubleBorrowBug;
+
+    /// Drop the double-borrows with a borrow suppressed in source, counting them.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        for pairs_chains in self.reports.values_mut() {
+            let before = pairs_chains.len();
+            pairs_chains.retain(|pair, _| {
+                !suppressions.is_suppressed(BugKind::DoubleBorrow, pair.first_borrow_span)
+                    && !suppressions.is_suppressed(BugKind::DoubleBorrow, pair.second_borrow_span)
+            });
+            self.suppressed += before - pairs_chains.len();
+        }
+        self.reports
+            .retain(|_, pairs_chains| !pairs_chains.is_empty());
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<DoubleBorrowBug> {
+        Findings {
+            bugs: self.bugs(tcx, fingerprinter),
+            suppressed: self.suppressed,
+            baselined: Vec::new(),
+            text: self.text(tcx, fingerprinter),
+        }
+    }
+}
+
+impl CrateReport for Findings<DoubleBorrowBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = DoubleBorrowCrateReport {
+            crate_name,
+            severity: severities.double_borrow.as_str(),
+            double_borrows: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log, the second borrow being the primary location.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let first = format!(
+                    "{}<{}>",
+                    bug.first_borrow.lock_type, bug.first_borrow.data_type
+                );
+                let second = format!(
+                    "{}<{}>",
+                    bug.second_borrow.lock_type, bug.second_borrow.data_type
+                );
+                let code_flows = bug
+                    .callchains
+                    .iter()
+                    .map(|chain| {
+                        let mut flow = vec![SarifLocation::new(
+                            bug.first_borrow.span.clone(),
+                            format!("first borrow {}", first),
+                        )];
+                        flow.extend(chain.iter().map(|callsite| {
+                            SarifLocation::new(
+                                callsite.clone(),
+                                "call while the first borrow is live",
+                            )
+                        }));
+                        flow.push(SarifLocation::new(
+                            bug.second_borrow.span.clone(),
+                            format!("second borrow {}", second),
+                        ));
+                        flow
+                    })
+                    .collect();
+                SarifFinding {
+                    rule: SarifRule::DoubleBorrow,
+                    message: format!(
+                        "Possible double-borrow: {} of a RefCell is taken while {} of it is live",
+                        second, first
+                    ),
+                    primary: SarifLocation::new(
+                        bug.second_borrow.span,
+                        format!("second borrow {}", second),
+                    ),
+                    related: vec![SarifLocation::new(
+                        bug.first_borrow.span,
+                        format!("first borrow {}", first),
+                    )],
+                    code_flows,
+                    fingerprint: bug.fingerprint,
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
@@ -0,0 +1,533 @@
+//! An intraprocedural points-to analysis over MIR places that gives each lock an abstract object.
+//! Two lockguards of the same abstract object are of the same lock (must-alias).
+//! Objects that cannot overlap, e.g. two locals, or a local and the object a param points to,
+//! are different locks. Any other two, e.g. the objects two `&Foo` params point to, may be the same lock (may-alias).
+//! The analysis is flow-insensitive: a local points to whatever any of its assignments makes it point to.
+//! It assumes callees do not store the address of a local into memory reachable from a param.
+extern crate rustc_hir;
+extern crate rustc_middle;
+
+use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_middle::mir::{
+    Body, Local, LocalInfo, LocalKind, Operand, Place, ProjectionElem, Rvalue, StatementKind,
+    TerminatorKind,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Pointers loaded through more derefs than this point to an unknown object.
+const MAX_DEREF_DEPTH: usize = 4;
+/// A local that may point to more objects than this points to an unknown object.
+const MAX_POINTS_TO: usize = 16;
+const MAX_ITERATIONS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AbstractRoot {
+    /// the storage of a local
+    Local(Local),
+    /// the storage of a param passed by value
+    Param(Local),
+    Static(DefId),
+    /// the object pointed to by the pointer stored at a place
+    Pointee(Box<AbstractPlace>),
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AbstractElem {
+    Field(usize),
+    Variant(usize),
+    /// any element of an array or slice
+    Index,
+}
+
+/// A place in an abstract object: the object and the fields projected into it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AbstractPlace {
+    pub root: AbstractRoot,
+    pub elems: Vec<AbstractElem>,
+}
+
+impl AbstractPlace {
+    fn new(root: AbstractRoot) -> Self {
+        Self {
+            root,
+            elems: Vec::new(),
+        }
+    }
+
+    fn project(&self, elem: AbstractElem) -> Self {
+        let mut place = self.clone();
+        place.elems.push(elem);
+        place
+    }
+
+    fn pointee(self) -> Self {
+        if self.deref_depth() >= MAX_DEREF_DEPTH {
+            AbstractPlace::new(AbstractRoot::Unknown)
+        } else {
+            AbstractPlace::new(AbstractRoot::Pointee(Box::new(self)))
+        }
+    }
+
+    fn deref_depth(&self) -> usize {
+        match &self.root {
+            AbstractRoot::Pointee(place) => place.deref_depth() + 1,
+            _ => 0,
+        }
+    }
+
+    /// Whether the object existed before the fn was called, so it is not a local of the fn.
+    fn from_caller(&self) -> bool {
+        match &self.root {
+            AbstractRoot::Param(_) | AbstractRoot::Static(_) => true,
+            AbstractRoot::Pointee(place) => place.from_caller(),
+            AbstractRoot::Local(_) | AbstractRoot::Unknown => false,
+        }
+    }
+
+    fn is_global(&self) -> bool {
+        match &self.root {
+            AbstractRoot::Static(_) => true,
+            AbstractRoot::Pointee(place) => place.is_global(),
+            _ => false,
+        }
+    }
+
+    /// Whether the place is the storage of a local or by-value param of the fn.
+    fn is_frame(&self) -> bool {
+        matches!(self.root, AbstractRoot::Local(_) | AbstractRoot::Param(_))
+    }
+
+    fn is_exact(&self) -> bool {
+        self.root != AbstractRoot::Unknown && !self.elems.contains(&AbstractElem::Index)
+    }
+
+    /// The places of the caller of `binding` this place of the callee may be,
+    /// `None` if it is in the storage of a local of the callee.
+    fn bind(&self, binding: &ParamBinding) -> Option<HashSet<AbstractPlace>> {
+        let places = match &self.root {
+            AbstractRoot::Local(_) => return None,
+            AbstractRoot::Param(param) => binding
+                .params
+                .get(param)
+                .map_or_else(unknown, |arg| arg.places.clone()),
+            AbstractRoot::Pointee(place) => match place.root {
+                AbstractRoot::Param(param) if place.elems.is_empty() => binding
+                    .params
+                    .get(&param)
+                    .map_or_else(unknown, |arg| arg.pointees.clone()),
+                // a pointer stored in a local of the callee may point anywhere in the caller
+                _ => place.bind(binding).map_or_else(unknown, |places| {
+                    places.into_iter().map(AbstractPlace::pointee).collect()
+                }),
+            },
+            AbstractRoot::Static(_) | AbstractRoot::Unknown => {
+                let mut places = HashSet::new();
+                places.insert(AbstractPlace::new(self.root.clone()));
+                places
+            }
+        };
+        Some(
+            self.elems
+                .iter()
+                .fold(places, |places, elem| project(places, *elem)),
+        )
+    }
+
+    fn may_alias(&self, other: &Self, escaped: bool) -> bool {
+        if self.root == other.root {
+            return self.elems.len() == other.elems.len()
+                && self.elems.iter().zip(other.elems.iter()).all(|(a, b)| {
+                    a == b || *a == AbstractElem::Index || *b == AbstractElem::Index
+                });
+        }
+        match (&self.root, &other.root) {
+            (AbstractRoot::Unknown, _) | (_, AbstractRoot::Unknown) => true,
+            (AbstractRoot::Pointee(_), AbstractRoot::Pointee(_))
+            | (AbstractRoot::Pointee(_), AbstractRoot::Static(_))
+            | (AbstractRoot::Static(_), AbstractRoot::Pointee(_)) => true,
+            // an object of the caller does not point into the frame of the fn, unless the fn stores an address there
+            (AbstractRoot::Pointee(_), _) => escaped || !self.from_caller(),
+            (_, AbstractRoot::Pointee(_)) => escaped || !other.from_caller(),
+            // distinct locals, params and statics
+            _ => false,
+        }
+    }
+}
+
+/// Whether two lockguards are of the same lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Alias {
+    Must,
+    May,
+    No,
+}
+
+/// The abstract places a lock may be, in the fn `fn_id`.
+#[derive(Debug, Clone)]
+pub struct LockObject {
+    pub fn_id: LocalDefId,
+    pub places: Vec<AbstractPlace>,
+    /// the fn stores the address of a local into memory, so a pointer may point into its frame
+    pub escaped: bool,
+}
+
+impl LockObject {
+    /// `None` if the objects are in different fns, where only statics can be compared.
+    pub fn alias(&self, other: &Self) -> Option<Alias> {
+        if self.fn_id != other.fn_id
+            && !(self.places.iter().all(AbstractPlace::is_global)
+                && other.places.iter().all(AbstractPlace::is_global))
+        {
+            return None;
+        }
+        if let ([place], [other_place]) = (self.places.as_slice(), other.places.as_slice()) {
+            if place == other_place && place.is_exact() {
+                return Some(Alias::Must);
+            }
+        }
+        let escaped = self.escaped || other.escaped;
+        if self.places.iter().any(|place| {
+            other
+                .places
+                .iter()
+                .any(|other_place| place.may_alias(other_place, escaped))
+        }) {
+            Some(Alias::May)
+        } else {
+            Some(Alias::No)
+        }
+    }
+
+    /// The object in the frame of the caller of `binding`.
+    /// A lock in a local of the callee is none of the locks of the caller, so it has no places there.
+    pub fn bind(&self, binding: &ParamBinding) -> LockObject {
+        let places = self
+            .places
+            .iter()
+            .filter_map(|place| place.bind(binding))
+            .flatten()
+            .collect();
+        LockObject {
+            fn_id: binding.caller,
+            places: cap(places).into_iter().collect(),
+            escaped: self.escaped || binding.escaped,
+        }
+    }
+}
+
+/// An arg of a call in the frame of the caller.
+#[derive(Debug, Clone)]
+struct BoundArg {
+    /// the places the arg is read from
+    places: HashSet<AbstractPlace>,
+    /// the places the arg points to, if it is a pointer
+    pointees: HashSet<AbstractPlace>,
+}
+
+/// The args of a call bound to the params of the callee, to rewrite the places of the callee in the frame of the caller.
+#[derive(Debug, Clone)]
+pub struct ParamBinding {
+    pub caller: LocalDefId,
+    params: HashMap<Local, BoundArg>,
+    escaped: bool,
+}
+
+/// The abstract places each local of a fn may point to.
+pub struct PointsTo {
+    points_to: HashMap<Local, HashSet<AbstractPlace>>,
+    escaped: bool,
+    converged: bool,
+}
+
+impl PointsTo {
+    pub fn new(body: &Body) -> Self {
+        let mut points_to = Self {
+            points_to: HashMap::new(),
+            escaped: false,
+            converged: false,
+        };
+        for arg in body.args_iter() {
+            let pointee = opaque(body, arg);
+            points_to.insert(arg, pointee);
+        }
+        for _ in 0..MAX_ITERATIONS {
+            let mut changed = false;
+            for data in body.basic_blocks().iter() {
+                for stmt in data.statements.iter() {
+                    if let StatementKind::Assign(box (lhs, rvalue)) = &stmt.kind {
+                        changed |= points_to.assign(body, *lhs, rvalue);
+                    }
+                }
+                // the result of a call points to an object only known by the local it is stored in
+                if let TerminatorKind::Call {
+                    destination: Some((lhs, _)),
+                    ..
+                } = &data.terminator().kind
+                {
+                    if let Some(local) = lhs.as_local() {
+                        let pointee = opaque(body, local);
+                        changed |= points_to.insert(local, pointee);
+                    }
+                }
+            }
+            if !changed {
+                points_to.converged = true;
+                break;
+            }
+        }
+        points_to
+    }
+
+    /// The lock of the place a lockguard is tracked to, `derefs` pointers away from it.
+    pub fn lock_object(
+        &self,
+        fn_id: LocalDefId,
+        body: &Body,
+        place: Place,
+        derefs: usize,
+    ) -> LockObject {
+        let mut places = self.resolve(body, place);
+        for _ in 0..derefs {
+            places = self.deref(places);
+        }
+        if !self.converged || places.is_empty() {
+            places = unknown();
+        }
+        LockObject {
+            fn_id,
+            places: places.into_iter().collect(),
+            escaped: self.escaped,
+        }
+    }
+
+    /// Bind `args` of a call in the fn `caller` to the `params` of the callee.
+    pub fn bind_params<'tcx>(
+        &self,
+        caller: LocalDefId,
+        body: &Body<'tcx>,
+        args: &[Operand<'tcx>],
+        params: impl Iterator<Item = Local>,
+    ) -> ParamBinding {
+        let params = if self.converged {
+            params
+                .zip(args.iter())
+                .map(|(param, arg)| {
+                    let arg = match arg {
+                        Operand::Copy(place) | Operand::Move(place) => BoundArg {
+                            places: self.resolve(body, *place),
+                            pointees: self.load(body, *place),
+                        },
+                        Operand::Constant(_) => BoundArg {
+                            places: unknown(),
+                            pointees: unknown(),
+                        },
+                    };
+                    (param, arg)
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        ParamBinding {
+            caller,
+            params,
+            escaped: self.escaped,
+        }
+    }
+
+    /// The abstract places `place` may be.
+    fn resolve(&self, body: &Body, place: Place) -> HashSet<AbstractPlace> {
+        let mut places = HashSet::new();
+        places.insert(AbstractPlace::new(frame_root(body, place.local)));
+        for elem in place.projection.iter() {
+            places = match elem {
+                ProjectionElem::Deref => self.deref(places),
+                ProjectionElem::Field(field, _) => {
+                    project(places, AbstractElem::Field(field.index()))
+                }
+                ProjectionElem::Downcast(_, variant) => {
+                    project(places, AbstractElem::Variant(variant.index()))
+                }
+                ProjectionElem::Index(_)
+                | ProjectionElem::ConstantIndex { .. }
+                | ProjectionElem::Subslice { .. } => project(places, AbstractElem::Index),
+            };
+        }
+        places
+    }
+
+    /// The places pointed to by the pointers stored at `places`.
+    fn deref(&self, places: HashSet<AbstractPlace>) -> HashSet<AbstractPlace> {
+        let mut pointees = HashSet::new();
+        for place in places {
+            match place.root {
+                AbstractRoot::Local(local) | AbstractRoot::Param(local)
+                    if place.elems.is_empty() =>
+                {
+                    pointees.extend(self.points_to.get(&local).cloned().unwrap_or_default())
+                }
+                _ => {
+                    pointees.insert(place.pointee());
+                }
+            }
+        }
+        cap(pointees)
+    }
+
+    /// The places the value read from `place` may point to.
+    fn load(&self, body: &Body, place: Place) -> HashSet<AbstractPlace> {
+        match place.as_local() {
+            Some(local) => self.points_to.get(&local).cloned().unwrap_or_default(),
+            None => self.deref(self.resolve(body, place)),
+        }
+    }
+
+    fn assign(&mut self, body: &Body, lhs: Place, rvalue: &Rvalue) -> bool {
+        let values = match rvalue {
+            Rvalue::Ref(_, _, place) | Rvalue::AddressOf(_, place) => self.resolve(body, *place),
+            Rvalue::Use(Operand::Copy(place))
+            | Rvalue::Use(Operand::Move(place))
+            | Rvalue::Cast(_, Operand::Copy(place), _)
+            | Rvalue::Cast(_, Operand::Move(place), _) => self.load(body, *place),
+            Rvalue::Aggregate(_, operands) => {
+                // a struct holding the address of a local
+                let escaped = operands.iter().any(|operand| match operand {
+                    Operand::Copy(place) | Operand::Move(place) => {
+                        self.load(body, *place).iter().any(AbstractPlace::is_frame)
+                    }
+                    Operand::Constant(_) => false,
+                });
+                self.escaped |= escaped;
+                match lhs.as_local() {
+                    Some(local) => opaque(body, local),
+                    None => HashSet::new(),
+                }
+            }
+            _ => match lhs.as_local() {
+                Some(local) => static_ref(body, local).unwrap_or_else(|| opaque(body, local)),
+                None => HashSet::new(),
+            },
+        };
+        match lhs.as_local() {
+            Some(local) => self.insert(local, values),
+            None => {
+                // stored into memory
+                if !self.escaped && values.iter().any(AbstractPlace::is_frame) {
+                    self.escaped = true;
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    fn insert(&mut self, local: Local, places: HashSet<AbstractPlace>) -> bool {
+        let points_to = self.points_to.entry(local).or_default();
+        let unknown_place = AbstractPlace::new(AbstractRoot::Unknown);
+        if points_to.contains(&unknown_place) {
+            return false;
+        }
+        let len = points_to.len();
+        points_to.extend(places);
+        if points_to.len() > MAX_POINTS_TO {
+            *points_to = unknown();
+        }
+        points_to.len() != len
+    }
+}
+
+/// The storage of `local` in the frame of the fn.
+fn frame_root(body: &Body, local: Local) -> AbstractRoot {
+    if body.local_kind(local) == LocalKind::Arg {
+        AbstractRoot::Param(local)
+    } else {
+        AbstractRoot::Local(local)
+    }
+}
+
+/// The object a local points to when nothing more is known, e.g. a param or the result of a call.
+fn opaque(body: &Body, local: Local) -> HashSet<AbstractPlace> {
+    let mut places = HashSet::new();
+    places.insert(AbstractPlace::new(frame_root(body, local)).pointee());
+    places
+}
+
+/// The static `local` is a reference to, e.g. `_3 = const {alloc0: &Mutex<i32>}`.
+fn static_ref(body: &Body, local: Local) -> Option<HashSet<AbstractPlace>> {
+    match body.local_decls[local].local_info {
+        Some(box LocalInfo::StaticRef { def_id, .. }) => {
+            let mut places = HashSet::new();
+            places.insert(AbstractPlace::new(AbstractRoot::Static(def_id)));
+            Some(places)
+        }
+        _ => None,
+    }
+}
+
+fn project(places: HashSet<AbstractPlace>, elem: AbstractElem) -> HashSet<AbstractPlace> {
+    places.iter().map(|place| place.project(elem)).collect()
+}
+
+fn cap(places: HashSet<AbstractPlace>) -> HashSet<AbstractPlace> {
+    if places.len() > MAX_POINTS_TO {
+        unknown()
+    } else {
+        places
+    }
+}
+
+fn unknown() -> HashSet<AbstractPlace> {
+    let mut places = HashSet::new();
+    places.insert(AbstractPlace::new(AbstractRoot::Unknown));
+    places
+}
+
+#[test]
+fn test_lock_object_alias() {
+    let fn_id = LocalDefId {
+        local_def_index: rustc_hir::def_id::CRATE_DEF_INDEX,
+    };
+    let param_field = |param: usize| {
+        AbstractPlace::new(AbstractRoot::Param(Local::from_usize(param)))
+            .pointee()
+            .project(AbstractElem::Field(0))
+    };
+    let object = |places: Vec<AbstractPlace>| LockObject {
+        fn_id,
+        places,
+        escaped: false,
+    };
+    let a_mu = object(vec![param_field(1)]);
+    let b_mu = object(vec![param_field(2)]);
+    let local_mu = object(vec![AbstractPlace::new(AbstractRoot::Local(
+        Local::from_usize(3),
+    ))
+    .project(AbstractElem::Field(0))]);
+    assert_eq!(a_mu.alias(&a_mu), Some(Alias::Must));
+    assert_eq!(a_mu.alias(&b_mu), Some(Alias::May));
+    assert_eq!(a_mu.alias(&local_mu), Some(Alias::No));
+    // `helper(&self.a)` locking `.b` of its param is `self.a.b`
+    let self_a = param_field(1);
+    let mut params = HashMap::new();
+    params.insert(
+        Local::from_usize(1),
+        BoundArg {
+            places: unknown(),
+            pointees: vec![self_a.clone()].into_iter().collect(),
+        },
+    );
+    let binding = ParamBinding {
+        caller: fn_id,
+        params,
+        escaped: false,
+    };
+    let self_a_b = object(vec![self_a.project(AbstractElem::Field(1))]);
+    let helper_b = object(vec![AbstractPlace::new(AbstractRoot::Param(
+        Local::from_usize(1),
+    ))
+    .pointee()
+    .project(AbstractElem::Field(1))]);
+    assert_eq!(helper_b.bind(&binding).alias(&self_a_b), Some(Alias::Must));
+    assert_eq!(local_mu.bind(&binding).alias(&self_a_b), Some(Alias::No));
+}
@@ -2,6 +2,8 @@ extern crate rustc_hir;
 extern crate rustc_middle;
 extern crate rustc_mir;
 
+use crate::callback::callback_edges;
+use crate::trait_call::{trait_callees, TraitImpls};
 use rustc_hir::def_id::{DefId, LocalDefId, LOCAL_CRATE};
 use rustc_middle::mir::visit::*;
 use rustc_middle::mir::*;
@@ -9,23 +11,84 @@ use rustc_middle::ty::*;
 use rustc_mir::util::def_use::DefUseAnalysis;
 use std::collections::{HashMap, HashSet};
 pub struct Callgraph {
-    pub direct: HashMap<LocalDefId, HashMap<BasicBlock, LocalDefId>>,
+    /// a callsite of a generic or `dyn Trait` call may have more than one callee
+    pub direct: HashMap<LocalDefId, HashMap<BasicBlock, Vec<LocalDefId>>>,
+    /// the local impls `dyn Trait` calls are linked to, `dyn Trait` calls are dropped if not provided
+    dyn_impls: Option<TraitImpls>,
 }
 
 impl Callgraph {
     pub fn new() -> Self {
         Self {
             direct: HashMap::new(),
+            dyn_impls: None,
+        }
+    }
+
+    /// Conservatively link every `dyn Trait` call to all the local impls of the method.
+    pub fn link_dyn_calls(&mut self, tcx: TyCtxt, crate_fn_ids: &[LocalDefId]) {
+        self.dyn_impls = Some(TraitImpls::new(tcx, crate_fn_ids));
+    }
+
+    /// Link the calls of fn pointer and `Fn*` params to the local callbacks passed to them, see `callback`.
+    pub fn link_callbacks(&mut self, tcx: TyCtxt, crate_fn_ids: &[LocalDefId]) {
+        for (caller, bb, callback) in callback_edges(tcx, crate_fn_ids) {
+            if caller != callback {
+                self.insert_direct(caller, bb, callback);
+            }
         }
     }
 
     fn insert_direct(&mut self, caller: LocalDefId, bb: BasicBlock, callee: LocalDefId) {
-        if let Some(callees) = self.direct.get_mut(&caller) {
-            callees.insert(bb, callee);
-        } else {
-            let mut callees: HashMap<BasicBlock, LocalDefId> = HashMap::new();
-            callees.insert(bb, callee);
-            self.direct.insert(caller, callees);
+        let callees = self
+            .direct
+            .entry(caller)
+            .or_insert_with(HashMap::new)
+            .entry(bb)
+            .or_insert_with(Vec::new);
+        if !callees.contains(&callee) {
+            callees.push(callee);
+        }
+    }
+
+    /// Link the trait method calls in the generic `callee`, whose impls depend on its type params,
+    /// to the impls they resolve to with the concrete `substs` of one call of it.
+    fn link_substituted_trait_calls<'tcx>(
+        &mut self,
+        callee: LocalDefId,
+        substs: SubstsRef<'tcx>,
+        crate_fn_ids: &[LocalDefId],
+        tcx: TyCtxt<'tcx>,
+    ) {
+        let body = tcx.optimized_mir(callee);
+        for (bb, bb_data) in body.basic_blocks().iter_enumerated() {
+            if let TerminatorKind::Call {
+                func: Operand::Constant(box constant),
+                ..
+            } = &bb_data.terminator().kind
+            {
+                if let TyKind::FnDef(method, method_substs) = constant.literal.ty.kind {
+                    if tcx.trait_of_item(method).is_none()
+                        || !method_substs.has_param_types_or_consts()
+                    {
+                        continue;
+                    }
+                    let method_substs = tcx.subst_and_normalize_erasing_regions(
+                        substs,
+                        ParamEnv::reveal_all(),
+                        &method_substs,
+                    );
+                    for impl_fn in trait_callees(
+                        tcx,
+                        method,
+                        method_substs,
+                        crate_fn_ids,
+                        self.dyn_impls.as_ref(),
+                    ) {
+                        self.insert_direct(callee, bb, impl_fn);
+                    }
+                }
+            }
         }
     }
 
@@ -239,6 +302,17 @@ impl Callgraph {
                                         }
                                         continue;
                                     }
+                                    if !substs.is_empty()
+                                        && !tcx.is_closure(callee_def_id)
+                                        && crate_fn_ids.contains(&local_callee_def_id)
+                                    {
+                                        self.link_substituted_trait_calls(
+                                            local_callee_def_id,
+                                            substs,
+                                            crate_fn_ids,
+                                            tcx,
+                                        );
+                                    }
                                     if let Ok(Some(instance)) = Instance::resolve(
                                         tcx,
                                         ParamEnv::reveal_all(),
@@ -339,6 +413,21 @@ impl Callgraph {
             if let TerminatorKind::Call { ref func, .. } = terminator.kind {
                 if let Operand::Constant(box constant) = func {
                     match constant.literal.ty.kind {
+                        // statically dispatched trait calls, e.g. `<Local as Iterator>::next`
+                        TyKind::FnDef(callee_def_id, substs)
+                            if tcx.trait_of_item(callee_def_id).is_some()
+                                && !substs.has_param_types_or_consts() =>
+                        {
+                            for callee in trait_callees(
+                                tcx,
+                                callee_def_id,
+                                substs,
+                                crate_fn_ids,
+                                self.dyn_impls.as_ref(),
+                            ) {
+                                self.insert_direct(caller, bb, callee);
+                            }
+                        }
                         TyKind::FnDef(callee_def_id, substs)
                         | TyKind::Closure(callee_def_id, substs) => {
                             if let Some(local_callee_def_id) = callee_def_id.as_local() {
@@ -410,10 +499,18 @@ impl Callgraph {
         }
     }
 
-    pub fn get(&self, fn_id: &LocalDefId) -> Option<&HashMap<BasicBlock, LocalDefId>> {
+    /// Every callsite of `fn_id` with each of its callees.
+    pub fn get(
+        &self,
+        fn_id: &LocalDefId,
+    ) -> Option<impl Iterator<Item = (&BasicBlock, &LocalDefId)>> {
         if let Some(callsites) = self.direct.get(fn_id) {
             if !callsites.is_empty() {
-                return Some(callsites);
+                return Some(
+                    callsites
+                        .iter()
+                        .flat_map(|(bb, callees)| callees.iter().map(move |callee| (bb, callee))),
+                );
             } else {
                 return None;
             }
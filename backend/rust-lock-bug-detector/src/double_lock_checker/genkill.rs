@@ -1,11 +1,11 @@
 extern crate rustc_hir;
 extern crate rustc_middle;
 
-use super::config::RUN_LIMIT;
+use super::alias::{Alias, ParamBinding};
 use super::lock::DoubleLockInfo;
 use super::lock::{LockGuardId, LockGuardInfo};
 use rustc_hir::def_id::LocalDefId;
-use rustc_middle::mir::{BasicBlock, Body, START_BLOCK};
+use rustc_middle::mir::{BasicBlock, Body, Local, START_BLOCK};
 use std::collections::HashMap;
 use std::collections::HashSet;
 pub struct GenKill<'a> {
@@ -15,6 +15,9 @@ pub struct GenKill<'a> {
     after: HashMap<BasicBlock, HashSet<LockGuardId>>,
     worklist: Vec<BasicBlock>,
     crate_lockguards: &'a HashMap<LockGuardId, LockGuardInfo>,
+    run_limit: u32,
+    /// the args bound to the params of each call on the callchain, the last one of the call to the fn analyzed
+    bindings: &'a [ParamBinding],
 }
 
 impl<'a> GenKill<'a> {
@@ -23,6 +26,7 @@ impl<'a> GenKill<'a> {
         body: &Body,
         crate_lockguards: &'a HashMap<LockGuardId, LockGuardInfo>,
         context: &HashSet<LockGuardId>,
+        run_limit: u32,
     ) -> GenKill<'a> {
         let mut gen: HashMap<BasicBlock, HashSet<LockGuardId>> = HashMap::new();
         let mut kill: HashMap<BasicBlock, HashSet<LockGuardId>> = HashMap::new();
@@ -56,12 +60,35 @@ impl<'a> GenKill<'a> {
             after,
             worklist,
             crate_lockguards,
+            run_limit,
+            bindings: &[],
         }
     }
+
+    /// Compare the lockguards of the fn analyzed with the ones of its callers in the frames of the callers,
+    /// rewriting the places of the callee rooted at its params with the args of each call in `bindings`.
+    pub fn bind_params(&mut self, bindings: &'a [ParamBinding]) {
+        self.bindings = bindings;
+    }
+
+    /// Kill the lockguards of the callers moved into the params of `fn_id` where the params are released.
+    pub fn kill_moved_guards(&mut self, fn_id: LocalDefId, moved_guards: &[(Local, LockGuardId)]) {
+        for (param, guard) in moved_guards {
+            if let Some(param_guard) = self.crate_lockguards.get(&LockGuardId::new(fn_id, *param)) {
+                for bb in param_guard.kill_bbs.iter() {
+                    self.kill
+                        .entry(*bb)
+                        .or_insert_with(HashSet::new)
+                        .insert(*guard);
+                }
+            }
+        }
+    }
+
     pub fn analyze(&mut self, body: &Body) -> Vec<DoubleLockInfo> {
         let mut double_lock_bugs: Vec<DoubleLockInfo> = Vec::new();
         let mut count: u32 = 0;
-        while !self.worklist.is_empty() && count <= RUN_LIMIT {
+        while !self.worklist.is_empty() && count <= self.run_limit {
             count += 1;
             let cur = self.worklist.pop().unwrap();
             let mut new_before: HashSet<LockGuardId> = HashSet::new();
@@ -105,6 +132,12 @@ impl<'a> GenKill<'a> {
         }
         None
     }
+
+    /// The lockguards live at the terminator of `bb`, after the gen and kill of `bb`.
+    pub fn get_live_lockguards_after(&self, bb: &BasicBlock) -> Option<&HashSet<LockGuardId>> {
+        self.after.get(bb).filter(|context| !context.is_empty())
+    }
+
     fn union_gen_set(
         &self,
         new_before: &mut HashSet<LockGuardId>,
@@ -113,15 +146,16 @@ impl<'a> GenKill<'a> {
         let mut double_locks: Vec<DoubleLockInfo> = Vec::new();
         for first in new_before.iter() {
             for second in lockguards.iter() {
-                if self
-                    .crate_lockguards
-                    .get(first)
-                    .unwrap()
-                    .deadlock_with(self.crate_lockguards.get(second).unwrap())
-                {
+                let first_info = self.crate_lockguards.get(first).unwrap();
+                let alias = match self.bind_lockguard(second, first.fn_id) {
+                    Some(second_info) => first_info.deadlock_with(&second_info),
+                    None => first_info.deadlock_with(self.crate_lockguards.get(second).unwrap()),
+                };
+                if alias != Alias::No {
                     double_locks.push(DoubleLockInfo {
                         first: *first,
                         second: *second,
+                        alias,
                     });
                 }
             }
@@ -130,6 +164,24 @@ impl<'a> GenKill<'a> {
         double_locks
     }
 
+    /// The lockguard `id` of the fn analyzed, with its lock rewritten in the frame of `caller` on the callchain.
+    fn bind_lockguard(&self, id: &LockGuardId, caller: LocalDefId) -> Option<LockGuardInfo> {
+        if id.fn_id == caller {
+            return None;
+        }
+        let info = self.crate_lockguards.get(id).unwrap();
+        let mut object = info.lock_object.clone()?;
+        for binding in self.bindings.iter().rev() {
+            object = object.bind(binding);
+            if binding.caller == caller {
+                let mut info = info.clone();
+                info.lock_object = Some(object);
+                return Some(info);
+            }
+        }
+        None
+    }
+
     fn kill_kill_set(
         &self,
         new_before: &mut HashSet<LockGuardId>,
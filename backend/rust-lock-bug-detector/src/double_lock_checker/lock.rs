@@ -4,30 +4,33 @@ extern crate rustc_span;
 
 use rustc_hir::def_id::{DefId, LocalDefId};
 use rustc_middle::mir::{BasicBlock, Local};
-use rustc_middle::ty::Ty;
+use rustc_middle::ty::{Ty, TyCtxt};
 use rustc_span::Span;
 
+use super::alias::{Alias, LockObject};
+use crate::custom_guard::{CustomGuard, CustomGuardTypes};
+use crate::summary::{LockKey, SummaryLock};
 use std::hash::Hash;
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum LockGuardSrc {
     ParamSrc(ParamSrcContext),
     LocalSrc(LocalSrcContext),
     GlobalSrc(GlobalSrcContext),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct ParamSrcContext {
     pub struct_type: String,
     pub fields: String,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct LocalSrcContext {
     pub place: String,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct GlobalSrcContext {
     pub global_id: DefId,
 }
@@ -36,6 +39,10 @@ pub struct GlobalSrcContext {
 pub struct LockGuardInfo {
     pub type_name: (LockGuardType, String),
     pub src: Option<LockGuardSrc>,
+    /// the crate-independent identity of the lock, see `crate::summary`
+    pub lock_key: Option<LockKey>,
+    /// the abstract object of the lock within its fn, see `super::alias`
+    pub lock_object: Option<LockObject>,
     pub span: Span,
     pub gen_bbs: Vec<BasicBlock>,
     pub kill_bbs: Vec<BasicBlock>,
@@ -57,18 +64,51 @@ impl PartialEq for LockGuardInfo {
 }
 
 impl LockGuardInfo {
-    pub fn deadlock_with(&self, other: &Self) -> bool {
-        self.type_name.0.deadlock_with(&other.type_name.0)
-            && self.type_name.1 == other.type_name.1
-            && if let Some(self_src) = &self.src {
-                if let Some(other_src) = &other.src {
-                    *self_src == *other_src
-                } else {
-                    false
-                }
-            } else {
-                false
+    /// Whether acquiring `other` while `self` is held deadlocks: `Must` if both are of the same abstract object,
+    /// `May` if they are only of the same src (the same type and fields), `No` otherwise.
+    pub fn deadlock_with(&self, other: &Self) -> Alias {
+        if !self.type_name.0.deadlock_with(&other.type_name.0) {
+            return Alias::No;
+        }
+        // the abstract objects tell apart the locks of the same src within a fn
+        if let (Some(self_object), Some(other_object)) = (&self.lock_object, &other.lock_object) {
+            match self_object.alias(other_object) {
+                Some(Alias::Must) => return Alias::Must,
+                Some(Alias::No) => return Alias::No,
+                Some(Alias::May) | None => {}
             }
+        }
+        // a lockguard returned by an extern fn only knows its lock from the summary
+        let same_src = match (&self.lock_key, &other.lock_key) {
+            (Some(self_key), Some(other_key)) => self_key == other_key,
+            _ => {
+                self.type_name.1 == other.type_name.1
+                    && if let Some(self_src) = &self.src {
+                        if let Some(other_src) = &other.src {
+                            *self_src == *other_src
+                        } else {
+                            false
+                        }
+                    } else {
+                        false
+                    }
+            }
+        };
+        if same_src {
+            Alias::May
+        } else {
+            Alias::No
+        }
+    }
+
+    /// Whether a lock acquired by an extern callee, as read from its summary, deadlocks with `self`.
+    pub fn deadlock_with_summary(&self, lock: &SummaryLock) -> bool {
+        match (&self.lock_key, LockGuardType::from_summary_name(&lock.lock_type)) {
+            (Some(self_key), Some(lock_type)) => {
+                self.type_name.0.deadlock_with(&lock_type) && *self_key == lock.key
+            }
+            _ => false,
+        }
     }
 }
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
@@ -93,6 +133,12 @@ pub enum LockGuardType {
     SpinMutexGuard,
     SpinRwLockReadGuard,
     SpinRwLockWriteGuard,
+    /// declared in the config
+    Custom(CustomGuard),
+    /// `std::cell::Ref`, only collected by the double-borrow detector
+    RefCellRef,
+    /// `std::cell::RefMut`, only collected by the double-borrow detector
+    RefCellRefMut,
 }
 
 impl LockGuardType {
@@ -115,6 +161,43 @@ impl LockGuardType {
                 *other == LockGuardType::SpinRwLockReadGuard
                     || *other == LockGuardType::SpinRwLockWriteGuard
             }
+            LockGuardType::Custom(guard) => match other {
+                LockGuardType::Custom(other) => guard.deadlock_with(other),
+                _ => false,
+            },
+            // any number of `Ref`s, or one `RefMut`
+            LockGuardType::RefCellRef => *other == LockGuardType::RefCellRefMut,
+            LockGuardType::RefCellRefMut => {
+                *other == LockGuardType::RefCellRef || *other == LockGuardType::RefCellRefMut
+            }
+        }
+    }
+
+    /// The `lock_type` in the summaries.
+    pub fn summary_name(&self) -> String {
+        match self {
+            LockGuardType::Custom(guard) => guard.summary_name(),
+            _ => format!("{:?}", self),
+        }
+    }
+
+    /// Parse the `lock_type` of a summary lock.
+    /// Summaries written by earlier versions with the lockguard types of the conflict-lock detector
+    /// do not tell read from write guards, which are taken as write guards.
+    pub fn from_summary_name(name: &str) -> Option<Self> {
+        match name {
+            "StdMutexGuard" => Some(LockGuardType::StdMutexGuard),
+            "StdRwLockReadGuard" => Some(LockGuardType::StdRwLockReadGuard),
+            "StdRwLockWriteGuard" | "StdRwLockGuard" => Some(LockGuardType::StdRwLockWriteGuard),
+            "ParkingLotMutexGuard" => Some(LockGuardType::ParkingLotMutexGuard),
+            "ParkingLotRwLockReadGuard" => Some(LockGuardType::ParkingLotRwLockReadGuard),
+            "ParkingLotRwLockWriteGuard" | "ParkingLotRwLockGuard" => {
+                Some(LockGuardType::ParkingLotRwLockWriteGuard)
+            }
+            "SpinMutexGuard" => Some(LockGuardType::SpinMutexGuard),
+            "SpinRwLockReadGuard" => Some(LockGuardType::SpinRwLockReadGuard),
+            "SpinRwLockWriteGuard" | "SpinRwLockGuard" => Some(LockGuardType::SpinRwLockWriteGuard),
+            _ => CustomGuard::from_summary_name(name).map(LockGuardType::Custom),
         }
     }
 }
@@ -186,6 +269,37 @@ pub fn parse_lockguard_type(ty: &Ty) -> Option<(LockGuardType, String)> {
     }
 }
 
+/// The built-in lockguard types, then the ones declared in the config.
+pub fn parse_lockguard_type_or_custom<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    ty: Ty<'tcx>,
+    custom_guard_types: &CustomGuardTypes,
+) -> Option<(LockGuardType, String)> {
+    parse_lockguard_type(&ty).or_else(|| {
+        custom_guard_types
+            .parse(tcx, ty)
+            .map(|(guard, data_type)| (LockGuardType::Custom(guard), data_type))
+    })
+}
+
+/// `Ref` and `RefMut` behave like the read and write guards of a `RwLock` that panics instead of blocking.
+pub fn parse_refcell_guard_type(ty: &Ty) -> Option<(LockGuardType, String)> {
+    let type_name = ty.to_string();
+    if type_name.starts_with("std::cell::Ref<") {
+        Some((
+            LockGuardType::RefCellRef,
+            extract_data_type("std::cell::Ref<", &type_name),
+        ))
+    } else if type_name.starts_with("std::cell::RefMut<") {
+        Some((
+            LockGuardType::RefCellRefMut,
+            extract_data_type("std::cell::RefMut<", &type_name),
+        ))
+    } else {
+        None
+    }
+}
+
 fn extract_data_type(lockguard_type: &str, type_name: &str) -> String {
     assert!(type_name.starts_with(lockguard_type) && type_name.ends_with('>'));
     type_name[lockguard_type.len()..type_name.len() - 1].to_string()
@@ -206,7 +320,17 @@ fn test_extract_data_type() {
         ) == "parking_lot::raw_mutex::RawMutex, i32"
     );
 }
+
+#[test]
+fn test_refcell_deadlock_with() {
+    assert!(!LockGuardType::RefCellRef.deadlock_with(&LockGuardType::RefCellRef));
+    assert!(LockGuardType::RefCellRef.deadlock_with(&LockGuardType::RefCellRefMut));
+    assert!(LockGuardType::RefCellRefMut.deadlock_with(&LockGuardType::RefCellRef));
+    assert!(LockGuardType::RefCellRefMut.deadlock_with(&LockGuardType::RefCellRefMut));
+    assert!(!LockGuardType::RefCellRefMut.deadlock_with(&LockGuardType::StdRwLockWriteGuard));
+}
 pub struct DoubleLockInfo {
     pub first: LockGuardId,
     pub second: LockGuardId,
+    pub alias: Alias,
 }
@@ -1,10 +1,12 @@
-mod callgraph;
+pub(crate) mod alias;
+pub(crate) mod callgraph;
 mod checker;
-mod collector;
+pub(crate) mod collector;
 mod dataflow;
-mod genkill;
-mod lock;
+pub(crate) mod genkill;
+pub(crate) mod lock;
 mod tracker;
-mod report;
+pub(crate) mod report;
 pub use self::checker::DoubleLockChecker;
+pub use self::report::{CallchainInfo, DoubleLockBug};
 use super::config;
@@ -1,50 +1,347 @@
+extern crate rustc_middle;
 extern crate rustc_span;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use super::alias::Alias;
 use super::lock::{LockGuardType, LockGuardSrc, LockGuardInfo};
+use crate::baseline::{self, Fingerprinter};
+use crate::crate_context::CrateReports;
+use crate::output::{
+    lock_src_info, CallSiteInfo, CrateReport, Findings, LockSite, LockSrcInfo, ReportCallSite,
+    ReportSpan, SpanInfo,
+};
+use crate::config::Severities;
+use crate::results::TextReport;
+use crate::sarif::{sarif_log, SarifFinding, SarifLocation, SarifRule};
+use crate::summary::{LockKey, SummaryLock};
+use crate::suppression::{BugKind, Suppressions};
+use rustc_middle::ty::TyCtxt;
 use rustc_span::Span;
+use serde::Serialize;
 #[derive(PartialEq, Eq, Hash, Debug)]
 struct DoubleLockPair {
     first_lock_type_name: (LockGuardType, String),
     first_lock_span: Span,
     second_lock_type_name: (LockGuardType, String),
-    second_lock_span: Span,
+    // in another crate if the second lock is read from the summary of an extern callee
+    second_lock_span: ReportSpan,
+    // Must if both lockguards are of the same abstract object, May if only of the same src
+    alias: Alias,
 }
-// LockGuardSrc, DoubleLockPair, Callchains
+
+/// The lock of a double-lock: the src of the first lockguard,
+/// or its lock key if the lockguard is returned by an extern fn and the src is unknown.
+#[derive(PartialEq, Eq, Hash)]
+enum DoubleLockSrc {
+    Src(LockGuardSrc),
+    Key(LockKey),
+}
+
+impl fmt::Debug for DoubleLockSrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoubleLockSrc::Src(src) => write!(f, "{:?}", src),
+            DoubleLockSrc::Key(key) => write!(f, "{:?}", key.src),
+        }
+    }
+}
+
+/// How a double-lock is reached: the entry fn the analysis started from, the calls from it,
+/// and the fn acquiring the second lock, which is the last callee or the entry fn itself.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Callchain {
+    pub entry_fn: String,
+    pub calls: Vec<ReportCallSite>,
+    pub second_lock_fn: String,
+}
+
+impl fmt::Debug for Callchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "from {}: {:?}, second lock in {}", self.entry_fn, self.calls, self.second_lock_fn)
+    }
+}
+
+/// Serializable form of `Callchain`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct CallchainInfo {
+    pub entry_fn: String,
+    pub calls: Vec<CallSiteInfo>,
+    pub second_lock_fn: String,
+}
+
+/// One double-lock finding in the serialized report.
+#[derive(Debug, Clone, Serialize)]
+pub struct DoubleLockBug {
+    /// "must" if both locks are the same object, "may" if they are only of the same type and field
+    pub alias: &'static str,
+    pub lock_src: LockSrcInfo,
+    pub first_lock: LockSite,
+    pub second_lock: LockSite,
+    pub callchains: Vec<CallchainInfo>,
+    /// identifies the double-lock in a baseline
+    pub fingerprint: String,
+}
+
+#[derive(Serialize)]
+struct DoubleLockCrateReport<'a> {
+    crate_name: &'a str,
+    severity: &'static str,
+    double_locks: Vec<DoubleLockBug>,
+    suppressed: usize,
+}
+
+// DoubleLockSrc, DoubleLockPair, Callchains
 pub struct DoubleLockReports {
-    reports: HashMap<LockGuardSrc, HashMap<DoubleLockPair, HashSet<Vec<Span>>>>,
+    reports: HashMap<DoubleLockSrc, HashMap<DoubleLockPair, HashSet<Callchain>>>,
+    // the number of double-locks suppressed in source
+    suppressed: usize,
 }
 
 impl DoubleLockReports {
     pub fn new() -> Self {
         Self {
             reports: HashMap::new(),
+            suppressed: 0,
         }
     }
 
-    pub fn add(&mut self, pair: (&LockGuardInfo, &LockGuardInfo), callchain: &Vec<Span>) {
-        let src1 = pair.0.src.as_ref().unwrap().clone();
-        let src2 = pair.1.src.as_ref().unwrap().clone();
-        assert!(src1 == src2);
-        self.reports.entry(src1).or_insert(HashMap::new()).entry(DoubleLockPair {
+    pub fn add(&mut self, pair: (&LockGuardInfo, &LockGuardInfo), alias: Alias, callchain: Callchain) {
+        self.insert(pair.0, DoubleLockPair {
             first_lock_type_name: pair.0.type_name.clone(),
             first_lock_span: pair.0.span,
             second_lock_type_name: pair.1.type_name.clone(),
-            second_lock_span: pair.1.span,
-        }).or_insert(HashSet::new()).insert(callchain.clone());
+            second_lock_span: ReportSpan::from(pair.1.span),
+            alias,
+        }, callchain);
+    }
+
+    /// Add a double-lock whose second lock is acquired by an extern callee,
+    /// `callchain` ending with the call of the extern callee, continued with the calls of its summary.
+    /// The locks are only known to be of the same lock key, so it is a may-alias double-lock.
+    pub fn add_extern(&mut self, first: &LockGuardInfo, second: &SummaryLock, mut callchain: Callchain) {
+        let second_lock_type = match LockGuardType::from_summary_name(&second.lock_type) {
+            Some(lock_type) => lock_type,
+            None => return,
+        };
+        callchain.calls.extend(second.callchain.iter().cloned().map(ReportCallSite::from));
+        if let Some(call) = second.callchain.last() {
+            callchain.second_lock_fn = call.callee.clone();
+        }
+        self.insert(first, DoubleLockPair {
+            first_lock_type_name: first.type_name.clone(),
+            first_lock_span: first.span,
+            second_lock_type_name: (second_lock_type, second.key.data_type.clone()),
+            second_lock_span: ReportSpan::Extern(second.span.clone()),
+            alias: Alias::May,
+        }, callchain);
+    }
+
+    fn insert(&mut self, first: &LockGuardInfo, pair: DoubleLockPair, callchain: Callchain) {
+        let src = match (&first.src, &first.lock_key) {
+            (Some(src), _) => DoubleLockSrc::Src(src.clone()),
+            (None, Some(key)) => DoubleLockSrc::Key(key.clone()),
+            _ => return,
+        };
+        self.reports.entry(src).or_insert(HashMap::new()).entry(pair).or_insert(HashSet::new()).insert(callchain);
     }
 
     pub fn _print(&self) {
         println!("{:#?}", self.reports);
     }
 
-    pub fn pretty_print(&self) {
+    /// The must-alias double-locks, then the may-alias ones, in the order of their locks.
+    pub fn text(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> TextReport {
+        let mut text = TextReport::new("double-locks", self.suppressed);
+        // not in the order of the hash maps, which changes from run to run
+        let mut srcs = self.reports.iter().collect::<Vec<_>>();
+        srcs.sort_by_cached_key(|(src, pairs_chains)| {
+            (pairs_chains.keys().map(|pair| pair.first_lock_span).min(), format!("{:?}", src))
+        });
+        for (alias, title) in &[
+            (Alias::Must, "Must-alias double-locks"),
+            (Alias::May, "May-alias double-locks (same lock type and field)"),
+        ] {
+            text.heading(0, format!("{}:\n", title));
+            for (src, pairs_chains) in srcs.iter() {
+                let mut pairs_chains = pairs_chains.iter().filter(|(pair, _)| pair.alias == *alias).collect::<Vec<_>>();
+                pairs_chains.sort_by_cached_key(|(pair, _)| (pair.first_lock_span, format!("{:?}", pair.second_lock_span)));
+                text.heading(1, format!("LockGuardSrc: {:?}\n", src));
+                for (pair, chains) in pairs_chains {
+                    let mut out = format!("{{\tFirstLock: {:?}\n\t\t{:?}\n", pair.first_lock_type_name, pair.first_lock_span);
+                    out += &format!("\tSecondLock: {:?}\n\t\t{:?}\n", pair.second_lock_type_name, pair.second_lock_span);
+                    out += "\tCallchains:\n";
+                    let mut sorted_chains = chains.iter().map(|chain| format!("{:?}", chain)).collect::<Vec<_>>();
+                    sorted_chains.sort();
+                    for chain in sorted_chains {
+                        out += &format!("\t\t{}\n", chain);
+                    }
+                    out += "}\n";
+                    text.finding(Self::bug(src, pair, chains, tcx, fingerprinter).fingerprint, out);
+                }
+            }
+        }
+        text
+    }
+
+    fn bug(
+        src: &DoubleLockSrc,
+        pair: &DoubleLockPair,
+        chains: &HashSet<Callchain>,
+        tcx: TyCtxt,
+        fingerprinter: &Fingerprinter,
+    ) -> DoubleLockBug {
+        let source_map = tcx.sess.source_map();
+        let mut callchains = chains
+            .iter()
+            .map(|chain| CallchainInfo {
+                entry_fn: chain.entry_fn.clone(),
+                calls: chain.calls.iter().map(|call| call.resolve(source_map)).collect(),
+                second_lock_fn: chain.second_lock_fn.clone(),
+            })
+            .collect::<Vec<_>>();
+        callchains.sort();
+        let mut bug = DoubleLockBug {
+            alias: match pair.alias {
+                Alias::Must => "must",
+                _ => "may",
+            },
+            lock_src: match src {
+                DoubleLockSrc::Src(src) => lock_src_info(src, tcx),
+                DoubleLockSrc::Key(key) => key.src.src_info(),
+            },
+            first_lock: LockSite {
+                lock_type: format!("{:?}", pair.first_lock_type_name.0),
+                data_type: pair.first_lock_type_name.1.clone(),
+                span: SpanInfo::new(pair.first_lock_span, source_map),
+            },
+            second_lock: LockSite {
+                lock_type: format!("{:?}", pair.second_lock_type_name.0),
+                data_type: pair.second_lock_type_name.1.clone(),
+                span: pair.second_lock_span.resolve(source_map),
+            },
+            callchains,
+            fingerprint: String::new(),
+        };
+        bug.fingerprint = fingerprinter.fingerprint(
+            "double-lock",
+            &[
+                bug.alias.to_string(),
+                baseline::lock_src(&bug.lock_src),
+                fingerprinter.lock_site(&bug.first_lock),
+                fingerprinter.lock_site(&bug.second_lock),
+            ],
+        );
+        bug
+    }
+
+    /// Resolve spans and lock srcs into serializable findings, sorted by location.
+    pub fn bugs(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Vec<DoubleLockBug> {
+        let mut bugs = Vec::new();
         for (src, pairs_chains) in self.reports.iter() {
-            println!("LockGuardSrc: {:?}", src);
             for (pair, chains) in pairs_chains {
-                println!("{{\tFirstLock: {:?}\n\t\t{:?}", pair.first_lock_type_name, pair.first_lock_span);
-                println!("\tSecondLock: {:?}\n\t\t{:?}", pair.second_lock_type_name, pair.second_lock_span);
-                println!("\tCallchains: {:?}\n}}", chains);
+                bugs.push(Self::bug(src, pair, chains, tcx, fingerprinter));
             }
         }
+        // "must" before "may"
+        bugs.sort_by(|a, b| {
+            b.alias.cmp(a.alias).then_with(|| {
+                (&a.second_lock.span, &a.first_lock.span).cmp(&(&b.second_lock.span, &b.first_lock.span))
+            })
+        });
+        bugs
     }
-}
\ No newline at end of file
+}
+
+impl CrateReports for DoubleLockReports {
+    type Bug = DoubleLockBug;
+
+    /// Drop the double-locks with a lock suppressed in source, counting them.
+    fn suppress(&mut self, suppressions: &Suppressions) {
+        for pairs_chains in self.reports.values_mut() {
+            let before = pairs_chains.len();
+            pairs_chains.retain(|pair, _| {
+                !suppressions.is_suppressed(BugKind::DoubleLock, pair.first_lock_span)
+                    && !pair
+                        .second_lock_span
+                        .local()
+                        .map_or(false, |span| suppressions.is_suppressed(BugKind::DoubleLock, span))
+            });
+            self.suppressed += before - pairs_chains.len();
+        }
+        self.reports.retain(|_, pairs_chains| !pairs_chains.is_empty());
+    }
+
+    /// The findings left after the suppressions, resolved against the source map.
+    fn findings(&self, tcx: TyCtxt, fingerprinter: &Fingerprinter) -> Findings<DoubleLockBug> {
+        Findings {
+            bugs: self.bugs(tcx, fingerprinter),
+            suppressed: self.suppressed,
+            baselined: Vec::new(),
+            text: self.text(tcx, fingerprinter),
+        }
+    }
+}
+
+impl CrateReport for Findings<DoubleLockBug> {
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String {
+        let report = DoubleLockCrateReport {
+            crate_name,
+            severity: severities.double_lock.as_str(),
+            double_locks: self.bugs.clone(),
+            suppressed: self.suppressed,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    /// The crate report as a SARIF log.
+    /// Each callchain becomes a code flow from the first lock through the callsites to the second lock.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String {
+        let findings = self
+            .bugs
+            .iter()
+            .cloned()
+            .map(|bug| {
+                let first_lock = format!("{}<{}>", bug.first_lock.lock_type, bug.first_lock.data_type);
+                let second_lock = format!("{}<{}>", bug.second_lock.lock_type, bug.second_lock.data_type);
+                let code_flows = bug
+                    .callchains
+                    .iter()
+                    .map(|chain| {
+                        let mut flow = vec![SarifLocation::new(
+                            bug.first_lock.span.clone(),
+                            format!("first lock {}", first_lock),
+                        )];
+                        flow.extend(chain.calls.iter().map(|call| {
+                            SarifLocation::new(
+                                call.span.clone(),
+                                format!("{} calls {} while holding the first lock", call.caller, call.callee),
+                            )
+                        }));
+                        flow.push(SarifLocation::new(
+                            bug.second_lock.span.clone(),
+                            format!("second lock {} in {}", second_lock, chain.second_lock_fn),
+                        ));
+                        flow
+                    })
+                    .collect();
+                let message = if bug.alias == "must" {
+                    format!("Double-lock: {} is acquired while {} of the same lock is held", second_lock, first_lock)
+                } else {
+                    format!(
+                        "Possible double-lock: {} is acquired while {} of a lock of the same type and field is held",
+                        second_lock, first_lock
+                    )
+                };
+                SarifFinding {
+                    rule: SarifRule::DoubleLock,
+                    message,
+                    primary: SarifLocation::new(bug.second_lock.span, format!("second lock {}", second_lock)),
+                    related: vec![SarifLocation::new(bug.first_lock.span, format!("first lock {}", first_lock))],
+                    code_flows,
+                    fingerprint: bug.fingerprint,
+                }
+            })
+            .collect::<Vec<_>>();
+        sarif_log(crate_name, &findings, self.suppressed, severities)
+    }
+}
@@ -18,6 +18,12 @@ pub struct Tracker<'a, 'b, 'c, 'tcx> {
     place: Place<'tcx>,
     contain_result: bool,
     batch_depend_results: &'c BatchDependResults<'a, 'b, 'tcx>,
+    /// the number of wrapper locks (e.g. `Arc<Mutex<T>>`) derefed to get to the lock
+    wrappers: usize,
+    /// tracked to a reference to the lock
+    lock_ref: bool,
+    /// tracked to the borrowed lock itself
+    borrowed: bool,
 }
 
 impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
@@ -31,6 +37,21 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
             place,
             contain_result,
             batch_depend_results,
+            wrappers: 0,
+            lock_ref: false,
+            borrowed: false,
+        }
+    }
+
+    /// How many pointers away from the place tracked to the lock is, once tracked.
+    /// `None` if the place is not tracked as far as the lock, e.g. a lockguard passed as a param.
+    pub fn lock_derefs(&self) -> Option<usize> {
+        if self.borrowed {
+            Some(self.wrappers)
+        } else if self.lock_ref {
+            Some(self.wrappers + 1)
+        } else {
+            None
         }
     }
 
@@ -151,7 +172,10 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
         }
         let rhses = self.batch_depend_results.get_depends(place);
         let mut defs = rhses.iter().filter(|(_, result)| {
-            *result == DependResult::CallDepend || *result == DependResult::MoveDepend || *result == DependResult::CopyDepend
+            *result == DependResult::CallDepend
+                || *result == DependResult::MoveDepend
+                || *result == DependResult::CopyDepend
+                || *result == DependResult::WaitDepend
         });
         if defs.clone().count() > 1 {
             return None;
@@ -165,6 +189,11 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
                 self.state = TrackerState::Result;
                 Some(*place)
             }
+            // the guard passed to a condvar wait, tracked on to its lock
+            Some((place, DependResult::WaitDepend)) => {
+                self.state = TrackerState::Guard;
+                Some(*place)
+            }
             Some((place, DependResult::CopyDepend)) => {
                 self.state = TrackerState::RefLock;
                 Some(*place)
@@ -174,6 +203,7 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
     }
 
     fn handle_reflock(&mut self, place: Place<'tcx>) -> Option<Place<'tcx>> {
+        self.lock_ref = true;
         if self.batch_depend_results.body.local_kind(place.local) == LocalKind::Arg {
             self.state = TrackerState::ParamSrc;
             return None;
@@ -182,6 +212,7 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
         // heuristically only consider the first one
         match defs.into_iter().next() {
             Some((place, DependResult::RefDepend)) => {
+                self.borrowed = true;
                 if self.batch_depend_results.body.local_kind(place.local) == LocalKind::Arg {
                     self.state = TrackerState::ParamSrc;
                 } else {
@@ -191,6 +222,7 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
             }
             Some((place, DependResult::CopyDepend)) => Some(place),
             Some((place, DependResult::CallDepend)) => {
+                self.wrappers += 1;
                 self.state = TrackerState::WrapperLock;
                 Some(place)
             }
@@ -206,6 +238,7 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
         // heuristically only consider the first one
         match defs.into_iter().next() {
             Some((place, DependResult::RefDepend)) => {
+                self.borrowed = true;
                 if self.batch_depend_results.body.local_kind(place.local) == LocalKind::Arg {
                     self.state = TrackerState::ParamSrc;
                 } else {
@@ -215,6 +248,7 @@ impl<'a, 'b, 'c, 'tcx> Tracker<'a, 'b, 'c, 'tcx> {
             }
             Some((place, DependResult::CopyDepend)) => Some(place),
             Some((place, DependResult::CallDepend)) => {
+                self.wrappers += 1;
                 self.state = TrackerState::WrapperLock;
                 Some(place)
             }
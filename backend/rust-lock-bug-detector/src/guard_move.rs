@@ -0,0 +1,141 @@
+//! What a call does with a lockguard moved into it.
+//! `drop(guard)` releases the lock, while `mem::forget(guard)` and `ManuallyDrop::new(guard)` leak the guard
+//! and the lock is never released. A local fn takes the guard over and is analyzed holding it.
+//! A wait of a std condvar releases the lock and returns the guard holding it again, whatever the config.
+//! A move into another local, e.g. `let g2 = g;`, hands the lock over to that local.
+//! What any other fn does with it, or a move into another place, e.g. a struct or a tuple,
+//! is configured by `UnknownGuardMove`.
+extern crate rustc_hir;
+extern crate rustc_middle;
+use crate::condvar::CondvarWait;
+use crate::config::{strip_generic_args, UnknownGuardMove};
+use crate::summary::resolve_callee;
+use rustc_hir::def_id::LocalDefId;
+use rustc_middle::mir::{
+    BasicBlock, Body, Local, Location, Operand, Rvalue, StatementKind, TerminatorKind,
+};
+use rustc_middle::ty::TyCtxt;
+
+const RELEASING_FNS: [&str; 2] = ["std::mem::drop", "core::mem::drop"];
+
+const LEAKING_FNS: [&str; 4] = [
+    "std::mem::forget",
+    "core::mem::forget",
+    "std::mem::ManuallyDrop::new",
+    "core::mem::ManuallyDrop::new",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardMove {
+    Released,
+    Leaked,
+    /// moved into a local fn
+    Passed(LocalDefId),
+    /// moved into another local of the fn, which holds the lock from then on
+    Moved(Local),
+    Unknown,
+}
+
+impl GuardMove {
+    /// Whether the lock is still held after the move, in the fn moving the guard.
+    /// A local fn releases the guard in its own analysis.
+    pub fn keeps_held(self, unknown_guard_move: UnknownGuardMove) -> bool {
+        match self {
+            GuardMove::Released | GuardMove::Passed(_) | GuardMove::Moved(_) => false,
+            GuardMove::Leaked => true,
+            GuardMove::Unknown => unknown_guard_move == UnknownGuardMove::Hold,
+        }
+    }
+}
+
+/// How the lockguard moved at `location` is handled.
+pub fn classify_guard_move<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    location: Location,
+    condvar_waits: &[CondvarWait<'tcx>],
+) -> GuardMove {
+    let data = &body.basic_blocks()[location.block];
+    if location.statement_index < data.statements.len() {
+        return classify_statement_move(tcx, body, location, condvar_waits);
+    }
+    if let TerminatorKind::Call { .. } = data.terminator().kind {
+        classify_call(tcx, body, location.block, condvar_waits)
+    } else {
+        GuardMove::Released
+    }
+}
+
+/// A move into a temp moved on into the call ending the block, e.g. `_5 = move _3; drop(move _5)`,
+/// is a move into the call. A move of a local into another local is `Moved`,
+/// a move into any other place, e.g. an aggregate, a field or a deref, is unknown.
+fn classify_statement_move<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    location: Location,
+    condvar_waits: &[CondvarWait<'tcx>],
+) -> GuardMove {
+    let data = &body.basic_blocks()[location.block];
+    let moved_into = match &data.statements[location.statement_index].kind {
+        StatementKind::Assign(box (lhs, Rvalue::Use(Operand::Move(rhs))))
+            if rhs.as_local().is_some() =>
+        {
+            match lhs.as_local() {
+                Some(local) => local,
+                None => return GuardMove::Unknown,
+            }
+        }
+        _ => return GuardMove::Unknown,
+    };
+    let mut local = moved_into;
+    for stmt in data.statements[location.statement_index + 1..].iter() {
+        if let StatementKind::Assign(box (lhs, Rvalue::Use(Operand::Move(rhs)))) = &stmt.kind {
+            if rhs.as_local() == Some(local) {
+                match lhs.as_local() {
+                    Some(lhs) => local = lhs,
+                    None => return GuardMove::Unknown,
+                }
+            }
+        }
+    }
+    match &data.terminator().kind {
+        TerminatorKind::Call { args, .. }
+            if args.iter().any(
+                |arg| matches!(arg, Operand::Move(place) if place.as_local() == Some(local)),
+            ) =>
+        {
+            classify_call(tcx, body, location.block, condvar_waits)
+        }
+        _ => GuardMove::Moved(moved_into),
+    }
+}
+
+fn classify_call<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    body: &Body<'tcx>,
+    bb: BasicBlock,
+    condvar_waits: &[CondvarWait<'tcx>],
+) -> GuardMove {
+    // the guard is handed back in the result of the wait, see `CondvarWait::reacquired_guard`
+    if condvar_waits
+        .iter()
+        .any(|wait| wait.bb == bb && wait.takes_guard())
+    {
+        return GuardMove::Released;
+    }
+    let callee = match resolve_callee(tcx, body, bb) {
+        Some(callee) => callee,
+        None => return GuardMove::Unknown,
+    };
+    let def_path = strip_generic_args(&tcx.def_path_str(callee));
+    if RELEASING_FNS.contains(&def_path.as_str()) {
+        GuardMove::Released
+    } else if LEAKING_FNS.contains(&def_path.as_str()) {
+        GuardMove::Leaked
+    } else {
+        match callee.as_local() {
+            Some(callee) if tcx.is_mir_available(callee.to_def_id()) => GuardMove::Passed(callee),
+            _ => GuardMove::Unknown,
+        }
+    }
+}
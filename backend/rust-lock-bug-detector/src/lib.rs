@@ -0,0 +1,34 @@
+//! Lock bug detectors on the MIR of a crate, run from a rustc driver with `analyze`.
+#![feature(rustc_private)]
+#![feature(box_patterns)]
+
+mod analysis;
+mod await_lock_checker;
+mod baseline;
+mod blocking_call_checker;
+pub mod cache;
+mod callback;
+mod condvar;
+mod condvar_checker;
+pub mod config;
+mod conflict_lock_checker;
+mod crate_context;
+mod custom_guard;
+mod double_borrow_checker;
+mod double_lock_checker;
+mod guard_move;
+pub mod output;
+pub mod results;
+mod sarif;
+mod summary;
+mod suppression;
+mod trait_call;
+
+pub use analysis::{analyze, AnalysisResult};
+pub use await_lock_checker::AwaitLockBug;
+pub use blocking_call_checker::BlockingCallBug;
+pub use condvar_checker::{CondvarBug, CondvarBugKind};
+pub use conflict_lock_checker::{ConflictLockBug, LockNode, LockOrderReport};
+pub use double_borrow_checker::DoubleBorrowBug;
+pub use double_lock_checker::{CallchainInfo, DoubleLockBug};
+pub use suppression::TOOL_NAME;
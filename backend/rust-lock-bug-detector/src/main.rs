@@ -1,20 +1,20 @@
 #![feature(rustc_private)]
-#![feature(box_patterns)]
 
 extern crate rustc_driver;
 extern crate rustc_interface;
 
-mod config;
-mod conflict_lock_checker;
-mod double_lock_checker;
-
-use config::*;
-use conflict_lock_checker::ConflictLockChecker;
-use double_lock_checker::DoubleLockChecker;
+use rust_lock_bug_detector::cache::{cache_file_name, write_results};
+use rust_lock_bug_detector::config::{LockDetectorConfig, OutputFormat};
+use rust_lock_bug_detector::output::emit;
+use rust_lock_bug_detector::results::CachedResults;
+use rust_lock_bug_detector::{analyze, TOOL_NAME};
 use rustc_driver::Compilation;
 use rustc_interface::{interface, Queries};
 
-struct DetectorCallbacks;
+struct DetectorCallbacks {
+    // the cache file of the results of the crate compiled
+    cache_file_name: Option<String>,
+}
 
 impl rustc_driver::Callbacks for DetectorCallbacks {
     fn after_analysis<'tcx>(
@@ -25,31 +25,36 @@ impl rustc_driver::Callbacks for DetectorCallbacks {
         compiler.session().abort_if_errors();
         queries.global_ctxt().unwrap().peek_mut().enter(|tcx| {
             let lock_config = LockDetectorConfig::from_env().unwrap();
-            match lock_config.lock_detector_type {
-                LockDetectorType::DoubleLockDetector => match lock_config.crate_name_lists {
-                    CrateNameLists::Black(crate_name_black_lists) => {
-                        let mut double_lock_checker =
-                            DoubleLockChecker::new(false, crate_name_black_lists);
-                        double_lock_checker.check(tcx);
-                    }
-                    CrateNameLists::White(crate_name_white_lists) => {
-                        let mut double_lock_checker =
-                            DoubleLockChecker::new(true, crate_name_white_lists);
-                        double_lock_checker.check(tcx);
-                    }
-                },
-                LockDetectorType::ConflictLockDetector => match lock_config.crate_name_lists {
-                    CrateNameLists::Black(crate_name_black_lists) => {
-                        let mut conflict_lock_checker =
-                            ConflictLockChecker::new(false, crate_name_black_lists);
-                        conflict_lock_checker.check(tcx);
-                    }
-                    CrateNameLists::White(crate_name_white_lists) => {
-                        let mut conflict_lock_checker =
-                            ConflictLockChecker::new(true, crate_name_white_lists);
-                        conflict_lock_checker.check(tcx);
-                    }
-                },
+            let result = analyze(tcx, &lock_config);
+            // `cargo lock-bug-detect` emits the results of every crate from the cache, fresh or not,
+            // in the output format of its run and without the findings of its baseline
+            if let (Some(cache), Some(file_name)) = (&lock_config.cache, &self.cache_file_name) {
+                if lock_config.crate_filter.is_checked(&result.crate_name) {
+                    let results = CachedResults {
+                        key: cache.key.clone(),
+                        crate_name: result.crate_name.clone(),
+                        stats: result.stats,
+                        reports: result.cached_reports(&lock_config.output.severities),
+                        digest: result.finding_digest(&lock_config.output.severities),
+                    };
+                    write_results(cache, file_name, &results);
+                }
+                return;
+            }
+            let reports = result.render(&lock_config.output);
+            let stats = match lock_config.output.format {
+                OutputFormat::Text => result.stats,
+                _ => None,
+            };
+            if let Some(stats) = stats {
+                println!("{}", result.crate_name);
+                println!(
+                    "fn with locks: {}, lockguards num: {}, local fn num: {}",
+                    stats.fns_with_locks, stats.lockguards, stats.fns
+                );
+            }
+            for report in reports {
+                emit(&report, &lock_config.output.path);
             }
         });
         Compilation::Continue
@@ -70,12 +75,33 @@ fn compile_time_sysroot() -> Option<String> {
     })
 }
 
+// The name of the crate compiled, `None` for `rustc -vV` and the like.
+fn crate_name(rustc_args: &[String]) -> Option<&str> {
+    let i = rustc_args.iter().position(|arg| arg == "--crate-name")?;
+    rustc_args.get(i + 1).map(String::as_str)
+}
+
 fn main() {
     let mut rustc_args = vec![];
     for arg in std::env::args() {
         rustc_args.push(arg);
     }
 
+    // register the tool of the `lock_bug_detector::allow` attributes in the crates checked only,
+    // dependencies may register it or the feature themselves and their `cfg`s are left alone
+    let checked = match (crate_name(&rustc_args), LockDetectorConfig::from_env()) {
+        (Some(crate_name), Ok(config)) => {
+            !crate_name.starts_with("build_script_") && config.crate_filter.is_checked(crate_name)
+        }
+        _ => false,
+    };
+    if checked {
+        rustc_args.push("-Zcrate-attr=feature(register_tool)".to_owned());
+        rustc_args.push(format!("-Zcrate-attr=register_tool({})", TOOL_NAME));
+        rustc_args.push("--cfg".to_owned());
+        rustc_args.push(TOOL_NAME.to_owned());
+    }
+
     if let Some(sysroot) = compile_time_sysroot() {
         let sysroot_flag = "--sysroot";
         if !rustc_args.iter().any(|e| e == sysroot_flag) {
@@ -85,8 +111,11 @@ fn main() {
         }
     }
 
+    let mut callbacks = DetectorCallbacks {
+        cache_file_name: cache_file_name(&rustc_args),
+    };
     let result = rustc_driver::catch_fatal_errors(move || {
-        rustc_driver::run_compiler(&rustc_args, &mut DetectorCallbacks, None, None)
+        rustc_driver::run_compiler(&rustc_args, &mut callbacks, None, None)
     })
     .and_then(|result| result);
 
@@ -0,0 +1,233 @@
+extern crate rustc_middle;
+extern crate rustc_span;
+
+use crate::baseline::Baseline;
+use crate::config::{OutputConfig, OutputFormat, Severities};
+use crate::double_lock_checker::lock::LockGuardSrc;
+use crate::results::{CachedReport, TextReport};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::source_map::SourceMap;
+use rustc_span::Span;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+pub use crate::results::SpanInfo;
+
+impl SpanInfo {
+    pub fn new(span: Span, source_map: &SourceMap) -> Self {
+        let lo = source_map.lookup_char_pos(span.lo());
+        let hi = source_map.lookup_char_pos(span.hi());
+        Self {
+            file: lo.file.name.to_string(),
+            start_line: lo.line,
+            start_col: lo.col.0 + 1,
+            end_line: hi.line,
+            end_col: hi.col.0 + 1,
+        }
+    }
+}
+
+/// A location in a report: a `Span` of the local crate,
+/// or a location read from the lock summary of another crate.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ReportSpan {
+    Local(Span),
+    Extern(SpanInfo),
+}
+
+impl ReportSpan {
+    pub fn resolve(&self, source_map: &SourceMap) -> SpanInfo {
+        match self {
+            ReportSpan::Local(span) => SpanInfo::new(*span, source_map),
+            ReportSpan::Extern(span_info) => span_info.clone(),
+        }
+    }
+
+    pub fn local(&self) -> Option<Span> {
+        match self {
+            ReportSpan::Local(span) => Some(*span),
+            ReportSpan::Extern(_) => None,
+        }
+    }
+}
+
+impl From<Span> for ReportSpan {
+    fn from(span: Span) -> Self {
+        ReportSpan::Local(span)
+    }
+}
+
+// printed the same as a `Span` in the text reports
+impl fmt::Debug for ReportSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportSpan::Local(span) => write!(f, "{:?}", span),
+            ReportSpan::Extern(span) => write!(
+                f,
+                "{}:{}:{}: {}:{}",
+                span.file, span.start_line, span.start_col, span.end_line, span.end_col
+            ),
+        }
+    }
+}
+
+/// A call on a callchain: the def path of the calling fn, the fn called and the callsite.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CallSiteInfo {
+    pub caller: String,
+    pub callee: String,
+    pub span: SpanInfo,
+}
+
+/// A call on a callchain of a report, its callsite a `ReportSpan`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ReportCallSite {
+    pub caller: String,
+    pub callee: String,
+    pub span: ReportSpan,
+}
+
+impl ReportCallSite {
+    pub fn resolve(&self, source_map: &SourceMap) -> CallSiteInfo {
+        CallSiteInfo {
+            caller: self.caller.clone(),
+            callee: self.callee.clone(),
+            span: self.span.resolve(source_map),
+        }
+    }
+}
+
+impl From<CallSiteInfo> for ReportCallSite {
+    fn from(call: CallSiteInfo) -> Self {
+        Self {
+            caller: call.caller,
+            callee: call.callee,
+            span: ReportSpan::Extern(call.span),
+        }
+    }
+}
+
+// `caller -> callee @ span` in the text reports
+impl fmt::Debug for ReportCallSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} @ {:?}", self.caller, self.callee, self.span)
+    }
+}
+
+/// Serializable form of `LockGuardSrc`.
+/// `GlobalSrc` is printed as the def path of the static instead of a `DefId`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "kind")]
+pub enum LockSrcInfo {
+    ParamSrc { struct_type: String, fields: String },
+    LocalSrc { place: String },
+    GlobalSrc { global: String },
+}
+
+/// The serializable form of `src`.
+pub fn lock_src_info(src: &LockGuardSrc, tcx: TyCtxt) -> LockSrcInfo {
+    match src {
+        LockGuardSrc::ParamSrc(context) => LockSrcInfo::ParamSrc {
+            struct_type: context.struct_type.clone(),
+            fields: context.fields.clone(),
+        },
+        LockGuardSrc::LocalSrc(context) => LockSrcInfo::LocalSrc {
+            place: context.place.clone(),
+        },
+        LockGuardSrc::GlobalSrc(context) => LockSrcInfo::GlobalSrc {
+            global: tcx.def_path_str(context.global_id),
+        },
+    }
+}
+
+/// One lock acquisition in a report: the lockguard type, the protected data type and where.
+#[derive(Debug, Clone, Serialize)]
+pub struct LockSite {
+    pub lock_type: String,
+    pub data_type: String,
+    pub span: SpanInfo,
+}
+
+/// The findings of one detector on a crate.
+#[derive(Debug, Clone)]
+pub struct Findings<B> {
+    pub bugs: Vec<B>,
+    /// the number of findings suppressed in source
+    pub suppressed: usize,
+    /// the fingerprints of the findings dropped as they are in the baseline
+    pub baselined: Vec<String>,
+    /// the findings grouped by lock, as printed with the text output format
+    pub text: TextReport,
+}
+
+/// The machine-readable crate reports of the findings of a detector.
+pub trait CrateReport {
+    /// The crate report as a single-line JSON document.
+    fn to_json(&self, crate_name: &str, severities: &Severities) -> String;
+    /// The crate report as a SARIF log.
+    fn to_sarif(&self, crate_name: &str, severities: &Severities) -> String;
+}
+
+impl<B> Findings<B>
+where
+    Self: CrateReport,
+{
+    /// The crate report in the output format, nothing for an empty text report.
+    pub fn render(&self, crate_name: &str, output: &OutputConfig) -> Option<String> {
+        match output.format {
+            OutputFormat::Text => Some(self.text.render()).filter(|text| !text.is_empty()),
+            OutputFormat::Json => Some(self.to_json(crate_name, &output.severities)),
+            OutputFormat::Sarif => Some(self.to_sarif(crate_name, &output.severities)),
+        }
+    }
+
+    /// The crate report in every output format, for `cargo lock-bug-detect` to cache.
+    pub fn cached_report(&self, crate_name: &str, severities: &Severities) -> CachedReport {
+        CachedReport {
+            text: self.text.clone(),
+            json: self.to_json(crate_name, severities),
+            sarif: self.to_sarif(crate_name, severities),
+        }
+    }
+
+    /// Drop the findings in `baseline`, keeping their fingerprints.
+    /// `fingerprint` gives the fingerprint of a finding.
+    pub fn apply_baseline(&mut self, baseline: &Baseline, fingerprint: impl Fn(&B) -> &String) {
+        let baselined = &mut self.baselined;
+        self.bugs.retain(|bug| {
+            let fingerprint = fingerprint(bug);
+            if baseline.contains(fingerprint) {
+                baselined.push(fingerprint.clone());
+                return false;
+            }
+            true
+        });
+        self.text
+            .apply_baseline(|fingerprint| baseline.contains(fingerprint));
+    }
+}
+
+/// Append `content` to the file at `output_path` or print it to stdout.
+/// Each rustc invocation appends its own crate report, so reports of a whole
+/// `cargo check` run without `cargo lock-bug-detect`, e.g. by `run.sh`, end up in one file.
+pub fn emit(content: &str, output_path: &Option<String>) {
+    let mut content = content.to_string();
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    match output_path {
+        Some(path) => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|e| panic!("cannot open output file {}: {}", path, e));
+            // one write per crate report to keep concurrent rustc invocations from interleaving
+            file.write_all(content.as_bytes())
+                .unwrap_or_else(|e| panic!("cannot write output file {}: {}", path, e));
+        }
+        None => print!("{}", content),
+    }
+}
@@ -0,0 +1,225 @@
+//! The results of a crate checked, as the driver caches them and `cargo lock-bug-detect` reads them back.
+//!
+//! The cargo subcommand does not link the detectors, which need the rustc crates,
+//! so it includes this module, free of them, with `#[path]` instead of importing it from the library.
+use serde::{Deserialize, Serialize};
+
+/// A `Span` resolved against the source map.
+/// Lines and columns are 1-based, the same as rustc prints a `Span`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SpanInfo {
+    pub file: String,
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+/// What the double-lock or conflict-lock detector analyzed in a crate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalysisStats {
+    /// the fns and closures of the crate
+    pub fns: usize,
+    /// the fns creating a lockguard
+    pub fns_with_locks: usize,
+    pub lockguards: usize,
+}
+
+/// The number of findings of a bug kind, the suppressed ones not counted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FindingCount {
+    pub kind: String,
+    pub severity: String,
+    pub count: usize,
+}
+
+/// A finding reported, identified by its fingerprint in the baselines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindingFingerprint {
+    pub kind: String,
+    pub fingerprint: String,
+    /// where the lock that would block is acquired, or the blocking `.await`, wait or call
+    pub location: SpanInfo,
+}
+
+/// The findings of a crate, reported back to `cargo lock-bug-detect`
+/// to set its exit code and to write and check baselines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrateFindingDigest {
+    pub crate_name: String,
+    /// the number of findings at each severity
+    pub findings: Vec<FindingCount>,
+    pub fingerprints: Vec<FindingFingerprint>,
+    /// the fingerprints of the findings dropped as they are in the baseline
+    pub baselined: Vec<String>,
+}
+
+/// The version of the baseline files written, a baseline of another version is rejected.
+pub const BASELINE_VERSION: u32 = 1;
+
+/// The baseline file of `--baseline` and `--write-baseline`, and of `RUST_LOCK_DETECTOR_BASELINE`.
+/// Only the fingerprints are matched, the rest tells the users what is in the baseline.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BaselineFile {
+    pub version: u32,
+    pub findings: Vec<BaselineEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub fingerprint: String,
+    pub kind: String,
+    pub crate_name: String,
+    /// where the finding was when the baseline was written
+    pub location: String,
+}
+
+/// The results of a crate checked, cached by the driver for `cargo lock-bug-detect`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedResults {
+    /// the config key of the run the results are computed in
+    pub key: String,
+    pub crate_name: String,
+    pub stats: Option<AnalysisStats>,
+    pub reports: Vec<CachedReport>,
+    pub digest: CrateFindingDigest,
+}
+
+/// The crate report of a detector in every output format, before the baseline is applied,
+/// so that changing the output format or the baseline does not invalidate the cached results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedReport {
+    pub text: TextReport,
+    pub json: String,
+    pub sarif: String,
+}
+
+/// A text report with each finding printed in its own block,
+/// so that the findings of a baseline can be dropped from it once it is rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextReport {
+    /// what the findings are called in the counts at the end, e.g. `double-locks`
+    findings_name: String,
+    lines: Vec<TextLine>,
+    suppressed: usize,
+    baselined: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum TextLine {
+    /// printed if one of the findings after it is, up to the next heading of the same or a lower level
+    Heading {
+        level: usize,
+        text: String,
+    },
+    Finding {
+        fingerprint: String,
+        text: String,
+    },
+}
+
+impl TextReport {
+    pub fn new(findings_name: &str, suppressed: usize) -> Self {
+        Self {
+            findings_name: findings_name.to_string(),
+            lines: Vec::new(),
+            suppressed,
+            baselined: 0,
+        }
+    }
+
+    pub fn heading(&mut self, level: usize, text: String) {
+        self.lines.push(TextLine::Heading { level, text });
+    }
+
+    pub fn finding(&mut self, fingerprint: String, text: String) {
+        self.lines.push(TextLine::Finding { fingerprint, text });
+    }
+
+    /// Drop the findings whose fingerprint is in the baseline, counting them.
+    pub fn apply_baseline(&mut self, in_baseline: impl Fn(&str) -> bool) {
+        let before = self.lines.len();
+        self.lines.retain(|line| match line {
+            TextLine::Finding { fingerprint, .. } => !in_baseline(fingerprint),
+            TextLine::Heading { .. } => true,
+        });
+        self.baselined += before - self.lines.len();
+    }
+
+    /// The report without the headings left with no finding, empty if there is nothing to report.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                TextLine::Heading { level, text } => {
+                    let has_findings = self.lines[i + 1..]
+                        .iter()
+                        .take_while(|line| match line {
+                            TextLine::Heading { level: next, .. } => next > level,
+                            TextLine::Finding { .. } => true,
+                        })
+                        .any(|line| matches!(line, TextLine::Finding { .. }));
+                    if has_findings {
+                        out += text;
+                    }
+                }
+                TextLine::Finding { text, .. } => out += text,
+            }
+        }
+        if self.suppressed > 0 {
+            out += &format!("Suppressed {}: {}\n", self.findings_name, self.suppressed);
+        }
+        if self.baselined > 0 {
+            out += &format!("Baselined {}: {}\n", self.findings_name, self.baselined);
+        }
+        out
+    }
+}
+
+impl CrateFindingDigest {
+    /// Move the findings whose fingerprint is in the baseline from the counts to `baselined`.
+    pub fn apply_baseline(&mut self, in_baseline: impl Fn(&str) -> bool) {
+        let (baselined, fingerprints): (Vec<_>, Vec<_>) = self
+            .fingerprints
+            .drain(..)
+            .partition(|finding| in_baseline(&finding.fingerprint));
+        self.fingerprints = fingerprints;
+        for finding in baselined {
+            if let Some(count) = self
+                .findings
+                .iter_mut()
+                .find(|count| count.kind == finding.kind)
+            {
+                count.count -= 1;
+            }
+            self.baselined.push(finding.fingerprint);
+        }
+    }
+}
+
+#[test]
+fn test_text_report_baseline() {
+    let mut report = TextReport::new("double-locks", 1);
+    report.heading(0, "Must-alias double-locks:\n".to_string());
+    report.heading(1, "LockGuardSrc: a\n".to_string());
+    report.finding("fa".to_string(), "{ a }\n".to_string());
+    report.heading(1, "LockGuardSrc: b\n".to_string());
+    report.finding("fb1".to_string(), "{ b1 }\n".to_string());
+    report.finding("fb2".to_string(), "{ b2 }\n".to_string());
+    assert_eq!(
+        report.render(),
+        "Must-alias double-locks:\nLockGuardSrc: a\n{ a }\nLockGuardSrc: b\n{ b1 }\n{ b2 }\n\
+         Suppressed double-locks: 1\n"
+    );
+    report.apply_baseline(|fingerprint| fingerprint == "fa" || fingerprint == "fb2");
+    assert_eq!(
+        report.render(),
+        "Must-alias double-locks:\nLockGuardSrc: b\n{ b1 }\n\
+         Suppressed double-locks: 1\nBaselined double-locks: 2\n"
+    );
+    report.apply_baseline(|fingerprint| fingerprint == "fb1");
+    assert_eq!(
+        report.render(),
+        "Suppressed double-locks: 1\nBaselined double-locks: 3\n"
+    );
+}
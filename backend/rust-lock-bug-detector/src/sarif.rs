@@ -0,0 +1,225 @@
+//! SARIF 2.1.0 output of the lock bug reports.
+//! Every rustc invocation emits a complete SARIF log with one run for its crate.
+//! `cargo lock-bug-detect --output` merges the runs of all the crates into one log.
+use crate::config::Severities;
+use crate::output::SpanInfo;
+use serde_json::{json, Value};
+use std::path::Path;
+
+pub const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+pub const SARIF_VERSION: &str = "2.1.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SarifRule {
+    DoubleLock,
+    ConflictLock,
+    AwaitLock,
+    Condvar,
+    DoubleBorrow,
+    BlockingCall,
+}
+
+impl SarifRule {
+    const ALL: [SarifRule; 6] = [
+        SarifRule::DoubleLock,
+        SarifRule::ConflictLock,
+        SarifRule::AwaitLock,
+        SarifRule::Condvar,
+        SarifRule::DoubleBorrow,
+        SarifRule::BlockingCall,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            SarifRule::DoubleLock => "double-lock",
+            SarifRule::ConflictLock => "conflict-lock",
+            SarifRule::AwaitLock => "await-lock",
+            SarifRule::Condvar => "condvar",
+            SarifRule::DoubleBorrow => "double-borrow",
+            SarifRule::BlockingCall => "blocking-call",
+        }
+    }
+
+    fn index(self) -> usize {
+        SarifRule::ALL.iter().position(|r| *r == self).unwrap()
+    }
+
+    /// The SARIF level is the configured severity of the bug kind.
+    fn level(self, severities: &Severities) -> &'static str {
+        match self {
+            SarifRule::DoubleLock => severities.double_lock.as_str(),
+            SarifRule::ConflictLock => severities.conflict_lock.as_str(),
+            SarifRule::AwaitLock => severities.await_lock.as_str(),
+            SarifRule::Condvar => severities.condvar.as_str(),
+            SarifRule::DoubleBorrow => severities.double_borrow.as_str(),
+            SarifRule::BlockingCall => severities.blocking_call.as_str(),
+        }
+    }
+
+    fn short_description(self) -> &'static str {
+        match self {
+            SarifRule::DoubleLock => "Lock acquired while it is already held",
+            SarifRule::ConflictLock => "Locks acquired in conflicting order",
+            SarifRule::AwaitLock => "Lock held across a suspension point",
+            SarifRule::Condvar => "Condvar wait that can block forever",
+            SarifRule::DoubleBorrow => "RefCell borrowed mutably while it is borrowed",
+            SarifRule::BlockingCall => "Blocking call while a lock is held",
+        }
+    }
+
+    fn full_description(self) -> &'static str {
+        match self {
+            SarifRule::DoubleLock => "The second lock is acquired while a lockguard of the same lock is still alive, possibly across function calls. This deadlocks (or panics) at runtime.",
+            SarifRule::ConflictLock => "Two locks are acquired in one order in one place and in the reverse order in another place. Two threads running both can deadlock.",
+            SarifRule::AwaitLock => "A lockguard of a blocking lock is alive across an `.await`. The executor thread blocks while the task is suspended, and another task locking it on the same thread deadlocks.",
+            SarifRule::Condvar => "A `Condvar` wait releases only the mutex of the guard passed. Waiting while holding another lock blocks the notifier, waiting outside a loop or predicate misses spurious and earlier wakeups, and one condvar used with guards of different mutexes panics (std) or loses wakeups.",
+            SarifRule::DoubleBorrow => "`borrow_mut` of a `RefCell` is called while a `Ref` or `RefMut` of the same cell is still alive, possibly across function calls. This panics with `BorrowMutError` at runtime.",
+            SarifRule::BlockingCall => "A blocking function (a join, a channel receive, a sleep, file or socket IO, ...) is called while a lockguard is alive, possibly in a callee. Every thread waiting for the lock waits for the blocking call too, and deadlocks if the call waits for one of them.",
+        }
+    }
+}
+
+pub struct SarifLocation {
+    pub span: SpanInfo,
+    pub message: String,
+}
+
+impl SarifLocation {
+    pub fn new(span: SpanInfo, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+pub struct SarifFinding {
+    pub rule: SarifRule,
+    pub message: String,
+    /// where the lock that would block is acquired
+    pub primary: SarifLocation,
+    pub related: Vec<SarifLocation>,
+    /// one thread flow per interprocedural callchain
+    pub code_flows: Vec<Vec<SarifLocation>>,
+    /// the baseline fingerprint, for the code scanning tools to match results across runs
+    pub fingerprint: String,
+}
+
+fn physical_location(span: &SpanInfo) -> Value {
+    let artifact_location = if span.file.starts_with('/') {
+        json!({ "uri": format!("file://{}", span.file) })
+    } else {
+        json!({ "uri": span.file, "uriBaseId": "%SRCROOT%" })
+    };
+    json!({
+        "artifactLocation": artifact_location,
+        "region": {
+            "startLine": span.start_line,
+            "startColumn": span.start_col,
+            "endLine": span.end_line,
+            "endColumn": span.end_col,
+        }
+    })
+}
+
+fn result(finding: &SarifFinding, severities: &Severities) -> Value {
+    let related = finding
+        .related
+        .iter()
+        .enumerate()
+        .map(|(id, loc)| {
+            json!({
+                "id": id,
+                "physicalLocation": physical_location(&loc.span),
+                "message": { "text": loc.message },
+            })
+        })
+        .collect::<Vec<_>>();
+    let code_flows = finding
+        .code_flows
+        .iter()
+        .map(|flow| {
+            let locations = flow
+                .iter()
+                .map(|loc| {
+                    json!({
+                        "location": {
+                            "physicalLocation": physical_location(&loc.span),
+                            "message": { "text": loc.message },
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "threadFlows": [{ "locations": locations }] })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "ruleId": finding.rule.id(),
+        "ruleIndex": finding.rule.index(),
+        "level": finding.rule.level(severities),
+        "message": { "text": finding.message },
+        "locations": [{
+            "physicalLocation": physical_location(&finding.primary.span),
+            "message": { "text": finding.primary.message },
+        }],
+        "relatedLocations": related,
+        "codeFlows": code_flows,
+        "partialFingerprints": { "lockBugDetect/v1": finding.fingerprint },
+    })
+}
+
+/// The `file` URI of `dir` ending with a slash, as the URI of a base the relative URIs are resolved against.
+pub fn dir_uri(dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    if dir.ends_with('/') {
+        format!("file://{}", dir)
+    } else {
+        format!("file://{}/", dir)
+    }
+}
+
+/// A complete SARIF log with a single run holding the findings of one crate.
+/// The number of findings suppressed in source is a property of the run.
+/// The relative paths of the spans are relative to the current dir of rustc, the workspace root under cargo,
+/// given as `%SRCROOT%` in the `originalUriBaseIds` of the run.
+pub fn sarif_log(
+    crate_name: &str,
+    findings: &[SarifFinding],
+    suppressed: usize,
+    severities: &Severities,
+) -> String {
+    let rules = SarifRule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "shortDescription": { "text": rule.short_description() },
+                "fullDescription": { "text": rule.full_description() },
+                "defaultConfiguration": { "level": rule.level(severities) },
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut log = json!({
+        "$schema": SARIF_SCHEMA,
+        "version": SARIF_VERSION,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rust-lock-bug-detector",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/BurtonQin/rust-lock-bug-detector",
+                    "rules": rules,
+                }
+            },
+            "properties": { "crate_name": crate_name, "suppressed": suppressed },
+            "results": findings
+                .iter()
+                .map(|finding| result(finding, severities))
+                .collect::<Vec<_>>(),
+        }]
+    });
+    if let Ok(dir) = std::env::current_dir() {
+        log["runs"][0]["originalUriBaseIds"] = json!({ "%SRCROOT%": { "uri": dir_uri(&dir) } });
+    }
+    serde_json::to_string(&log).unwrap()
+}
@@ -0,0 +1,427 @@
+//! Per-function lock summaries shared across crates.
+//!
+//! The callgraph only links local fns, so a lock acquired inside a dependency is invisible to its callers.
+//! After a crate is analyzed, the locks each fn acquires (by itself or through its callees),
+//! the locks it returns still held and its callees are written to `<summary dir>/<crate>-<disambiguator>.json`.
+//! A dependent crate loads the summary of each of its extern crates by its disambiguator,
+//! so the summaries of other compilations of a crate with the same name are never read,
+//! and looks up its extern callees.
+//! A lock is identified by a `LockKey` built from crate-qualified def paths,
+//! which reads the same in the crate writing the summary and in the crates loading it.
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_span;
+
+use crate::config::AnalysisLimits;
+use crate::output::{CallSiteInfo, LockSrcInfo, SpanInfo};
+use rustc_hir::def_id::{CrateNum, DefId, LocalDefId, LOCAL_CRATE};
+use rustc_middle::mir::{BasicBlock, Body, Local, Operand, Rvalue, StatementKind, TerminatorKind};
+use rustc_middle::ty::{Instance, ParamEnv, Ty, TyCtxt, TyKind, TypeFoldable};
+use rustc_span::Span;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Crate-independent src of a lock. A lock from `LocalSrc` cannot outlive its fn and has no key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum SrcKey {
+    ParamSrc { struct_type: String, fields: String },
+    GlobalSrc { global: String },
+}
+
+impl SrcKey {
+    pub fn src_info(&self) -> LockSrcInfo {
+        match self {
+            SrcKey::ParamSrc {
+                struct_type,
+                fields,
+            } => LockSrcInfo::ParamSrc {
+                struct_type: struct_type.clone(),
+                fields: fields.clone(),
+            },
+            SrcKey::GlobalSrc { global } => LockSrcInfo::GlobalSrc {
+                global: global.clone(),
+            },
+        }
+    }
+}
+
+/// Crate-independent identity of a lock: the protected data type and where the lock comes from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LockKey {
+    pub data_type: String,
+    pub src: SrcKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SummaryLock {
+    /// `Debug` name of the `LockGuardType` of the checker writing the summary
+    pub lock_type: String,
+    pub key: LockKey,
+    pub span: SpanInfo,
+    /// calls from the summarized fn to the fn acquiring the lock
+    pub callchain: Vec<CallSiteInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FnSummary {
+    pub acquired: Vec<SummaryLock>,
+    pub held_on_return: Vec<SummaryLock>,
+    pub callees: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrateSummary {
+    pub crate_name: String,
+    pub fns: BTreeMap<String, FnSummary>,
+}
+
+/// A lockguard of the local crate handed to the summary by a checker.
+pub struct SummaryLockSite {
+    pub lock_type: String,
+    pub key: LockKey,
+    pub span: Span,
+}
+
+/// `<crate name>::<def path>`, the same in every crate, unlike `def_path_str` that prints visible paths.
+pub fn canonical_def_path(tcx: TyCtxt, def_id: DefId) -> String {
+    let mut path = tcx.crate_name(def_id.krate).to_string();
+    for component in tcx.def_path(def_id).data {
+        path += &format!("::{}", component.data.as_symbol());
+        if component.disambiguator != 0 {
+            path += &format!("[{}]", component.disambiguator);
+        }
+    }
+    path
+}
+
+/// Print a type with `canonical_def_path` for ADTs.
+pub fn canonical_ty<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> String {
+    match ty.kind {
+        TyKind::Adt(adt_def, substs) => {
+            let path = canonical_def_path(tcx, adt_def.did);
+            let tys = substs
+                .types()
+                .map(|ty| canonical_ty(tcx, ty))
+                .collect::<Vec<_>>();
+            if tys.is_empty() {
+                path
+            } else {
+                format!("{}<{}>", path, tys.join(", "))
+            }
+        }
+        TyKind::Ref(_, ty, mutbl) => format!("&{}{}", mutbl.prefix_str(), canonical_ty(tcx, ty)),
+        TyKind::Tuple(_) => format!(
+            "({})",
+            ty.tuple_fields()
+                .map(|ty| canonical_ty(tcx, ty))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => ty.to_string(),
+    }
+}
+
+/// The lock key of a lockguard of type `guard_ty` from `src`.
+/// The protected data is the `data`-th type argument of the lockguard if provided (for declared guards),
+/// else the last one, e.g. `T` of `std::sync::MutexGuard<T>` and of `lock_api::MutexGuard<R, T>`.
+pub fn lock_key<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    guard_ty: Ty<'tcx>,
+    data: Option<usize>,
+    src: Option<SrcKey>,
+) -> Option<LockKey> {
+    let data_type = match guard_ty.kind {
+        TyKind::Adt(_, substs) => match data {
+            Some(data) => canonical_ty(tcx, substs.types().nth(data)?),
+            None => canonical_ty(tcx, substs.types().last()?),
+        },
+        _ => return None,
+    };
+    Some(LockKey {
+        data_type,
+        src: src?,
+    })
+}
+
+/// The `SrcKey::ParamSrc` of a lock reached through the fields of a param, with the references peeled.
+pub fn param_src_key<'tcx>(tcx: TyCtxt<'tcx>, mut param_ty: Ty<'tcx>, fields: &str) -> SrcKey {
+    while let TyKind::Ref(_, ty, _) = param_ty.kind {
+        param_ty = ty;
+    }
+    SrcKey::ParamSrc {
+        struct_type: canonical_ty(tcx, param_ty),
+        fields: fields.to_string(),
+    }
+}
+
+/// The def path of `def_id` prefixed with the crate name, as the fns of a summary are named
+/// in the reports of the crates reading it.
+fn crate_def_path_str(tcx: TyCtxt, def_id: DefId) -> String {
+    if def_id.is_local() {
+        format!(
+            "{}::{}",
+            tcx.crate_name(LOCAL_CRATE),
+            tcx.def_path_str(def_id)
+        )
+    } else {
+        tcx.def_path_str(def_id)
+    }
+}
+
+/// The name of the summary file of crate `cnum`, the same when it is written and when it is loaded.
+fn summary_file_name(tcx: TyCtxt, cnum: CrateNum) -> String {
+    format!(
+        "{}-{}.json",
+        tcx.crate_name(cnum),
+        tcx.crate_disambiguator(cnum)
+    )
+}
+
+/// The fn called at the terminator of `bb`, if it is a call.
+pub fn resolve_callee(tcx: TyCtxt, body: &Body, bb: BasicBlock) -> Option<DefId> {
+    if let TerminatorKind::Call {
+        func: Operand::Constant(box constant),
+        ..
+    } = &body.basic_blocks()[bb].terminator().kind
+    {
+        if let TyKind::FnDef(callee_def_id, substs) | TyKind::Closure(callee_def_id, substs) =
+            constant.literal.ty.kind
+        {
+            if substs.has_param_types_or_consts() {
+                return Some(callee_def_id);
+            }
+            return match Instance::resolve(tcx, ParamEnv::reveal_all(), callee_def_id, substs) {
+                Ok(Some(instance)) => Some(instance.def.def_id()),
+                _ => Some(callee_def_id),
+            };
+        }
+    }
+    None
+}
+
+/// Calls to fns of other crates. Trait methods are resolved to the impl when the substs are known.
+pub fn extern_callsites(tcx: TyCtxt, body: &Body) -> Vec<(BasicBlock, DefId)> {
+    body.basic_blocks()
+        .indices()
+        .filter_map(|bb| resolve_callee(tcx, body, bb).map(|callee| (bb, callee)))
+        .filter(|(_, callee)| !callee.is_local())
+        .collect()
+}
+
+fn local_callsites(
+    tcx: TyCtxt,
+    body: &Body,
+    crate_fn_ids: &[LocalDefId],
+) -> Vec<(BasicBlock, LocalDefId)> {
+    body.basic_blocks()
+        .indices()
+        .filter_map(|bb| {
+            let callee = resolve_callee(tcx, body, bb)?.as_local()?;
+            if crate_fn_ids.contains(&callee) {
+                Some((bb, callee))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The summaries of all the crates analyzed so far.
+pub struct LockSummaries {
+    fns: HashMap<String, FnSummary>,
+}
+
+impl LockSummaries {
+    pub fn new() -> Self {
+        Self {
+            fns: HashMap::new(),
+        }
+    }
+
+    /// The summaries of the extern crates of the crate of `tcx` found in `dir`.
+    pub fn load(tcx: TyCtxt, dir: &Option<String>) -> Self {
+        let mut summaries = Self::new();
+        let dir = match dir {
+            Some(dir) => Path::new(dir),
+            None => return summaries,
+        };
+        for cnum in tcx.crates().iter() {
+            // a crate not analyzed or whose summary is written by another version of the detector is skipped
+            if let Some(crate_summary) = fs::read_to_string(dir.join(summary_file_name(tcx, *cnum)))
+                .ok()
+                .and_then(|content| serde_json::from_str::<CrateSummary>(&content).ok())
+            {
+                summaries.fns.extend(crate_summary.fns.into_iter());
+            }
+        }
+        summaries
+    }
+
+    pub fn get(&self, tcx: TyCtxt, def_id: DefId) -> Option<&FnSummary> {
+        if self.fns.is_empty() {
+            return None;
+        }
+        self.fns.get(&canonical_def_path(tcx, def_id))
+    }
+
+    /// The lock keys of the locals holding a lockguard returned by an extern callee,
+    /// and of the locals the lockguard is moved to.
+    pub fn returned_lock_keys(&self, tcx: TyCtxt, body: &Body) -> HashMap<Local, LockKey> {
+        let mut keys = HashMap::new();
+        for (bb, callee) in extern_callsites(tcx, body) {
+            if let TerminatorKind::Call {
+                destination: Some((place, _)),
+                ..
+            } = &body.basic_blocks()[bb].terminator().kind
+            {
+                if let Some(lock) = self
+                    .get(tcx, callee)
+                    .and_then(|summary| summary.held_on_return.first())
+                {
+                    keys.insert(place.local, lock.key.clone());
+                }
+            }
+        }
+        let mut changed = !keys.is_empty();
+        while changed {
+            changed = false;
+            for stmt in body
+                .basic_blocks()
+                .iter()
+                .flat_map(|bb_data| bb_data.statements.iter())
+            {
+                if let StatementKind::Assign(box (lhs, Rvalue::Use(Operand::Move(rhs)))) = &stmt.kind
+                {
+                    if lhs.projection.is_empty() && !keys.contains_key(&lhs.local) {
+                        if let Some(key) = keys.get(&rhs.local).cloned() {
+                            keys.insert(lhs.local, key);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// Summarize every local fn and write the summary of the crate to `dir`.
+/// `own` are the lockguards created in each fn and `returned` the ones it returns still held.
+/// The locks acquired by callees are added to their callers until a fixpoint,
+/// through callchains of at most the callchain depth of `limits` calls.
+pub fn write_crate_summary(
+    tcx: TyCtxt,
+    dir: &str,
+    crate_fn_ids: &[LocalDefId],
+    own: &HashMap<LocalDefId, Vec<SummaryLockSite>>,
+    returned: &HashMap<LocalDefId, Vec<SummaryLockSite>>,
+    extern_summaries: &LockSummaries,
+    limits: AnalysisLimits,
+) {
+    let source_map = tcx.sess.source_map();
+    let summary_lock = |site: &SummaryLockSite| SummaryLock {
+        lock_type: site.lock_type.clone(),
+        key: site.key.clone(),
+        span: SpanInfo::new(site.span, source_map),
+        callchain: Vec::new(),
+    };
+    let mut summaries: HashMap<LocalDefId, FnSummary> = HashMap::new();
+    let mut local_calls: HashMap<LocalDefId, Vec<(CallSiteInfo, LocalDefId)>> = HashMap::new();
+    for fn_id in crate_fn_ids {
+        let body = tcx.optimized_mir(*fn_id);
+        let callsite = |bb: BasicBlock, callee: DefId| CallSiteInfo {
+            caller: crate_def_path_str(tcx, fn_id.to_def_id()),
+            callee: crate_def_path_str(tcx, callee),
+            span: SpanInfo::new(
+                body.basic_blocks()[bb].terminator().source_info.span,
+                source_map,
+            ),
+        };
+        let mut summary = FnSummary::default();
+        summary
+            .held_on_return
+            .extend(returned.get(fn_id).into_iter().flatten().map(summary_lock));
+        summary
+            .acquired
+            .extend(own.get(fn_id).into_iter().flatten().map(summary_lock));
+        summary
+            .acquired
+            .extend(summary.held_on_return.iter().cloned());
+        for (bb, callee) in extern_callsites(tcx, body) {
+            summary.callees.push(canonical_def_path(tcx, callee));
+            if let Some(callee_summary) = extern_summaries.get(tcx, callee) {
+                for lock in callee_summary.acquired.iter() {
+                    if !limits.stops_at(lock.callchain.len()) {
+                        let mut lock = lock.clone();
+                        lock.callchain.insert(0, callsite(bb, callee));
+                        summary.acquired.push(lock);
+                    }
+                }
+            }
+        }
+        let calls = local_callsites(tcx, body, crate_fn_ids)
+            .into_iter()
+            .map(|(bb, callee)| (callsite(bb, callee.to_def_id()), callee))
+            .collect::<Vec<_>>();
+        summary.callees.extend(
+            calls
+                .iter()
+                .map(|(_, callee)| canonical_def_path(tcx, callee.to_def_id())),
+        );
+        local_calls.insert(*fn_id, calls);
+        summaries.insert(*fn_id, summary);
+    }
+    // a lock in a summary is identified by its key, lockguard type and where it is acquired
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for fn_id in crate_fn_ids {
+            let mut new_locks: Vec<SummaryLock> = Vec::new();
+            for (callsite, callee) in local_calls[fn_id].iter() {
+                for lock in summaries[callee].acquired.iter() {
+                    if limits.stops_at(lock.callchain.len()) {
+                        continue;
+                    }
+                    let known = summaries[fn_id]
+                        .acquired
+                        .iter()
+                        .chain(new_locks.iter())
+                        .any(|known| {
+                            known.key == lock.key
+                                && known.lock_type == lock.lock_type
+                                && known.span == lock.span
+                        });
+                    if !known {
+                        let mut lock = lock.clone();
+                        lock.callchain.insert(0, callsite.clone());
+                        new_locks.push(lock);
+                    }
+                }
+            }
+            if !new_locks.is_empty() {
+                changed = true;
+                summaries.get_mut(fn_id).unwrap().acquired.extend(new_locks);
+            }
+        }
+    }
+    let crate_summary = CrateSummary {
+        crate_name: tcx.crate_name(LOCAL_CRATE).to_string(),
+        fns: summaries
+            .into_iter()
+            .map(|(fn_id, summary)| (canonical_def_path(tcx, fn_id.to_def_id()), summary))
+            .collect(),
+    };
+    let file_name = summary_file_name(tcx, LOCAL_CRATE);
+    if fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    // write then rename so that a concurrent rustc never loads a partial summary
+    let path = Path::new(dir).join(&file_name);
+    let tmp_path = Path::new(dir).join(format!("{}.tmp", file_name));
+    if fs::write(&tmp_path, serde_json::to_string(&crate_summary).unwrap()).is_ok() {
+        let _ = fs::rename(&tmp_path, &path);
+    }
+}
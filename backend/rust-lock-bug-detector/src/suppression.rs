@@ -0,0 +1,171 @@
+//! In-source suppression of lock bug findings.
+//!
+//! A finding is suppressed if one of its local lock acquisitions is
+//! - inside an item (fn, impl, trait, mod or the crate) marked with `#[lock_bug_detector::allow(double_lock)]`, or
+//! - on a line ending with `// lock-bug-detect: allow(double_lock)`, or below a line with only that comment.
+//!
+//! The driver registers the `lock_bug_detector` tool and sets `--cfg lock_bug_detector` in the crates checked,
+//! so the attribute can be written as `#[cfg_attr(lock_bug_detector, lock_bug_detector::allow(double_lock))]`
+//! to keep the crate building with plain rustc.
+extern crate rustc_ast;
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_span;
+use rustc_ast::ast::{AttrKind, Attribute};
+use rustc_hir::ItemKind;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+
+pub const TOOL_NAME: &str = "lock_bug_detector";
+const COMMENT_MARKER: &str = "lock-bug-detect:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BugKind {
+    DoubleLock,
+    ConflictLock,
+    AwaitLock,
+    Condvar,
+    DoubleBorrow,
+    BlockingCall,
+}
+
+impl BugKind {
+    /// The name in `allow(...)`.
+    fn name(self) -> &'static str {
+        match self {
+            BugKind::DoubleLock => "double_lock",
+            BugKind::ConflictLock => "conflict_lock",
+            BugKind::AwaitLock => "await_lock",
+            BugKind::Condvar => "condvar",
+            BugKind::DoubleBorrow => "double_borrow",
+            BugKind::BlockingCall => "blocking_call",
+        }
+    }
+}
+
+pub struct Suppressions<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    // the spans of the items with an allow attribute and the bug kinds they allow
+    allowed_items: Vec<(Span, Vec<String>)>,
+}
+
+impl<'tcx> Suppressions<'tcx> {
+    pub fn new(tcx: TyCtxt<'tcx>) -> Self {
+        let krate = tcx.hir().krate();
+        let mut allowed_items = Vec::new();
+        let mut add = |attrs: &[Attribute], span: Span| {
+            let kinds = attr_allowed_kinds(attrs);
+            if !kinds.is_empty() {
+                allowed_items.push((span, kinds));
+            }
+        };
+        add(krate.item.attrs, krate.item.span);
+        for item in krate.items.values() {
+            match &item.kind {
+                // the body of an out-of-line mod is in another file
+                ItemKind::Mod(module) => add(item.attrs, module.inner),
+                _ => add(item.attrs, item.span),
+            }
+        }
+        for item in krate.trait_items.values() {
+            add(item.attrs, item.span);
+        }
+        for item in krate.impl_items.values() {
+            add(item.attrs, item.span);
+        }
+        Self { tcx, allowed_items }
+    }
+
+    /// Whether the lock acquired at `span` is allowed to take part in a `kind` bug.
+    pub fn is_suppressed(&self, kind: BugKind, span: Span) -> bool {
+        let span = span.source_callsite();
+        self.allowed_items
+            .iter()
+            .any(|(item_span, kinds)| item_span.contains(span) && kinds.iter().any(|k| k == kind.name()))
+            || self.allowed_by_comment(kind, span)
+    }
+
+    fn allowed_by_comment(&self, kind: BugKind, span: Span) -> bool {
+        let loc = self.tcx.sess.source_map().lookup_char_pos(span.lo());
+        // `loc.line` is 1-based, `get_line` 0-based
+        let line = match loc.file.get_line(loc.line - 1) {
+            Some(line) => line,
+            None => return false,
+        };
+        if comment_allowed_kinds(&line).iter().any(|k| k == kind.name()) {
+            return true;
+        }
+        if loc.line < 2 {
+            return false;
+        }
+        match loc.file.get_line(loc.line - 2) {
+            Some(above) => {
+                above.trim_start().starts_with("//")
+                    && comment_allowed_kinds(&above).iter().any(|k| k == kind.name())
+            }
+            None => false,
+        }
+    }
+}
+
+/// The bug kinds in the `#[lock_bug_detector::allow(...)]` attributes.
+fn attr_allowed_kinds(attrs: &[Attribute]) -> Vec<String> {
+    let mut kinds = Vec::new();
+    for attr in attrs {
+        let is_allow = match &attr.kind {
+            AttrKind::Normal(item) => {
+                let segments = item
+                    .path
+                    .segments
+                    .iter()
+                    .map(|segment| segment.ident.name.to_string())
+                    .collect::<Vec<_>>();
+                segments == [TOOL_NAME, "allow"]
+            }
+            _ => false,
+        };
+        if !is_allow {
+            continue;
+        }
+        for nested in attr.meta_item_list().unwrap_or_default() {
+            if let Some(ident) = nested.ident() {
+                kinds.push(ident.name.to_string());
+            }
+        }
+    }
+    kinds
+}
+
+/// The bug kinds in a `// lock-bug-detect: allow(...)` comment of `line`.
+fn comment_allowed_kinds(line: &str) -> Vec<String> {
+    let comment = match line.find("//") {
+        Some(start) => &line[start + 2..],
+        None => return Vec::new(),
+    };
+    let allow = match comment.find(COMMENT_MARKER) {
+        Some(start) => comment[start + COMMENT_MARKER.len()..].trim_start(),
+        None => return Vec::new(),
+    };
+    if !allow.starts_with("allow(") {
+        return Vec::new();
+    }
+    match allow["allow(".len()..].find(')') {
+        Some(end) => allow["allow(".len().."allow(".len() + end]
+            .split(',')
+            .map(|kind| kind.trim().to_string())
+            .filter(|kind| !kind.is_empty())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[test]
+fn test_comment_allowed_kinds() {
+    assert_eq!(
+        comment_allowed_kinds("    let g = m.lock(); // lock-bug-detect: allow(double_lock, conflict_lock)"),
+        vec!["double_lock", "conflict_lock"]
+    );
+    assert_eq!(comment_allowed_kinds("// lock-bug-detect:allow(conflict_lock)"), vec!["conflict_lock"]);
+    assert!(comment_allowed_kinds("let s = \"lock-bug-detect: allow(double_lock)\";").is_empty());
+    assert!(comment_allowed_kinds("// lock-bug-detect: allow(double_lock").is_empty());
+}
@@ -0,0 +1,78 @@
+//! Calls of trait methods in the callgraphs.
+//! In MIR a trait method call is a call of the method of the trait, e.g. `<T as Iterator>::next`.
+//! With concrete substs it runs the one impl `Instance::resolve` finds.
+//! A `dyn Trait` call runs the impl in the vtable, which is only known at runtime,
+//! so it is either dropped or, conservatively, linked to every local impl of the method.
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_span;
+use rustc_hir::def_id::{DefId, LocalDefId};
+use rustc_middle::ty::{Instance, InstanceDef, ParamEnv, SubstsRef, TyCtxt, TypeFoldable};
+use rustc_span::Symbol;
+use std::collections::HashMap;
+
+/// The local fns implementing the methods of each trait, keyed by the trait and the method name,
+/// including the default bodies of local traits.
+pub struct TraitImpls {
+    impls: HashMap<(DefId, Symbol), Vec<LocalDefId>>,
+}
+
+impl TraitImpls {
+    pub fn new(tcx: TyCtxt, crate_fn_ids: &[LocalDefId]) -> Self {
+        let mut impls: HashMap<(DefId, Symbol), Vec<LocalDefId>> = HashMap::new();
+        for fn_id in crate_fn_ids {
+            let def_id = fn_id.to_def_id();
+            let trait_id = match tcx.trait_of_item(def_id) {
+                Some(trait_id) => trait_id,
+                None => match tcx
+                    .impl_of_method(def_id)
+                    .and_then(|impl_id| tcx.trait_id_of_impl(impl_id))
+                {
+                    Some(trait_id) => trait_id,
+                    // a closure, a free fn or an inherent method
+                    None => continue,
+                },
+            };
+            impls
+                .entry((trait_id, tcx.item_name(def_id)))
+                .or_default()
+                .push(*fn_id);
+        }
+        Self { impls }
+    }
+
+    /// The local fns a call of the trait method `method` may run.
+    pub fn get(&self, tcx: TyCtxt, method: DefId) -> &[LocalDefId] {
+        tcx.trait_of_item(method)
+            .and_then(|trait_id| self.impls.get(&(trait_id, tcx.item_name(method))))
+            .map_or(&[], |impls| impls.as_slice())
+    }
+}
+
+/// The local fns a call of the trait method `method` with the concrete `substs` runs.
+/// A `dyn Trait` call is linked to all the local impls in `dyn_impls`, and dropped without them.
+pub fn trait_callees<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    method: DefId,
+    substs: SubstsRef<'tcx>,
+    crate_fn_ids: &[LocalDefId],
+    dyn_impls: Option<&TraitImpls>,
+) -> Vec<LocalDefId> {
+    if substs.has_param_types_or_consts() {
+        return Vec::new();
+    }
+    match Instance::resolve(tcx, ParamEnv::reveal_all(), method, substs) {
+        Ok(Some(Instance {
+            def: InstanceDef::Virtual(..),
+            ..
+        })) => dyn_impls.map_or_else(Vec::new, |impls| impls.get(tcx, method).to_vec()),
+        Ok(Some(instance)) => instance
+            .def
+            .def_id()
+            .as_local()
+            .filter(|callee| crate_fn_ids.contains(callee))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
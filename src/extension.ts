@@ -249,71 +249,60 @@ export function activate(context: vscode.ExtensionContext) {
 		timeout1 = setTimeout(updateDecorations, TIMEOUT_TIME);
 	}
 
+	function spanToPos(span: any) {
+		return `${span.start_line}:${span.start_col}: ${span.end_line}:${span.end_col}`;
+	}
+
 	function parseDetectorOutput(s: string) {
+		// one JSON report per line for each checked crate, see `cargo lock-bug-detect --output-format json`
 		let lines = s.split("\n");
-		// state:
-		// 0: start
-		// 1: first lock type
-		// 2: first lock pos
-		// 3: second lock type
-		// 4: second lock pos
-		//5: call chain
-		let state = 0;
 		let results = [];
-		let result = Object();
 		for (let i in lines) {
 			let line = lines[i];
-			if (state == 0) {
-				if (line.startsWith("{")) {
-					let regex = /FirstLock: \((\w+), "(.+)"\)/
-					let matchObj = line.match(regex);
-					if (matchObj) {
-						result["firstLock"] = {type: `${matchObj[1]}<${matchObj[2]}>`}
-						state = 1;
-					}
-				}
+			if (!line.startsWith("{")) {
+				continue;
 			}
-			else if (state == 1) {
-				let regex = /([^\t].*?):(\d+:\d+: \d+:\d+)/;
-				let matchObj = line.match(regex);
-				if (matchObj) {
-					let fname = matchObj[1];
-					let pos = matchObj[2];
-					result["firstLock"].pos = pos;
-					result["firstLock"].fname = fname;
-					result["firstLock"].msg = "the other lock causing double-lock."
-					state = 2;
-				}
+			let report = Object();
+			try {
+				report = JSON.parse(line);
+			} catch(e){
+				// a line of the build output that is not a report
+				outputChannel.appendLine("Error:" + e);
+				outputChannel.appendLine(line);
+				continue;
 			}
-			else if (state == 2) {
-				let regex = /SecondLock: \((\w+), "(.+)"\)/
-				let matchObj = line.match(regex);
-				if (matchObj) {
-					result["secondLock"] = {type: `${matchObj[1]}<${matchObj[2]}>`}
-					state = 3;
-				}
+			// the totals of the run, or a report of another detector
+			if (!("double_locks" in report)) {
+				continue;
 			}
-			else if (state == 3) {
-				let regex = /([^\t].*?):(\d+:\d+: \d+:\d+)/;
-				let matchObj = line.match(regex);
-				if (matchObj) {
-					let fname = matchObj[1];
-					let pos = matchObj[2];
-					result["secondLock"].pos = pos;
-					result["secondLock"].fname = fname;
-					result["secondLock"].msg = "Potential double-locking bug.";
-					state = 4;
+			for (let j in report["double_locks"]) {
+				let bug = report["double_locks"][j];
+				let callchains = [];
+				for (let k in bug["callchains"]) {
+					// {entry_fn, calls: [{caller, callee, span}], second_lock_fn}
+					let chain = bug["callchains"][k];
+					if (chain.calls && chain.calls.length > 0) {
+						let calls = chain.calls.map((call: any) => `${call.caller} -> ${call.callee} @ ${call.span.file}:${spanToPos(call.span)}`);
+						callchains.push(`from ${chain.entry_fn}: ${calls.join(", ")}, second lock in ${chain.second_lock_fn}`);
+					}
 				}
-			}
-			else if (state == 4) {
-				let regex = /Callchains: \{(.+)\}/
-				let matchObj = line.match(regex);
-				if (matchObj) {
-					result["firstLock"].msg += " Call chain: " + matchObj[1];
-					state = 0;
-					results.push(result);
-					result = Object();
+				let result = Object();
+				result["firstLock"] = {
+					type: `${bug["first_lock"]["lock_type"]}<${bug["first_lock"]["data_type"]}>`,
+					fname: bug["first_lock"]["span"]["file"],
+					pos: spanToPos(bug["first_lock"]["span"]),
+					msg: "the other lock causing double-lock."
+				};
+				if (callchains.length > 0) {
+					result["firstLock"].msg += " Call chain: " + callchains.join("; ");
 				}
+				result["secondLock"] = {
+					type: `${bug["second_lock"]["lock_type"]}<${bug["second_lock"]["data_type"]}>`,
+					fname: bug["second_lock"]["span"]["file"],
+					pos: spanToPos(bug["second_lock"]["span"]),
+					msg: "Potential double-locking bug."
+				};
+				results.push(result);
 			}
 		}
 		return results;
@@ -324,7 +313,7 @@ export function activate(context: vscode.ExtensionContext) {
 		let analyzerInputObj = {
 		}
 		let child = child_process.spawnSync(
-			`cd ${rootPath} && cargo clean && cargo +nightly-2020-05-10 lock-bug-detect double-lock`, 
+			`cd ${rootPath} && cargo +nightly-2020-05-10 lock-bug-detect double-lock --output-format json`, 
 			{shell: true, env: process.env});
 		let returnMsg = child.stdout.toString();
 		let detectorOutput = Object();